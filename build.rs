@@ -0,0 +1,23 @@
+use std::env;
+
+/// Links libilbc when the `ilbc` feature is enabled, from `ILBC_LIB_DIR` if set, found with pkg-config otherwise.
+fn main() {
+    println!("cargo:rerun-if-env-changed=ILBC_LIB_DIR");
+    if env::var_os("CARGO_FEATURE_ILBC").is_none() {
+        return;
+    }
+
+    if let Some(lib_dir) = env::var_os("ILBC_LIB_DIR") {
+        println!("cargo:rustc-link-search=native={}", lib_dir.to_string_lossy());
+        println!("cargo:rustc-link-lib=ilbc");
+        return;
+    }
+
+    if let Err(e) = pkg_config::probe_library("libilbc") {
+        panic!(
+            "The `ilbc` feature requires libilbc (https://github.com/TimothyGu/libilbc). \
+            Install it where pkg-config finds `libilbc.pc`, or set ILBC_LIB_DIR to the directory containing the library.\n{}",
+            e
+        );
+    }
+}
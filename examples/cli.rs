@@ -110,7 +110,7 @@ async fn handle_current_call(current_call: &mut Option<Call>, buffer_play: &Arc<
         Either::Left(message) => {
             if let Some(control) = message {
                 println!("Received Control message {:?}", control);
-                if control == CallControl::Finished {
+                if matches!(control, CallControl::Finished(_)) {
                     drop(current_call.take());
                 }
             }
@@ -183,8 +183,11 @@ async fn handle_command_input(line: String, sip_manager: &mut SipManager, curren
                                         println!("Call has been accepted");
                                         *current_call = Some(call);
                                     }
-                                    OutgoingCallResponse::Rejected(status_code) => {
+                                    OutgoingCallResponse::Rejected(status_code, retry_after) => {
                                         println!("Call has been rejected with status {}", status_code);
+                                        if let Some(retry_after) = retry_after {
+                                            println!("Retry after {:?}", retry_after);
+                                        }
                                     }
                                 }
                             }
@@ -221,14 +224,14 @@ async fn main() {
 
     let args = Args::parse();
 
-    let config = Config {
-        server_addr: SocketAddr::from_str(args.server_address.as_str()).unwrap(),
-        own_addr: SocketAddr::from_str(args.own_address.as_str()).unwrap(),
-        username: args.username.clone(),
-        password: args.password.clone(),
-        rtp_port_start: 20480,
-        rtp_port_end: 20490
-    };
+    let config = Config::builder()
+        .server(SocketAddr::from_str(args.server_address.as_str()).unwrap())
+        .own_addr(SocketAddr::from_str(args.own_address.as_str()).unwrap())
+        .credentials(args.username.clone(), args.password.clone())
+        .rtp_ports(20480, 20490)
+        .max_audio_buffer(Some(Duration::from_millis(200)))
+        .build()
+        .unwrap();
 
     let mut sip_manager = SipManager::from_config(config).await.unwrap();
     sip_manager.start().await.unwrap();
@@ -0,0 +1,479 @@
+//! Blocking wrappers around the async API, for applications not using async.
+//!
+//! The wrappers own a tokio runtime running the SIP and call tasks on a background thread,
+//! they must not be used from within an async context.
+//!
+//! # Examples
+//! ```no_run
+//!  use std::net::SocketAddr;
+//!  use std::str::FromStr;
+//!  use simple_sip_rs::blocking::{OutgoingCallResponse, SipManager};
+//!  use simple_sip_rs::config::Config;
+//!
+//!  let config = Config::builder()
+//!     .server(SocketAddr::from_str("127.0.0.1:5060").unwrap())
+//!     .own_addr(SocketAddr::from_str("127.0.0.1:5060").unwrap())
+//!     .credentials("username", "password")
+//!     .build()
+//!     .unwrap();
+//!
+//!  let mut sip_manager = SipManager::from_config(config).unwrap();
+//!  sip_manager.start().unwrap();
+//!
+//!  let outgoing_call = sip_manager.call("1000".to_string()).unwrap();
+//!  if let OutgoingCallResponse::Accepted(mut call) = outgoing_call.into_call_response().unwrap() {
+//!     call.send_audio(vec![0.0; 48000]).unwrap();
+//!     call.block_for_output_empty();
+//!     call.hangup().unwrap();
+//!  }
+//! ```
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use anyhow::Result;
+use futures_util::future::Either;
+use rsip::{Header, Method, Response, StatusCode, Uri};
+use tokio::runtime::Runtime;
+
+use crate::call::{CallControl, Media, MediaDirection};
+use crate::call::identity::CallerIdentity;
+use crate::call::outgoing_call::{CallOptions, PeekOutgoingCallResponse};
+use crate::config::Config;
+use crate::error::SipError;
+use crate::media::{CodecInfo, CodecKind};
+use crate::observer::MessageObserver;
+use crate::media::telephone_events::TelephoneEvent;
+
+/// Blocking version of [SipManager](crate::manager::SipManager).
+pub struct SipManager {
+    inner: crate::manager::SipManager,
+    runtime: Arc<Runtime>,
+}
+
+impl SipManager {
+    /// Create SipManager from the config, starting the runtime used by the SIP and call tasks.
+    pub fn from_config(config: Config) -> Result<Self, SipError> {
+        // A single worker thread keeps the tasks running between blocking calls
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .map_err(anyhow::Error::from)?;
+        let inner = runtime.block_on(crate::manager::SipManager::from_config(config))?;
+
+        Ok(SipManager {
+            inner,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// See [SipManager::start](crate::manager::SipManager::start).
+    pub fn start(&mut self) -> Result<(), SipError> {
+        self.runtime.block_on(self.inner.start())
+    }
+
+    /// See [SipManager::set_message_observer](crate::manager::SipManager::set_message_observer).
+    pub fn set_message_observer(&mut self, message_observer: impl MessageObserver + 'static) {
+        self.inner.set_message_observer(message_observer)
+    }
+
+    /// See [SipManager::stop](crate::manager::SipManager::stop).
+    pub fn stop(&mut self) {
+        self.inner.stop()
+    }
+
+    /// See [SipManager::shutdown](crate::manager::SipManager::shutdown).
+    pub fn shutdown(&mut self) {
+        self.runtime.block_on(self.inner.shutdown())
+    }
+
+    /// See [SipManager::is_running](crate::manager::SipManager::is_running).
+    pub fn is_running(&self) -> bool {
+        self.runtime.block_on(self.inner.is_running())
+    }
+
+    /// See [SipManager::is_registered](crate::manager::SipManager::is_registered).
+    pub fn is_registered(&self) -> bool {
+        self.inner.is_registered()
+    }
+
+    /// Blocks until registered on the server.
+    /// See [SipManager::wait_registered](crate::manager::SipManager::wait_registered).
+    pub fn wait_registered(&self) {
+        self.runtime.block_on(self.inner.wait_registered())
+    }
+
+    /// See [SipManager::reregister](crate::manager::SipManager::reregister).
+    pub fn reregister(&self) -> Result<(), SipError> {
+        self.runtime.block_on(self.inner.reregister())
+    }
+
+    /// Blocks until the next incoming call.
+    /// See [SipManager::recv_incoming_call](crate::manager::SipManager::recv_incoming_call).
+    pub fn recv_incoming_call(&mut self) -> Result<Option<IncomingCall>> {
+        let incoming_call = self.runtime.block_on(self.inner.recv_incoming_call())?;
+        Ok(incoming_call.map(|inner| IncomingCall {
+            inner,
+            runtime: self.runtime.clone(),
+        }))
+    }
+
+    /// See [SipManager::call](crate::manager::SipManager::call).
+    pub fn call(&self, to: String) -> Result<OutgoingCall, SipError> {
+        let inner = self.runtime.block_on(self.inner.call(to))?;
+        Ok(OutgoingCall {
+            inner,
+            runtime: self.runtime.clone(),
+        })
+    }
+
+    /// See [SipManager::call_with](crate::manager::SipManager::call_with).
+    pub fn call_with(&self, to: String, options: CallOptions) -> Result<OutgoingCall, SipError> {
+        let inner = self.runtime.block_on(self.inner.call_with(to, options))?;
+        Ok(OutgoingCall {
+            inner,
+            runtime: self.runtime.clone(),
+        })
+    }
+}
+
+pub enum OutgoingCallResponse {
+    Accepted(Box<Call>),
+    /// See [OutgoingCallResponse::Rejected](crate::call::outgoing_call::OutgoingCallResponse::Rejected).
+    Rejected(StatusCode, Option<std::time::Duration>)
+}
+
+/// Blocking version of [OutgoingCall](crate::call::outgoing_call::OutgoingCall).
+pub struct OutgoingCall {
+    inner: crate::call::outgoing_call::OutgoingCall,
+    runtime: Arc<Runtime>,
+}
+
+impl OutgoingCall {
+    /// See [OutgoingCall::with_ring_timeout](crate::call::outgoing_call::OutgoingCall::with_ring_timeout).
+    pub fn with_ring_timeout(mut self, ring_timeout: std::time::Duration) -> Self {
+        self.inner = self.inner.with_ring_timeout(ring_timeout);
+        self
+    }
+
+    /// Blocks until the remote answers, without consuming the call.
+    /// See [OutgoingCall::peek_call_response](crate::call::outgoing_call::OutgoingCall::peek_call_response).
+    pub fn peek_call_response(&mut self) -> Result<PeekOutgoingCallResponse, SipError> {
+        self.runtime.block_on(self.inner.peek_call_response())
+    }
+
+    /// Blocks until the remote answers.
+    /// See [OutgoingCall::into_call_response](crate::call::outgoing_call::OutgoingCall::into_call_response).
+    pub fn into_call_response(self) -> Result<OutgoingCallResponse, SipError> {
+        let response = self.runtime.block_on(self.inner.into_call_response())?;
+        Ok(match response {
+            crate::call::outgoing_call::OutgoingCallResponse::Accepted(inner) => {
+                OutgoingCallResponse::Accepted(Box::new(Call {
+                    inner,
+                    runtime: self.runtime,
+                }))
+            }
+            crate::call::outgoing_call::OutgoingCallResponse::Rejected(status_code, retry_after) => {
+                OutgoingCallResponse::Rejected(status_code, retry_after)
+            }
+        })
+    }
+
+    /// See [OutgoingCall::cancel](crate::call::outgoing_call::OutgoingCall::cancel).
+    pub fn cancel(self) -> Result<(), SipError> {
+        self.runtime.block_on(self.inner.cancel())
+    }
+}
+
+pub enum IncomingCallResult {
+    Ok(Box<Call>),
+    Cancelled,
+}
+
+impl IncomingCallResult {
+    fn from_inner(result: crate::call::incoming_call::IncomingCallResult, runtime: Arc<Runtime>) -> Self {
+        match result {
+            crate::call::incoming_call::IncomingCallResult::Ok(inner) => IncomingCallResult::Ok(Box::new(Call { inner, runtime })),
+            crate::call::incoming_call::IncomingCallResult::Cancelled => IncomingCallResult::Cancelled,
+        }
+    }
+}
+
+pub enum EarlyCallResult {
+    Ok(Box<EarlyCall>),
+    Cancelled,
+}
+
+impl EarlyCallResult {
+    fn from_inner(result: crate::call::incoming_call::EarlyCallResult, runtime: Arc<Runtime>) -> Self {
+        match result {
+            crate::call::incoming_call::EarlyCallResult::Ok(inner) => EarlyCallResult::Ok(Box::new(EarlyCall { inner: *inner, runtime })),
+            crate::call::incoming_call::EarlyCallResult::Cancelled => EarlyCallResult::Cancelled,
+        }
+    }
+}
+
+/// Blocking version of [IncomingCall](crate::call::incoming_call::IncomingCall).
+pub struct IncomingCall {
+    inner: crate::call::incoming_call::IncomingCall,
+    runtime: Arc<Runtime>,
+}
+
+impl IncomingCall {
+    /// [Uri] of the caller.
+    pub fn get_remote_uri(&self) -> &Uri {
+        self.inner.get_remote_uri()
+    }
+
+    /// See [IncomingCall::caller_identity](crate::call::incoming_call::IncomingCall::caller_identity).
+    pub fn caller_identity(&self) -> &CallerIdentity {
+        self.inner.caller_identity()
+    }
+
+    /// See [IncomingCall::compatible_codecs](crate::call::incoming_call::IncomingCall::compatible_codecs).
+    pub fn compatible_codecs(&self) -> Vec<CodecKind> {
+        self.inner.compatible_codecs()
+    }
+
+    /// See [IncomingCall::header](crate::call::incoming_call::IncomingCall::header).
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.inner.header(name)
+    }
+
+    /// See [IncomingCall::with_direction](crate::call::incoming_call::IncomingCall::with_direction).
+    pub fn with_direction(mut self, direction: MediaDirection) -> Self {
+        self.inner = self.inner.with_direction(direction);
+        self
+    }
+
+    /// See [IncomingCall::ring](crate::call::incoming_call::IncomingCall::ring).
+    pub fn ring(&mut self) -> Result<()> {
+        self.runtime.block_on(self.inner.ring())
+    }
+
+    /// See [IncomingCall::progress](crate::call::incoming_call::IncomingCall::progress).
+    pub fn progress(&mut self) -> Result<()> {
+        self.runtime.block_on(self.inner.progress())
+    }
+
+    /// See [IncomingCall::accept](crate::call::incoming_call::IncomingCall::accept).
+    pub fn accept(self) -> Result<IncomingCallResult, SipError> {
+        let result = self.runtime.block_on(self.inner.accept())?;
+        Ok(IncomingCallResult::from_inner(result, self.runtime))
+    }
+
+    /// See [IncomingCall::accept_with](crate::call::incoming_call::IncomingCall::accept_with).
+    pub fn accept_with(self, codecs: &[CodecKind]) -> Result<IncomingCallResult, SipError> {
+        let result = self.runtime.block_on(self.inner.accept_with(codecs))?;
+        Ok(IncomingCallResult::from_inner(result, self.runtime))
+    }
+
+    /// See [IncomingCall::answer_early](crate::call::incoming_call::IncomingCall::answer_early).
+    pub fn answer_early(self) -> Result<EarlyCallResult, SipError> {
+        let result = self.runtime.block_on(self.inner.answer_early())?;
+        Ok(EarlyCallResult::from_inner(result, self.runtime))
+    }
+
+    /// See [IncomingCall::reject](crate::call::incoming_call::IncomingCall::reject).
+    pub fn reject(self) -> Result<()> {
+        self.runtime.block_on(self.inner.reject())
+    }
+
+    /// See [IncomingCall::reject_with](crate::call::incoming_call::IncomingCall::reject_with).
+    pub fn reject_with(self, status_code: StatusCode) -> Result<()> {
+        self.runtime.block_on(self.inner.reject_with(status_code))
+    }
+
+    /// See [IncomingCall::redirect](crate::call::incoming_call::IncomingCall::redirect).
+    pub fn redirect(self, target: Uri) -> Result<()> {
+        self.runtime.block_on(self.inner.redirect(target))
+    }
+}
+
+/// Blocking version of [EarlyCall](crate::call::incoming_call::EarlyCall).
+pub struct EarlyCall {
+    inner: crate::call::incoming_call::EarlyCall,
+    runtime: Arc<Runtime>,
+}
+
+impl EarlyCall {
+    /// [Uri] of the caller.
+    pub fn get_remote_uri(&self) -> &Uri {
+        self.inner.get_remote_uri()
+    }
+
+    /// See [EarlyCall::caller_identity](crate::call::incoming_call::EarlyCall::caller_identity).
+    pub fn caller_identity(&self) -> &CallerIdentity {
+        self.inner.caller_identity()
+    }
+
+    /// See [EarlyCall::header](crate::call::incoming_call::EarlyCall::header).
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.inner.header(name)
+    }
+
+    /// See [EarlyCall::send_audio](crate::call::incoming_call::EarlyCall::send_audio).
+    pub fn send_audio(&self, audio: Vec<f32>) -> Result<()> {
+        self.inner.send_audio(audio)
+    }
+
+    /// Blocks until the next media message.
+    pub fn recv_media(&mut self) -> Option<Media> {
+        self.runtime.block_on(self.inner.recv_media())
+    }
+
+    /// Blocks until the output buffer is empty.
+    pub fn block_for_output_empty(&mut self) {
+        self.runtime.block_on(self.inner.block_for_output_empty())
+    }
+
+    /// See [EarlyCall::accept](crate::call::incoming_call::EarlyCall::accept).
+    pub fn accept(self) -> Result<IncomingCallResult, SipError> {
+        let result = self.runtime.block_on(self.inner.accept())?;
+        Ok(IncomingCallResult::from_inner(result, self.runtime))
+    }
+
+    /// See [EarlyCall::reject_with](crate::call::incoming_call::EarlyCall::reject_with).
+    pub fn reject_with(self, status_code: StatusCode) -> Result<()> {
+        self.runtime.block_on(self.inner.reject_with(status_code))
+    }
+}
+
+/// Blocking version of [Call](crate::call::Call).
+pub struct Call {
+    inner: crate::call::Call,
+    runtime: Arc<Runtime>,
+}
+
+impl Call {
+    /// Blocks until the call has finished.
+    pub fn block_for_finished(&mut self) {
+        self.runtime.block_on(self.inner.block_for_finished())
+    }
+
+    /// Blocks until the output buffer is empty.
+    pub fn block_for_output_empty(&mut self) {
+        self.runtime.block_on(self.inner.block_for_output_empty())
+    }
+
+    /// See [Call::announce](crate::call::Call::announce).
+    pub fn announce(self, audio: Vec<f32>) -> Result<()> {
+        self.runtime.block_on(self.inner.announce(audio))
+    }
+
+    /// See [Call::send_audio](crate::call::Call::send_audio).
+    pub fn send_audio(&self, audio: Vec<f32>) -> Result<()> {
+        self.inner.send_audio(audio)
+    }
+
+    /// Blocks while more than `max_buffered` of audio is buffered.
+    /// See [Call::send_audio_bounded](crate::call::Call::send_audio_bounded).
+    pub fn send_audio_bounded(&self, audio: Vec<f32>, max_buffered: std::time::Duration) -> Result<()> {
+        self.runtime.block_on(self.inner.send_audio_bounded(audio, max_buffered))
+    }
+
+    /// See [Call::buffered_duration](crate::call::Call::buffered_duration).
+    pub fn buffered_duration(&self) -> std::time::Duration {
+        self.inner.buffered_duration()
+    }
+
+    /// See [Call::send_audio_with_format](crate::call::Call::send_audio_with_format).
+    pub fn send_audio_with_format(&self, audio: Vec<f32>, sample_rate: u32, channels: u8) -> Result<()> {
+        self.inner.send_audio_with_format(audio, sample_rate, channels)
+    }
+
+    /// See [Call::send_audio_i16](crate::call::Call::send_audio_i16).
+    pub fn send_audio_i16(&self, audio: Vec<i16>, sample_rate: u32, channels: u8) -> Result<()> {
+        self.inner.send_audio_i16(audio, sample_rate, channels)
+    }
+
+    /// See [Call::send_raw_payload](crate::call::Call::send_raw_payload).
+    pub fn send_raw_payload(&self, payload_type: u8, payload: Vec<u8>) -> Result<()> {
+        self.inner.send_raw_payload(payload_type, payload)
+    }
+
+    /// See [Call::send_dtmf_sequence](crate::call::Call::send_dtmf_sequence).
+    pub fn send_dtmf_sequence(&self, digits: &str, digit_duration: Duration, gap: Duration) -> Result<()> {
+        self.inner.send_dtmf_sequence(digits, digit_duration, gap)
+    }
+
+    /// See [Call::send_dtmf_info](crate::call::Call::send_dtmf_info).
+    pub fn send_dtmf_info(&self, digit: TelephoneEvent) -> Result<()> {
+        self.inner.send_dtmf_info(digit)
+    }
+
+    /// See [Call::send_update](crate::call::Call::send_update).
+    pub fn send_update(&self) -> Result<()> {
+        self.inner.send_update()
+    }
+
+    /// See [Call::send_in_dialog_request](crate::call::Call::send_in_dialog_request).
+    pub fn send_in_dialog_request(&self, method: Method, headers: Vec<Header>, body: Vec<u8>) -> Result<Response> {
+        self.runtime.block_on(self.inner.send_in_dialog_request(method, headers, body))
+    }
+
+    /// See [Call::set_max_duration](crate::call::Call::set_max_duration).
+    pub fn set_max_duration(&self, max_duration: Option<std::time::Duration>) -> Result<()> {
+        self.inner.set_max_duration(max_duration)
+    }
+
+    /// Tries to hang up the call. Might fail if the call is already over.
+    pub fn hangup(&self) -> Result<()> {
+        self.inner.hangup()
+    }
+
+    /// Blocks until the next control message from the call.
+    pub fn recv(&mut self) -> Option<CallControl> {
+        self.runtime.block_on(self.inner.recv())
+    }
+
+    /// Blocks until the next media message from the call.
+    pub fn recv_media(&mut self) -> Option<Media> {
+        self.runtime.block_on(self.inner.recv_media())
+    }
+
+    /// Blocks until the next DTMF digit pressed by the remote. See [Call::recv_dtmf](crate::call::Call::recv_dtmf).
+    pub fn recv_dtmf(&mut self) -> Option<TelephoneEvent> {
+        self.runtime.block_on(self.inner.recv_dtmf())
+    }
+
+    /// Blocks until either the next control message or the next media message.
+    pub fn recv_either(&mut self) -> Either<Option<CallControl>, Option<Media>> {
+        self.runtime.block_on(self.inner.recv_either())
+    }
+
+    /// See [Call::get_remote_uri](crate::call::Call::get_remote_uri).
+    pub fn get_remote_uri(&self) -> String {
+        self.inner.get_remote_uri()
+    }
+
+    /// See [Call::remote_identity](crate::call::Call::remote_identity).
+    pub fn remote_identity(&self) -> &CallerIdentity {
+        self.inner.remote_identity()
+    }
+
+    /// See [Call::remote_supports](crate::call::Call::remote_supports).
+    pub fn remote_supports(&self, method: Method) -> bool {
+        self.inner.remote_supports(method)
+    }
+
+    /// See [Call::answered_at](crate::call::Call::answered_at).
+    pub fn answered_at(&self) -> Instant {
+        self.inner.answered_at()
+    }
+
+    /// See [Call::duration](crate::call::Call::duration).
+    pub fn duration(&self) -> Duration {
+        self.inner.duration()
+    }
+
+    /// See [Call::negotiated_codec](crate::call::Call::negotiated_codec).
+    pub fn negotiated_codec(&self) -> Option<&CodecInfo> {
+        self.inner.negotiated_codec()
+    }
+
+    /// See [Call::is_finished](crate::call::Call::is_finished).
+    pub fn is_finished(&self) -> bool {
+        self.inner.is_finished()
+    }
+}
@@ -1,91 +1,497 @@
 use anyhow::{Result};
 
 use rsip::prelude::*;
-use rsip::{Method, Request, Response, SipMessage, StatusCode};
-use log::{debug, error, warn};
-use crate::call::CallControl;
+use rsip::{Header, Headers, Method, Request, Response, SipMessage, StatusCode, StatusCodeKind};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use crate::logging::{debug, error, info, warn};
+use rsip::headers::ContentLength;
+use rsip::typed::{CSeq, ContentType, MediaType};
+use crate::call::{CallControl, EndReason};
 use crate::call::session_parameters::SessionParameters;
 use crate::connection::call_connection::CallConnection;
-use crate::utils::BidirectionalChannel;
+use crate::media::get_codec_kinds_from_sdp_session;
+use crate::media::telephone_events::TelephoneEvent;
+use crate::sip_proto::{generate_error_response, generate_response, get_allow_header};
+use crate::sip_proto::multipart::parse_sdp_body;
+use crate::sip_proto::replaces::{get_replaces, Replaces};
+use crate::sip_proto::retransmission::Retransmission;
+use crate::sip_proto::sdp::{get_audio_media_index, increment_session_version};
+use crate::sip_proto::session_timer::{
+    add_response_headers, get_min_se, get_session_expires, min_se_header, supported_timer_header,
+    Refresher, SessionExpires, SessionTimer, MIN_SE,
+};
+use crate::utils::{sleep_until_optional, BidirectionalChannel};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::{oneshot, watch};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+/// Last message of the INVITE transaction that established the call.
+pub enum InviteTransaction {
+    /// 200 OK sent to the remote INVITE, retransmitted until the ACK is received.
+    Response(Response),
+    /// ACK sent to the remote 200 OK, sent again if the 200 OK is retransmitted.
+    Ack(Request),
+}
+
+/// Request sent by the application in the dialog, see [Call::send_in_dialog_request](crate::call::Call::send_in_dialog_request).
+pub struct InDialogRequest {
+    pub method: Method,
+    pub headers: Vec<Header>,
+    pub body: Vec<u8>,
+    /// Receives the final response to the request.
+    pub response_sender: oneshot::Sender<Response>,
+}
+
+/// Duration of the DTMF digits sent in INFO requests, in milliseconds.
+const DTMF_INFO_DURATION: u32 = 160;
 
 pub struct CallHandler {
     is_terminated: bool,
+    end_reason: Option<EndReason>,
 
     session_params: SessionParameters,
+    invite_transaction: InviteTransaction,
+    retransmission: Option<Retransmission>,
+    /// CSeq of the INFO request waiting for a response.
+    pending_info: Option<u32>,
+    dtmf_info_queue: VecDeque<TelephoneEvent>,
+    /// Next session timer event, sending a refresh when we refresh the session or hanging up once it expired.
+    session_timer_at: Option<Instant>,
+    /// Start of the current session interval.
+    session_refreshed_at: Instant,
+    /// Refreshing re-INVITE or UPDATE waiting for a response.
+    pending_refresh: Option<Request>,
+    /// Sends the UPDATE of the application again when reached, after it collided with a request of the remote.
+    update_retry_at: Option<Instant>,
+    /// Whether we generated the Call-ID, i.e. the call is outgoing, see [request_pending_delay].
+    call_id_owner: bool,
+    started_at: Instant,
+    /// Hangs up the call when reached, see [Call::set_max_duration](crate::call::Call::set_max_duration).
+    max_duration_at: Option<Instant>,
+    /// Requests of the application waiting for a final response, by CSeq.
+    pending_requests: HashMap<u32, oneshot::Sender<Response>>,
 
     call_channel: BidirectionalChannel<CallControl>,
+    in_dialog_requests: UnboundedReceiver<InDialogRequest>,
     connection: CallConnection,
+    session_update_sender: UnboundedSender<SessionParameters>,
+    /// Publishes `session_params` to the [Call](crate::call::Call) whenever they change.
+    session_params_sender: watch::Sender<SessionParameters>,
+    cancellation_token: CancellationToken,
 }
 
 impl CallHandler {
     pub async fn new(
         call_channel: BidirectionalChannel<CallControl>,
+        in_dialog_requests: UnboundedReceiver<InDialogRequest>,
         connection: CallConnection,
-        session_params: SessionParameters
+        session_params_sender: watch::Sender<SessionParameters>,
+        invite_transaction: InviteTransaction,
+        session_update_sender: UnboundedSender<SessionParameters>,
+        cancellation_token: CancellationToken,
     ) -> Result<Self>
     {
-        Ok(Self {
+        let session_params = session_params_sender.borrow().clone();
+        let retransmission = match &invite_transaction {
+            InviteTransaction::Response(response) => Some(Retransmission::new(response.clone().into())),
+            InviteTransaction::Ack(_) => None,
+        };
+
+        let call_id_owner = matches!(invite_transaction, InviteTransaction::Ack(_));
+        let started_at = Instant::now();
+        let max_duration_at = session_params.config.max_call_duration.map(|max_duration| started_at + max_duration);
+        let mut call_handler = Self {
             is_terminated: false,
+            end_reason: None,
 
             session_params,
+            invite_transaction,
+            retransmission,
+            pending_info: None,
+            dtmf_info_queue: VecDeque::new(),
+            session_timer_at: None,
+            session_refreshed_at: Instant::now(),
+            pending_refresh: None,
+            update_retry_at: None,
+            call_id_owner,
+            started_at,
+            max_duration_at,
+            pending_requests: HashMap::new(),
 
             call_channel,
+            in_dialog_requests,
             connection,
-        })
+            session_update_sender,
+            session_params_sender,
+            cancellation_token,
+        };
+        call_handler.reset_session_timer();
+        Ok(call_handler)
     }
 
     pub fn is_running(&self) -> bool {
-        !self.call_channel.one_sided() && !self.is_terminated
+        !self.is_terminated
     }
 
     pub async fn handle_next(&mut self) -> Result<()> {
-        if self.call_channel.one_sided() {
-            debug!("Control channel closed");
-            return self.hangup().await;
+        if self.call_channel.one_sided() || self.cancellation_token.is_cancelled() {
+            debug!("Call dropped, hanging up");
+            let res = self.hangup(EndReason::LocalHangup).await;
+            if let Err(e) = &res {
+                self.end_reason = Some(EndReason::Error(e.to_string()));
+            }
+            self.is_terminated = true;
+            return res;
         }
 
         tokio::select! {
+            // Hangs up on the next call
+            _ = self.cancellation_token.cancelled() => {},
             call_message = self.call_channel.receiver.recv() => {
                 if let Some(message) = call_message {
                     self.handle_call_message(message).await?;
                 }
             },
+            request = self.in_dialog_requests.recv() => {
+                if let Some(request) = request {
+                    self.send_in_dialog_request(request).await?;
+                }
+            },
             sip_message = self.connection.recv() => {
                 if let Some(message) = sip_message {
                     self.handle_sip_message(message).await?;
                 }
             },
+            message = next_retransmission(&mut self.retransmission) => {
+                match message {
+                    Some(message) => self.connection.send_message(message).await?,
+                    None => {
+                        warn!("No ACK received for the INVITE response, hanging up");
+                        self.retransmission = None;
+                        self.hangup(EndReason::Timeout).await?;
+                    }
+                }
+            },
+            _ = sleep_until_optional(self.session_timer_at) => self.handle_session_timer().await?,
+            _ = sleep_until_optional(self.update_retry_at) => {
+                self.update_retry_at = None;
+                self.send_update().await?;
+            },
+            _ = sleep_until_optional(self.max_duration_at) => {
+                info!("Maximum call duration reached, hanging up");
+                self.max_duration_at = None;
+                self.hangup(EndReason::MaxDuration).await?;
+            },
         }
         Ok(())
     }
 
-    fn notify_call_hangup(&mut self) {
+    fn notify_call_hangup(&mut self, reason: EndReason) {
         let _ = self.call_channel.sender.send(CallControl::Hangup);
+        self.end_reason = Some(reason);
         self.is_terminated = true;
     }
 
-    async fn hangup(&mut self) -> Result<()> {
-        let mut headers = self.session_params.get_headers_request();
-        headers.unique_push(rsip::typed::CSeq::from((self.session_params.get_next_cseq(), Method::Bye)).into());
+    async fn hangup(&mut self, reason: EndReason) -> Result<()> {
+        self.send_bye().await?;
+        self.notify_call_hangup(reason);
+        Ok(())
+    }
 
-        let req = Request {
-            method: Method::Bye,
-            uri: self.session_params.remote.uri.clone(),
+    async fn send_bye(&mut self) -> Result<()> {
+        let req = self.generate_request(Method::Bye);
+        self.connection.send_message(req.into()).await
+    }
+
+    /// Sends the next queued DTMF digit if no INFO is waiting for a response.
+    async fn send_next_dtmf_info(&mut self) -> Result<()> {
+        if self.pending_info.is_some() {
+            return Ok(());
+        }
+        let Some(digit) = self.dtmf_info_queue.pop_front() else {
+            return Ok(());
+        };
+
+        let body = format!("Signal={}\r\nDuration={}\r\n", digit.as_char(), DTMF_INFO_DURATION).into_bytes();
+        let mut req = self.generate_request(Method::Info);
+        req.headers.unique_push(ContentType(MediaType::Other("application/dtmf-relay".to_string(), Vec::new())).into());
+        req.headers.unique_push(ContentLength::from(body.len() as u32).into());
+        req.body = body;
+
+        self.pending_info = Some(req.cseq_header()?.seq()?);
+        self.connection.send_message(req.into()).await
+    }
+
+    async fn handle_info_response(&mut self, res: Response) -> Result<()> {
+        if self.pending_info != Some(res.cseq_header()?.seq()?) || res.status_code.kind() == StatusCodeKind::Provisional {
+            return Ok(());
+        }
+        if res.status_code.kind() != StatusCodeKind::Successful {
+            warn!("DTMF INFO rejected with status code {}", res.status_code);
+        }
+        self.pending_info = None;
+        self.send_next_dtmf_info().await
+    }
+
+    /// Sends a request of the application, see [Call::send_in_dialog_request](crate::call::Call::send_in_dialog_request).
+    async fn send_in_dialog_request(&mut self, request: InDialogRequest) -> Result<()> {
+        let mut req = self.generate_request(request.method);
+        req.headers.extend(request.headers);
+        req.headers.unique_push(ContentLength::from(request.body.len() as u32).into());
+        req.body = request.body;
+
+        self.pending_requests.insert(req.cseq_header()?.seq()?, request.response_sender);
+        self.connection.send_message(req.into()).await
+    }
+
+    /// Shares the current parameters of the session with the [Call](crate::call::Call), after they changed.
+    fn publish_session_params(&self) {
+        self.session_params_sender.send_replace(self.session_params.clone());
+    }
+
+    /// Restarts the session interval, after the session was established or refreshed.
+    fn reset_session_timer(&mut self) {
+        let now = Instant::now();
+        self.session_refreshed_at = now;
+        self.session_timer_at = self.session_params.session_timer.map(|timer| {
+            if timer.local_refresher {
+                now + Duration::from_secs(timer.interval as u64) / 2
+            } else {
+                now + session_expiry(timer)
+            }
+        });
+    }
+
+    async fn handle_session_timer(&mut self) -> Result<()> {
+        let Some(timer) = self.session_params.session_timer else {
+            self.session_timer_at = None;
+            return Ok(());
+        };
+        if timer.local_refresher && self.pending_refresh.is_none() {
+            self.session_timer_at = Some(self.session_refreshed_at + session_expiry(timer));
+            return self.send_session_refresh().await;
+        }
+
+        warn!("Session was not refreshed in time, hanging up");
+        self.session_timer_at = None;
+        self.hangup(EndReason::SessionExpired).await
+    }
+
+    /// Refreshes the session (RFC 4028), with an UPDATE without SDP when [Config::allow_update](crate::config::Config::allow_update)
+    /// is set and the remote allows UPDATE, with a re-INVITE offering our current SDP otherwise.
+    async fn send_session_refresh(&mut self) -> Result<()> {
+        if self.session_params.session_timer.is_none() {
+            return Ok(());
+        }
+        debug!("Refreshing the session");
+
+        if self.session_params.config.allow_update && self.session_params.remote.supports(&Method::Update) {
+            self.send_session_request(Method::Update, false).await
+        } else {
+            self.send_session_request(Method::Invite, true).await
+        }
+    }
+
+    /// Sends a re-INVITE or UPDATE, offering our current SDP if `with_sdp`, which also refreshes the session timer if any.
+    async fn send_session_request(&mut self, method: Method, with_sdp: bool) -> Result<()> {
+        let mut req = self.generate_request(method);
+        if with_sdp {
+            increment_session_version(&mut self.session_params.local.sdp);
+            self.publish_session_params();
+            let body = self.session_params.local.sdp.to_string().into_bytes();
+            req.headers.unique_push(ContentType(MediaType::Sdp(Vec::new())).into());
+            req.headers.unique_push(ContentLength::from(body.len() as u32).into());
+            req.body = body;
+        }
+        if let Some(timer) = self.session_params.session_timer {
+            req.headers.push(supported_timer_header());
+            req.headers.push(SessionExpires { interval: timer.interval, refresher: Some(Refresher::Uac) }.to_header());
+            req.headers.push(min_se_header(MIN_SE.min(timer.interval)));
+        }
+
+        self.pending_refresh = Some(req.clone());
+        self.connection.send_message(req.into()).await
+    }
+
+    /// Sends an UPDATE offering our current SDP, see [Call::send_update](crate::call::Call::send_update).
+    async fn send_update(&mut self) -> Result<()> {
+        if !self.session_params.config.allow_update {
+            warn!("UPDATE is not enabled in the config, not sending it");
+            return Ok(());
+        }
+        if !self.session_params.remote.supports(&Method::Update) {
+            warn!("Remote does not allow UPDATE, not sending it");
+            return Ok(());
+        }
+        if self.pending_refresh.is_some() {
+            warn!("A re-INVITE or UPDATE is already pending, not sending UPDATE");
+            return Ok(());
+        }
+        self.update_retry_at = None;
+        self.send_session_request(Method::Update, true).await
+    }
+
+    /// Switches the media to the SDP of the remote if it changed.
+    /// Returns `false`, leaving the media untouched, when the SDP offers none of our codecs.
+    fn apply_remote_sdp(&mut self, headers: &Headers, body: &[u8]) -> Result<bool> {
+        let remote_sdp = parse_sdp_body(headers, body)?;
+        let codecs = get_codec_kinds_from_sdp_session(&self.session_params.local.sdp);
+        if get_audio_media_index(&remote_sdp, &codecs, self.session_params.config.rtp_passthrough).is_none() {
+            return Ok(false);
+        }
+        if remote_sdp.to_string() != self.session_params.remote.sdp.to_string() {
+            debug!("Remote SDP changed, updating the media");
+            self.session_params.remote.sdp = remote_sdp;
+            self.session_update_sender.send(self.session_params.clone())?;
+        }
+        Ok(true)
+    }
+
+    async fn handle_refresh_response(&mut self, res: Response) -> Result<()> {
+        let Some(request) = self.pending_refresh.take() else {
+            return Ok(());
+        };
+        match res.status_code.kind() {
+            StatusCodeKind::Provisional => {
+                self.pending_refresh = Some(request);
+                return Ok(());
+            }
+            StatusCodeKind::Successful => {
+                self.session_params.update_remote_target(&res.headers);
+                if request.method == Method::Invite {
+                    let mut headers = self.session_params.get_headers_request();
+                    headers.unique_push(CSeq::from((res.cseq_header()?.seq()?, Method::Ack)).into());
+                    let ack = Request {
+                        method: Method::Ack,
+                        uri: self.session_params.remote.target.clone(),
+                        version: Default::default(),
+                        headers,
+                        body: vec![],
+                    };
+                    self.connection.send_message(ack.clone().into()).await?;
+                    self.invite_transaction = InviteTransaction::Ack(ack);
+                }
+                if !res.body.is_empty() && !self.apply_remote_sdp(&res.headers, &res.body)? {
+                    warn!("SDP answer to {} offers none of our codecs, keeping the current media", request.method);
+                }
+
+                // The session does not expire anymore if the response has no Session-Expires
+                self.session_params.session_timer = get_session_expires(&res.headers)?.map(SessionTimer::from_response);
+                self.reset_session_timer();
+                self.publish_session_params();
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        if request.method == Method::Invite {
+            let ack = generate_non_2xx_ack(&request, &res)?;
+            self.connection.send_message(ack.into()).await?;
+        }
+        match res.status_code {
+            StatusCode::SessionIntervalTooSmall => {
+                if let (Some(min_se), Some(timer)) = (get_min_se(&res.headers), self.session_params.session_timer.as_mut()) {
+                    timer.interval = timer.interval.max(min_se);
+                }
+                self.send_session_request(request.method, !request.body.is_empty()).await?;
+            }
+            StatusCode::MethodNotAllowed | StatusCode::NotImplemented if request.method == Method::Update => {
+                debug!("Remote does not support UPDATE, refreshing with a re-INVITE");
+                self.send_session_request(Method::Invite, true).await?;
+            }
+            StatusCode::RequestPending => {
+                let retry_at = Instant::now() + request_pending_delay(self.call_id_owner);
+                if request.method == Method::Update && !request.body.is_empty() {
+                    // UPDATE of the application, see send_update
+                    debug!("UPDATE collided with a request of the remote, retrying it");
+                    self.update_retry_at = Some(retry_at);
+                } else if self.session_params.session_timer.is_some_and(|timer| timer.local_refresher) {
+                    self.session_timer_at = Some(retry_at);
+                }
+            }
+            StatusCode::CallTransactionDoesNotExist | StatusCode::RequestTimeout => {
+                warn!("Session refresh failed with status code {}, hanging up", res.status_code);
+                self.hangup(EndReason::SessionExpired).await?;
+            }
+            _ => {
+                // The session remains valid until it expires
+                warn!("Session refresh rejected with status code {}", res.status_code);
+            }
+        }
+        Ok(())
+    }
+
+    /// Answers a re-INVITE or UPDATE with our current SDP and restarts the session timer.
+    /// The media switches to the SDP offered by the remote, rejected with 488 if it has none of our codecs.
+    async fn answer_session_refresh(&mut self, request: &Request) -> Result<Response> {
+        // Rejected before touching the media, see SessionTimer::from_request
+        let session_timer = SessionTimer::from_request(&request.headers)?;
+        if !request.body.is_empty() && !self.apply_remote_sdp(&request.headers, &request.body)? {
+            warn!("{} offers none of our codecs, rejecting it", request.method);
+            let response = Response {
+                status_code: StatusCode::NotAcceptableHere,
+                version: Default::default(),
+                headers: self.session_params.get_headers_response(request),
+                body: vec![],
+            };
+            self.connection.send_message(response.clone().into()).await?;
+            return Ok(response);
+        }
+        self.session_params.update_remote_target(&request.headers);
+
+        let mut headers = self.session_params.get_headers_response(request);
+        add_response_headers(&request.headers, &mut headers)?;
+
+        let mut body = Vec::new();
+        if request.method == Method::Invite || !request.body.is_empty() {
+            increment_session_version(&mut self.session_params.local.sdp);
+            body = self.session_params.local.sdp.to_string().into_bytes();
+            headers.unique_push(ContentType(MediaType::Sdp(Vec::new())).into());
+            headers.unique_push(ContentLength::from(body.len() as u32).into());
+        }
+        let response = Response {
+            status_code: StatusCode::OK,
             version: Default::default(),
             headers,
-            body: Vec::new(),
+            body,
         };
+        self.connection.send_message(response.clone().into()).await?;
 
-        self.connection.send_message(req.into()).await?;
+        self.session_params.session_timer = session_timer;
+        self.reset_session_timer();
+        self.publish_session_params();
+        Ok(response)
+    }
 
-        self.notify_call_hangup();
-        Ok(())
+    fn generate_request(&mut self, method: Method) -> Request {
+        let mut headers = self.session_params.get_headers_request();
+        headers.unique_push(rsip::typed::CSeq::from((self.session_params.get_next_cseq(), method)).into());
+
+        Request {
+            method,
+            uri: self.session_params.remote.target.clone(),
+            version: Default::default(),
+            headers,
+            body: Vec::new(),
+        }
     }
 
     async fn handle_sip_message(&mut self, message: SipMessage) -> Result<()>
     {
         match message {
-            SipMessage::Request(req) => self.handle_sip_request(req).await,
+            SipMessage::Request(req) => {
+                if let Err(e) = self.handle_sip_request(req.clone()).await {
+                    warn!("Failed to handle {} request: {:?}", req.method, e);
+                    // An ACK is never answered
+                    if req.method != Method::Ack {
+                        let response = generate_error_response(&req, &e)?;
+                        self.connection.send_message(response.into()).await?;
+                    }
+                }
+                Ok(())
+            }
             SipMessage::Response(res) => self.handle_sip_response(res).await
         }
     }
@@ -93,7 +499,27 @@ impl CallHandler {
     async fn handle_sip_response(&mut self, res: Response) -> Result<()>
     {
         if let Ok(cseq) = res.cseq_header() {
+            let seq = cseq.seq()?;
+            if self.pending_requests.contains_key(&seq) {
+                if res.status_code.kind() != StatusCodeKind::Provisional {
+                    if let Some(response_sender) = self.pending_requests.remove(&seq) {
+                        let _ = response_sender.send(res);
+                    }
+                }
+                return Ok(());
+            }
             match cseq.method()? {
+                Method::Invite | Method::Update if self.pending_refresh.as_ref().is_some_and(|req| req.cseq_header().ok() == Some(cseq)) => {
+                    self.handle_refresh_response(res).await?;
+                }
+                Method::Invite if res.status_code == StatusCode::OK => {
+                    // Our ACK was lost, the remote retransmits its 200 OK
+                    if let InviteTransaction::Ack(ack) = &self.invite_transaction {
+                        debug!("Received retransmitted 200 OK, sending ACK again");
+                        self.connection.send_message(ack.clone().into()).await?;
+                    }
+                }
+                Method::Info => self.handle_info_response(res).await?,
                 _ => {
                     warn!("Unhandled call response {}", cseq);
                 }
@@ -106,6 +532,18 @@ impl CallHandler {
     {
         match req.method {
             Method::Bye => self.handle_bye_request(req).await?,
+            Method::Ack => {
+                self.retransmission = None;
+            }
+            Method::Invite => self.handle_invite_request(req).await?,
+            Method::Update if self.session_params.config.allow_update => {
+                self.answer_session_refresh(&req).await?;
+            }
+            Method::Update => {
+                let mut response = generate_response(&req, StatusCode::MethodNotAllowed)?;
+                response.headers.push(get_allow_header(&self.session_params.config).into());
+                self.connection.send_message(response.into()).await?;
+            }
             _ => {
                 warn!("Unhandled request {}", req.method)
             }
@@ -125,15 +563,95 @@ impl CallHandler {
 
         let res = self.connection.send_message(response.into()).await;
 
-        self.notify_call_hangup();
+        self.notify_call_hangup(EndReason::RemoteBye);
 
         res
     }
 
+    async fn handle_invite_request(&mut self, request: Request) -> Result<()>
+    {
+        if let InviteTransaction::Response(response) = &self.invite_transaction {
+            if request.call_id_header()?.value() == response.call_id_header()?.value()
+                && request.cseq_header()?.seq()? == response.cseq_header()?.seq()? {
+                debug!("Received retransmitted INVITE, sending response again");
+                return self.connection.send_message(response.clone().into()).await;
+            }
+        }
+        match get_replaces(&request) {
+            Ok(Some(replaces)) => return self.handle_replacing_invite(request, replaces).await,
+            Ok(None) => {}
+            Err(e) => {
+                warn!("Invalid Replaces header: {:?}", e);
+                let response = generate_response(&request, StatusCode::BadRequest)?;
+                return self.connection.send_message(response.into()).await;
+            }
+        }
+
+        let response = self.answer_session_refresh(&request).await?;
+        self.retransmission = Some(Retransmission::new(response.clone().into()));
+        self.invite_transaction = InviteTransaction::Response(response);
+        Ok(())
+    }
+
+    /// Takes over the dialog of an INVITE replacing this call (RFC 3891), the previous dialog is hung up.
+    async fn handle_replacing_invite(&mut self, request: Request, replaces: Replaces) -> Result<()>
+    {
+        let params = &self.session_params;
+        if replaces.call_id != params.call_id || replaces.to_tag != params.local.tag || replaces.from_tag != params.remote.tag {
+            warn!("Replaces header does not match the call");
+            let response = generate_response(&request, StatusCode::CallTransactionDoesNotExist)?;
+            return self.connection.send_message(response.into()).await;
+        }
+        if replaces.early_only {
+            debug!("Refusing to replace a confirmed call with early-only");
+            let response = generate_response(&request, StatusCode::BusyHere)?;
+            return self.connection.send_message(response.into()).await;
+        }
+
+        let new_params = self.session_params.replaced_by(&request)?;
+        let body = new_params.local.sdp.to_string().into_bytes();
+        let mut headers = new_params.get_headers_response(&request);
+        add_response_headers(&request.headers, &mut headers)?;
+        headers.unique_push(ContentType(MediaType::Sdp(Vec::new())).into());
+        headers.unique_push(ContentLength::from(body.len() as u32).into());
+        let response = Response {
+            status_code: StatusCode::OK,
+            version: Default::default(),
+            headers,
+            body,
+        };
+        self.connection.send_message(response.clone().into()).await?;
+
+        self.send_bye().await?;
+        info!("Call replaced by dialog {}", new_params.call_id);
+
+        self.session_params = new_params;
+        self.retransmission = Some(Retransmission::new(response.clone().into()));
+        self.invite_transaction = InviteTransaction::Response(response);
+        self.pending_refresh = None;
+        self.reset_session_timer();
+        self.session_update_sender.send(self.session_params.clone())?;
+        self.publish_session_params();
+        let _ = self.call_channel.sender.send(CallControl::Replaced);
+        Ok(())
+    }
+
     async fn handle_call_message(&mut self, call_control: CallControl) -> Result<()>
     {
         match call_control {
-            CallControl::Hangup => self.hangup().await?,
+            CallControl::Hangup => self.hangup(EndReason::LocalHangup).await?,
+            CallControl::MediaTimeout => {
+                warn!("No RTP received, hanging up");
+                self.hangup(EndReason::MediaTimeout).await?;
+            }
+            CallControl::SendDtmfInfo(digit) => {
+                self.dtmf_info_queue.push_back(digit);
+                self.send_next_dtmf_info().await?;
+            }
+            CallControl::SendUpdate => self.send_update().await?,
+            CallControl::SetMaxDuration(max_duration) => {
+                self.max_duration_at = max_duration.map(|max_duration| self.started_at + max_duration);
+            }
             _ => {}
         }
         Ok(())
@@ -142,20 +660,35 @@ impl CallHandler {
 
 impl Drop for CallHandler {
     fn drop(&mut self) {
-        let _ = self.call_channel.send(CallControl::Finished);
+        let reason = self.end_reason.take()
+            .unwrap_or_else(|| EndReason::Error("Call task stopped unexpectedly".to_string()));
+        let _ = self.call_channel.send(CallControl::Finished(reason));
     }
 }
 
+#[cfg_attr(feature = "tracing", tracing::instrument(
+    name = "call",
+    skip_all,
+    fields(call_id = %session_params.borrow().call_id, remote_uri = %session_params.borrow().remote.uri),
+))]
 pub async fn call_task(
     call_channel: BidirectionalChannel<CallControl>,
+    in_dialog_requests: UnboundedReceiver<InDialogRequest>,
     connection: CallConnection,
-    session_params: SessionParameters
+    session_params: watch::Sender<SessionParameters>,
+    invite_transaction: InviteTransaction,
+    session_update_sender: UnboundedSender<SessionParameters>,
+    cancellation_token: CancellationToken,
 ) -> Result<()> {
     let mut call_handler = CallHandler::new(
         call_channel,
+        in_dialog_requests,
         connection,
-        session_params
-    ).await?;
+        session_params,
+        invite_transaction,
+        session_update_sender,
+        cancellation_token,
+    ).await.inspect_err(|e| debug!("Call task failed to start: {:?}", e))?;
 
     while call_handler.is_running() {
         if let Err(e) = call_handler.handle_next().await {
@@ -163,5 +696,44 @@ pub async fn call_task(
         }
     }
 
+    debug!("Call task finished");
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Waits for the next retransmission if any, never completes otherwise.
+async fn next_retransmission(retransmission: &mut Option<Retransmission>) -> Option<SipMessage> {
+    match retransmission {
+        Some(retransmission) => retransmission.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Delay before retrying a request rejected with 491 Request Pending (RFC 3261 §14.1), chosen randomly in
+/// 2.1 to 4 seconds by the owner of the Call-ID and in 0 to 2 seconds otherwise, so that both sides do not collide again.
+fn request_pending_delay(call_id_owner: bool) -> Duration {
+    let units: u64 = if call_id_owner { rand::random_range(210..=400) } else { rand::random_range(0..=200) };
+    Duration::from_millis(units * 10)
+}
+
+/// Time after which the session expires without refresh, leaving the refresher some margin (RFC 4028).
+fn session_expiry(timer: SessionTimer) -> Duration {
+    let interval = Duration::from_secs(timer.interval as u64);
+    interval - (interval / 3).min(Duration::from_secs(32))
+}
+
+/// Generates the ACK for a non 2xx final response to our request, part of its transaction.
+fn generate_non_2xx_ack(request: &Request, response: &Response) -> Result<Request> {
+    let mut headers = request.headers.clone();
+    headers.retain(|header| matches!(header, Header::Via(_) | Header::MaxForwards(_) | Header::From(_) | Header::CallId(_)));
+    headers.push(response.to_header()?.clone().into());
+    headers.push(CSeq::from((request.cseq_header()?.seq()?, Method::Ack)).into());
+    headers.push(ContentLength::default().into());
+
+    Ok(Request {
+        method: Method::Ack,
+        uri: request.uri.clone(),
+        version: Default::default(),
+        headers,
+        body: vec![],
+    })
+}
@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+/// Splits the received audio into frames of the same duration, whatever the framing of the remote.
+///
+/// Samples not filling a frame are held until the next audio arrives.
+pub struct FrameChunker {
+    frame_len: usize,
+    pending: Vec<f32>,
+}
+
+impl FrameChunker {
+    /// Chunks interleaved audio at `sample_rate` with `channels` channels into frames of `frame_duration`.
+    pub fn new(frame_duration: Duration, sample_rate: u32, channels: u8) -> Self {
+        let frames = (sample_rate as u128 * frame_duration.as_micros() / 1_000_000).max(1) as usize;
+        Self {
+            frame_len: frames * channels as usize,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Adds decoded audio, returns the complete frames.
+    pub fn push(&mut self, audio: Vec<f32>) -> Vec<Vec<f32>> {
+        if self.pending.is_empty() && audio.len() == self.frame_len {
+            return vec![audio];
+        }
+        self.pending.extend(audio);
+
+        let mut frames = Vec::new();
+        while self.pending.len() >= self.frame_len {
+            let rest = self.pending.split_off(self.frame_len);
+            frames.push(std::mem::replace(&mut self.pending, rest));
+        }
+        frames
+    }
+}
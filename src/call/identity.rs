@@ -0,0 +1,91 @@
+use rsip::{Header, Headers, Uri};
+use crate::sip_proto::split_name_addrs;
+
+/// Identity of the remote party of a call.
+///
+/// Taken from the `P-Asserted-Identity` header (RFC 3325), or the older `Remote-Party-ID`,
+/// which carriers use to convey the real number when the `From` is anonymous.
+/// Falls back to the `From` of an incoming call or the `To` of an outgoing call.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallerIdentity {
+    /// Display name, if any.
+    pub display_name: Option<String>,
+    /// URI of the party, `sip:`, `sips:` or `tel:`.
+    pub uri: String,
+    /// User part of the URI, the number for `tel:` URIs.
+    pub user: Option<String>,
+    /// The identity was asserted by the network rather than taken from the `From` or `To` header.
+    pub asserted: bool,
+}
+
+impl CallerIdentity {
+    /// Returns the asserted identity found in the headers, or the identity built from `uri`.
+    pub(crate) fn from_headers(headers: &Headers, display_name: Option<String>, uri: &Uri) -> Self {
+        get_asserted_identity(headers).unwrap_or_else(|| CallerIdentity {
+            display_name,
+            uri: uri.to_string(),
+            user: uri.auth.as_ref().map(|auth| auth.user.clone()),
+            asserted: false,
+        })
+    }
+}
+
+/// Returns the identity of the `P-Asserted-Identity` header, or of the `Remote-Party-ID` header of the calling party.
+fn get_asserted_identity(headers: &Headers) -> Option<CallerIdentity> {
+    let values = |name: &str| {
+        headers.iter().filter_map(|header| match header {
+            Header::Other(header_name, value) if header_name.eq_ignore_ascii_case(name) => Some(value.clone()),
+            _ => None,
+        }).collect::<Vec<_>>()
+    };
+
+    let asserted = values("p-asserted-identity").iter()
+        .flat_map(|value| split_name_addrs(value))
+        .find_map(|name_addr| parse_name_addr(name_addr).map(|(identity, _)| identity));
+    if asserted.is_some() {
+        return asserted;
+    }
+
+    values("remote-party-id").iter()
+        .flat_map(|value| split_name_addrs(value))
+        .filter_map(parse_name_addr)
+        .find(|(_, params)| {
+            // The party defaults to the calling party
+            params.split(';').all(|param| match param.split_once('=') {
+                Some((name, party)) if name.trim().eq_ignore_ascii_case("party") => party.trim().eq_ignore_ascii_case("calling"),
+                _ => true,
+            })
+        })
+        .map(|(identity, _)| identity)
+}
+
+/// Parses a name-addr (`"Name" <uri>;params`) or addr-spec (`uri;params`), returning the identity and the header parameters.
+fn parse_name_addr(value: &str) -> Option<(CallerIdentity, &str)> {
+    let value = value.trim();
+    let (display_name, uri, params) = match value.split_once('<') {
+        Some((display_name, rest)) => {
+            let (uri, params) = rest.split_once('>')?;
+            let display_name = display_name.trim().trim_matches('"').trim();
+            let display_name = (!display_name.is_empty()).then(|| display_name.to_string());
+            (display_name, uri.trim(), params)
+        }
+        None => match value.split_once(';') {
+            Some((uri, params)) => (None, uri.trim(), params),
+            None => (None, value, ""),
+        },
+    };
+
+    let (scheme, rest) = uri.split_once(':')?;
+    let user = if scheme.eq_ignore_ascii_case("tel") {
+        rest.split(';').next()
+    } else {
+        rest.split_once('@').map(|(user, _)| user.split(':').next().unwrap_or(user))
+    };
+
+    Some((CallerIdentity {
+        display_name,
+        uri: uri.to_string(),
+        user: user.filter(|user| !user.is_empty()).map(str::to_string),
+        asserted: true,
+    }, params.trim_start_matches(';')))
+}
@@ -1,22 +1,36 @@
 use crate::call::session_parameters::SessionParameters;
-use crate::call::Call;
+use crate::call::call_handler::InviteTransaction;
+use crate::call::identity::CallerIdentity;
+use crate::call::{queue_audio, Call, CallMedia, Media, MediaDirection};
 use crate::connection::call_connection::CallConnection;
 use crate::context::SipContext;
-use anyhow::Result;
-use log::info;
-use rsip::headers::ContentLength;
+use crate::error::SipError;
+use crate::media::{get_codec_kinds_from_sdp_session, CodecKind};
+use crate::sip_proto::get_header_value;
+use crate::sip_proto::sdp::generate_sdp_answer;
+use crate::sip_proto::session_timer::add_response_headers;
+use anyhow::{anyhow, Context, Result};
+use crate::logging::info;
+use rsip::headers::{ContentLength, RetryAfter};
+use rsip::prelude::UntypedHeader;
+use std::time::Duration;
 use rsip::typed::{ContentType, MediaType};
-use rsip::{Method, Request, Response, SipMessage, StatusCode, Uri, Version};
+use rsip::{Header, Method, Request, Response, SipMessage, StatusCode, Uri, Version};
 
 pub enum IncomingCallResult {
     Ok(Call),
     Cancelled,
 }
 
+pub enum EarlyCallResult {
+    Ok(Box<EarlyCall>),
+    Cancelled,
+}
+
 /// Represents an incoming call.
 /// You can choose to either accept or reject the incoming call.
 /// Accepting the call will yield a [Call].
-/// Rejecting the call will send a `BusyEverywhere` response.
+/// Rejecting the call will send a `BusyEverywhere` response, see [reject_with](IncomingCall::reject_with) to choose the status code.
 ///
 /// # Examples
 /// ```
@@ -39,6 +53,8 @@ pub struct IncomingCall {
     call_connection: CallConnection,
     call_session_params: SessionParameters,
     request: Request,
+    /// Last provisional response sent, repeated when the INVITE is retransmitted.
+    provisional_status: Option<StatusCode>,
 }
 
 impl IncomingCall {
@@ -49,11 +65,14 @@ impl IncomingCall {
     ) -> Result<IncomingCall> {
         let mut instance = Self {
             call_connection,
-            call_session_params: SessionParameters::from_request(context, &request)?,
+            call_session_params: SessionParameters::from_request(context, &request).await?,
             request,
+            provisional_status: None,
         };
 
-        instance.send_ringing().await?;
+        if context.config.auto_ringing {
+            instance.send_provisional(StatusCode::Ringing).await?;
+        }
         Ok(instance)
     }
 
@@ -62,6 +81,74 @@ impl IncomingCall {
         &self.call_session_params.remote.uri
     }
 
+    /// Identity of the caller, preferring the `P-Asserted-Identity` or `Remote-Party-ID` over the `From`.
+    /// See [CallerIdentity].
+    pub fn caller_identity(&self) -> &CallerIdentity {
+        &self.call_session_params.remote.identity
+    }
+
+    /// Returns the enabled codecs offered by the caller, in the order of preference of the caller.
+    ///
+    /// When empty, no audio could be exchanged: the call can be rejected with [StatusCode::NotAcceptableHere]
+    /// instead of being accepted. The codecs can be restricted further with [accept_with](IncomingCall::accept_with).
+    pub fn compatible_codecs(&self) -> Vec<CodecKind> {
+        get_codec_kinds_from_sdp_session(&self.call_session_params.remote.sdp)
+            .into_iter()
+            .filter(CodecKind::is_enabled)
+            .collect()
+    }
+
+    /// Returns the value of the INVITE header named `name`, compared case-insensitively.
+    /// Ex: `incoming_call.header("P-Asserted-Identity")`.
+    ///
+    /// Repeated headers are joined with `, `.
+    pub fn header(&self, name: &str) -> Option<String> {
+        get_header_value(&self.request.headers, name)
+    }
+
+    /// Sets the SSRC of the outgoing RTP stream, random by default.
+    pub fn with_rtp_ssrc(mut self, ssrc: u32) -> Self {
+        self.call_session_params.local.ssrc = ssrc;
+        self
+    }
+
+    /// Sets the sequence number of the first outgoing RTP packet, random by default.
+    pub fn with_rtp_initial_sequence_number(mut self, initial_sequence_number: u16) -> Self {
+        self.call_session_params.local.initial_sequence_number = initial_sequence_number;
+        self
+    }
+
+    /// Sets the direction of the audio of the call, [MediaDirection::SendRecv] by default.
+    ///
+    /// The answer only keeps the part of the direction the caller offered, ex: [MediaDirection::RecvOnly]
+    /// to a `sendonly` offer. No audio is sent while the call can not send.
+    pub fn with_direction(mut self, direction: MediaDirection) -> Self {
+        self.call_session_params.local.direction = direction;
+        self
+    }
+
+    /// Sends a `180 Ringing`, telling the caller that the call is being presented.
+    ///
+    /// Sent automatically when the call is received unless [Config::auto_ringing](crate::config::Config::auto_ringing) is disabled.
+    ///
+    /// # Errors
+    ///
+    /// The function will return an error if it fails to send the response.
+    pub async fn ring(&mut self) -> Result<()> {
+        self.send_provisional(StatusCode::Ringing).await
+    }
+
+    /// Sends a `183 Session Progress` without SDP, telling the caller that the call is progressing.
+    ///
+    /// See [answer_early](IncomingCall::answer_early) to send audio before accepting the call.
+    ///
+    /// # Errors
+    ///
+    /// The function will return an error if it fails to send the response.
+    pub async fn progress(&mut self) -> Result<()> {
+        self.send_provisional(StatusCode::SessionProgress).await
+    }
+
     /// Accept the incoming call.
     ///
     /// - If the call can start: initializes the call and returns [IncomingCallResult::Ok]
@@ -76,25 +163,142 @@ impl IncomingCall {
     ///
     /// The function will return an error if it fails to initialize the Call.
     /// This could happen for multiple reasons, for example, no compatible codecs where found or the response was malformed.
-    pub async fn accept(mut self) -> Result<IncomingCallResult>
+    pub async fn accept(self) -> Result<IncomingCallResult, SipError>
     {
-        if let Some(request) = self.get_cancel_request() {
+        self.accept_with(&CodecKind::enabled()).await
+    }
+
+    /// Accept the incoming call, only advertising the given codecs in the answer.
+    ///
+    /// Codecs are advertised in the given order, codecs whose feature is not enabled are ignored.
+    /// See [accept](IncomingCall::accept) for the returned values.
+    ///
+    /// # Errors
+    ///
+    /// The function will return an error if none of the given codecs are enabled.
+    /// Otherwise, see [accept](IncomingCall::accept).
+    pub async fn accept_with(mut self, codecs: &[CodecKind]) -> Result<IncomingCallResult, SipError>
+    {
+        self.set_sdp_answer(codecs)?;
+        self.answer(None).await
+    }
+
+    /// Answers with early media: sends a `183 Session Progress` with our SDP and starts the RTP session,
+    /// so that audio can be played (e.g. a greeting or a menu) before the call is accepted.
+    ///
+    /// - If the early media can start: returns [EarlyCallResult::Ok] with an [EarlyCall] to send audio to,
+    ///   see [EarlyCall::accept] to answer the call
+    ///
+    /// - If the call was cancelled by the remote (already hung up): acknowledges the cancellation and
+    ///   returns [EarlyCallResult::Cancelled]
+    ///
+    /// # Errors
+    ///
+    /// See [accept](IncomingCall::accept).
+    pub async fn answer_early(self) -> Result<EarlyCallResult, SipError>
+    {
+        self.answer_early_with(&CodecKind::enabled()).await
+    }
+
+    /// Answers with early media, only advertising the given codecs, see [answer_early](IncomingCall::answer_early)
+    /// and [accept_with](IncomingCall::accept_with).
+    pub async fn answer_early_with(self, codecs: &[CodecKind]) -> Result<EarlyCallResult, SipError>
+    {
+        self.start_early_media(codecs, StatusCode::SessionProgress).await
+    }
+
+    /// Answers with early media like [answer_early](IncomingCall::answer_early), with our SDP in a `180 Ringing`
+    /// instead of a `183 Session Progress`.
+    ///
+    /// For remotes expecting the SDP in the ringing response, the caller then usually plays our audio instead of its own ringback tone.
+    pub async fn ring_early(self) -> Result<EarlyCallResult, SipError>
+    {
+        self.ring_early_with(&CodecKind::enabled()).await
+    }
+
+    /// Rings with early media, only advertising the given codecs, see [ring_early](IncomingCall::ring_early)
+    /// and [accept_with](IncomingCall::accept_with).
+    pub async fn ring_early_with(self, codecs: &[CodecKind]) -> Result<EarlyCallResult, SipError>
+    {
+        self.start_early_media(codecs, StatusCode::Ringing).await
+    }
+
+    /// Sends our SDP in a provisional response and starts the RTP session.
+    /// The response has the To tag of all the other responses of the dialog, as the final response will.
+    #[cfg_attr(feature = "tracing", tracing::instrument(
+        name = "incoming_call",
+        skip_all,
+        fields(call_id = %self.call_session_params.call_id),
+    ))]
+    async fn start_early_media(mut self, codecs: &[CodecKind], status_code: StatusCode) -> Result<EarlyCallResult, SipError>
+    {
+        self.set_sdp_answer(codecs)?;
+
+        if self.acknowledge_cancel().await? {
+            info!("Trying to answer call with early media but was cancelled");
+            return Ok(EarlyCallResult::Cancelled);
+        }
+
+        let mut call_media = CallMedia::new(&self.call_session_params).await?;
+        let response = self.generate_sdp_response(status_code);
+        self.call_connection.send_message(response.into()).await?;
+        call_media.start_early();
+
+        Ok(EarlyCallResult::Ok(Box::new(EarlyCall {
+            incoming_call: self,
+            call_media,
+        })))
+    }
+
+    fn set_sdp_answer(&mut self, codecs: &[CodecKind]) -> Result<(), SipError> {
+        if !codecs.iter().any(CodecKind::is_enabled) {
+            return Err(SipError::NoCompatibleCodec);
+        }
+
+        let local = &mut self.call_session_params.local;
+        local.sdp = generate_sdp_answer(
+            &self.call_session_params.config,
+            local.rtp_addr,
+            codecs,
+            &self.call_session_params.remote.sdp,
+            local.direction,
+        )?;
+        Ok(())
+    }
+
+    /// Sends the 200 OK with our SDP and starts the call, reusing the media of the early media if any.
+    #[cfg_attr(feature = "tracing", tracing::instrument(
+        name = "incoming_call",
+        skip_all,
+        fields(call_id = %self.call_session_params.call_id),
+    ))]
+    async fn answer(mut self, call_media: Option<CallMedia>) -> Result<IncomingCallResult, SipError>
+    {
+        if self.acknowledge_cancel().await? {
             info!("Trying to accept call but was cancelled");
-            let response = self.generate_response(&request, StatusCode::OK);
-            self.call_connection.send_message(response.into()).await?;
             return Ok(IncomingCallResult::Cancelled);
         }
 
-        let mut response = self.generate_response(&self.request, StatusCode::OK);
+        let mut response = self.generate_sdp_response(StatusCode::OK);
+        add_response_headers(&self.request.headers, &mut response.headers)?;
+        self.call_connection.send_message(response.clone().into()).await?;
 
-        let body = self.call_session_params.local.sdp.to_string().into_bytes();
-        response.headers.unique_push(ContentType(MediaType::Sdp(Vec::new())).into());
-        response.headers.unique_push(ContentLength::from(body.len() as u32).into());
-        response.body = body;
+        let invite_transaction = InviteTransaction::Response(response);
+        let call = match call_media {
+            Some(call_media) => Call::with_media(self.call_connection, self.call_session_params, invite_transaction, call_media).await?,
+            None => Call::new(self.call_connection, self.call_session_params, invite_transaction).await?,
+        };
+        Ok(IncomingCallResult::Ok(call))
+    }
 
+    /// Replies to a pending CANCEL if any, returns `true` if the call was cancelled.
+    async fn acknowledge_cancel(&mut self) -> Result<bool> {
+        let Some(request) = self.get_cancel_request().await else {
+            return Ok(false);
+        };
+        let response = self.generate_response(&request, StatusCode::OK);
         self.call_connection.send_message(response.into()).await?;
-
-        Ok(IncomingCallResult::Ok(Call::new(self.call_connection, self.call_session_params).await?))
+        Ok(true)
     }
 
     /// Reject the incoming call.
@@ -104,34 +308,118 @@ impl IncomingCall {
     ///
     /// The function will return an error if it fails to reply.
     /// This could happen for multiple reasons, for example, the connection was lost to the SIP server.
-    pub async fn reject(mut self) -> Result<()>
+    pub async fn reject(self) -> Result<()>
+    {
+        self.reject_with(StatusCode::BusyEverywhere).await
+    }
+
+    /// Reject the incoming call with the given status code.
+    /// For example [StatusCode::Decline], [StatusCode::Forbidden] or [StatusCode::TemporarilyUnavailable].
+    ///
+    /// # Errors
+    ///
+    /// The function will return an error without replying if `status_code` is not a final error response (3xx to 6xx),
+    /// use [accept](IncomingCall::accept) to answer the call.
+    ///
+    /// The function will return an error if it fails to reply.
+    /// This could happen for multiple reasons, for example, the connection was lost to the SIP server.
+    pub async fn reject_with(self, status_code: StatusCode) -> Result<()>
+    {
+        self.send_rejection(status_code, Vec::new()).await
+    }
+
+    /// Reject the incoming call with the given status code and a `Retry-After` header.
+    /// Typically used with [StatusCode::TemporarilyUnavailable] or [StatusCode::ServiceUnavailable].
+    ///
+    /// # Errors
+    ///
+    /// The function will return an error without replying if `status_code` is not a final error response (3xx to 6xx).
+    ///
+    /// The function will return an error if it fails to reply.
+    /// This could happen for multiple reasons, for example, the connection was lost to the SIP server.
+    pub async fn reject_with_retry_after(self, status_code: StatusCode, retry_after: Duration) -> Result<()>
     {
-        if let Some(request) = self.get_cancel_request() {
+        self.send_rejection(status_code, vec![RetryAfter::new(retry_after.as_secs().to_string()).into()]).await
+    }
+
+    /// Redirects the incoming call to `target` with a `302 Moved Temporarily`, the caller then calls `target` instead.
+    /// Ex: forwarding unanswered calls to a mobile number.
+    ///
+    /// # Errors
+    ///
+    /// The function will return an error if it fails to reply.
+    /// This could happen for multiple reasons, for example, the connection was lost to the SIP server.
+    pub async fn redirect(self, target: Uri) -> Result<()>
+    {
+        let contact = rsip::typed::Contact {
+            display_name: None,
+            uri: target,
+            params: Vec::new(),
+        };
+        self.send_rejection(StatusCode::MovedTemporarily, vec![contact.into()]).await
+    }
+
+    /// Sends a final error response to the INVITE with the additional `headers`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(
+        name = "incoming_call",
+        skip_all,
+        fields(call_id = %self.call_session_params.call_id),
+    ))]
+    async fn send_rejection(mut self, status_code: StatusCode, headers: Vec<Header>) -> Result<()>
+    {
+        if status_code.code() < 300 {
+            return Err(anyhow!("Cannot reject a call with {}, a final error response (3xx to 6xx) is required", status_code));
+        }
+        if let Some(request) = self.get_cancel_request().await {
             info!("Try to reject call but was already cancelled");
             let response = self.generate_response(&request, StatusCode::OK);
             self.call_connection.send_message(response.into()).await?;
             return Ok(());
         }
-        self.call_connection.send_message(self.generate_response(&self.request, StatusCode::BusyEverywhere).into()).await?;
+
+        let mut response = self.generate_response(&self.request, status_code);
+        for header in headers {
+            response.headers.push(header);
+        }
+        self.call_connection.send_message(response.into()).await?;
         Ok(())
     }
 
-    async fn send_ringing(&mut self) -> Result<()> {
-        self.call_connection.send_message(self.generate_response(&self.request, StatusCode::Ringing).into()).await?;
+    async fn send_provisional(&mut self, status_code: StatusCode) -> Result<()> {
+        self.call_connection.send_message(self.generate_response(&self.request, status_code.clone()).into()).await?;
+        self.provisional_status = Some(status_code);
         Ok(())
     }
 
-    fn get_cancel_request(&mut self) -> Option<Request> {
+    async fn get_cancel_request(&mut self) -> Option<Request> {
         while let Ok(Some(message)) = self.call_connection.try_recv() {
             if let SipMessage::Request(request) = message {
-                if request.method == Method::Cancel {
-                    return Some(request);
+                match request.method {
+                    Method::Cancel => return Some(request),
+                    // The remote did not receive our provisional response
+                    Method::Invite => {
+                        if let Some(status_code) = self.provisional_status.clone() {
+                            let _ = self.send_provisional(status_code).await;
+                        }
+                    }
+                    _ => {}
                 }
             }
         }
         None
     }
 
+    /// Response to the INVITE carrying our SDP.
+    fn generate_sdp_response(&self, status_code: StatusCode) -> Response {
+        let mut response = self.generate_response(&self.request, status_code);
+
+        let body = self.call_session_params.local.sdp.to_string().into_bytes();
+        response.headers.unique_push(ContentType(MediaType::Sdp(Vec::new())).into());
+        response.headers.unique_push(ContentLength::from(body.len() as u32).into());
+        response.body = body;
+        response
+    }
+
     fn generate_response(&self, request: &Request, status_code: StatusCode) -> Response {
         let ok_res = Response {
             status_code,
@@ -142,4 +430,69 @@ impl IncomingCall {
         ok_res
     }
 
-}
\ No newline at end of file
+}
+
+/// Incoming call answered with early media, see [IncomingCall::answer_early].
+///
+/// Audio sent before [accept](EarlyCall::accept) is played to the remote before the call is answered,
+/// the call then continues on the same RTP session.
+/// Dropping the early call stops the media without replying to the remote.
+pub struct EarlyCall {
+    incoming_call: IncomingCall,
+    call_media: CallMedia,
+}
+
+impl EarlyCall {
+    /// [Uri] of the caller.
+    pub fn get_remote_uri(&self) -> &Uri {
+        self.incoming_call.get_remote_uri()
+    }
+
+    /// Identity of the caller, see [IncomingCall::caller_identity].
+    pub fn caller_identity(&self) -> &CallerIdentity {
+        self.incoming_call.caller_identity()
+    }
+
+    /// Returns the value of the INVITE header named `name`, see [IncomingCall::header].
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.incoming_call.header(name)
+    }
+
+    /// Adds the given samples to the output audio buffer, see [Call::send_audio].
+    ///
+    /// # Errors
+    /// Errors when failing to send the audio to the RTP session.
+    pub fn send_audio(&self, audio: Vec<f32>) -> Result<()>
+    {
+        queue_audio(&self.call_media.buffered_len, &self.call_media.media_channel.sender, audio).context("Failed to send audio to early media.")
+    }
+
+    /// Receive the next media message. Blocking until a message arrives.
+    pub async fn recv_media(&mut self) -> Option<Media> {
+        self.call_media.media_channel.receiver.recv().await
+    }
+
+    /// Blocks until the output buffer is empty, see [Call::block_for_output_empty].
+    pub async fn block_for_output_empty(&mut self) {
+        let mut buffered_len = self.call_media.buffered_len.subscribe();
+        tokio::select! {
+            // The early media task is over, the audio will not be sent
+            _ = self.call_media.media_channel.sender.closed() => {}
+            _ = buffered_len.wait_for(|buffered_len| *buffered_len == 0) => {}
+        }
+    }
+
+    /// Accepts the call, the RTP session of the early media is reused.
+    /// See [IncomingCall::accept] for the returned values.
+    pub async fn accept(self) -> Result<IncomingCallResult, SipError>
+    {
+        self.incoming_call.answer(Some(self.call_media)).await
+    }
+
+    /// Stops the early media and rejects the call, see [IncomingCall::reject_with].
+    pub async fn reject_with(self, status_code: StatusCode) -> Result<()>
+    {
+        drop(self.call_media);
+        self.incoming_call.reject_with(status_code).await
+    }
+}
@@ -1,91 +1,373 @@
 pub mod incoming_call;
 pub mod outgoing_call;
+pub mod identity;
 mod call_handler;
 mod session_parameters;
-mod rtp_session;
+pub(crate) mod rtp_session;
+mod rtp_reorder;
+mod frame_chunker;
 
 use std::cmp::PartialEq;
-use anyhow::{Context, Result};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Context, Result};
 use futures_util::future::Either;
-use rsip::Uri;
-use log::debug;
+use rsip::{Header, Method, Response};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::error::SendError;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{oneshot, watch};
 use tokio::task::JoinHandle;
+use tokio_util::sync::{CancellationToken, DropGuard};
+use webrtc_sdp::SdpSession;
 
+use crate::call::identity::CallerIdentity;
 use crate::call::session_parameters::SessionParameters;
-use crate::call::call_handler::call_task;
-use crate::call::rtp_session::rtp_task;
+use crate::call::call_handler::{call_task, InDialogRequest, InviteTransaction};
+use crate::call::rtp_session::{early_rtp_task, rtp_task, RTPSession};
 use crate::connection::call_connection::CallConnection;
-use crate::media::telephone_events::TelephoneEvent;
+use crate::media::telephone_events::{sdp_has_telephone_events, TelephoneEvent};
+use crate::media::{CodecInfo, RtpPacketInfo};
+use crate::media::resample::resample_f32;
 use crate::utils::{create_mpsc_bidirectional_unbounded, BidirectionalChannel};
 
 #[derive(Debug)]
 pub enum Media {
+    /// `f32` samples at [Config::audio_sample_rate](crate::config::Config::audio_sample_rate),
+    /// interleaved when [Config::audio_channels](crate::config::Config::audio_channels) is 2.
     Audio(Vec<f32>),
+    /// Received audio decoded from an RTP packet, like [Media::Audio], with the header of the packet.
+    /// Replaces [Media::Audio] for received audio when [Config::rtp_packet_info](crate::config::Config::rtp_packet_info) is enabled,
+    /// generated audio like comfort noise is still received as [Media::Audio].
+    ReceivedAudio {
+        audio: Vec<f32>,
+        info: RtpPacketInfo,
+    },
     TelephoneEvent((TelephoneEvent, bool)),
+    /// RMS level of the next received audio frame, from 0.0 for silence to 1.0 for a full scale signal.
+    /// Only sent when [Config::audio_level](crate::config::Config::audio_level) is enabled.
+    AudioLevel(f32),
     OutputEmpty,
+    /// Outgoing audio was dropped because the output buffer is full.
+    OutputFull,
+    /// The first RTP packet was received from the remote, the inbound media path is live. Sent once per call.
+    ReceivingStarted,
+    /// The first RTP packet was sent to the remote. Sent once per call.
+    SendingStarted,
+    /// Outgoing `i16` samples, interleaved when `channels` is 2, see [Call::send_audio_i16]. Never received.
+    AudioI16 {
+        samples: Vec<i16>,
+        sample_rate: u32,
+        channels: u8,
+    },
+    /// Payload of an RTP packet of a codec the crate can not decode, received and sent as is
+    /// when [Config::rtp_passthrough](crate::config::Config::rtp_passthrough) is enabled.
+    /// Sent payloads are queued, one packet being sent per packet interval.
+    RawPayload {
+        payload_type: u8,
+        payload: Vec<u8>,
+    },
+    /// Outgoing DTMF digit sent as RFC 4733 telephone events for `duration`, followed by `gap` of silence before the next digit,
+    /// see [Call::send_dtmf_sequence]. Never received.
+    DtmfDigit {
+        digit: TelephoneEvent,
+        duration: Duration,
+        gap: Duration,
+    },
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+impl Media {
+    /// Number of samples at `audio_rate` with `channels` channels the outgoing audio accounts for in the output buffer.
+    pub(crate) fn buffered_len(&self, audio_rate: u32, channels: u8) -> usize {
+        match self {
+            Media::Audio(audio) => audio.len(),
+            Media::AudioI16 { samples, sample_rate, channels: from_channels } => {
+                samples.len() / *from_channels as usize * audio_rate as usize / *sample_rate as usize * channels as usize
+            }
+            _ => 0,
+        }
+    }
+}
+
+/// Direction of the audio of a call as advertised in our SDP, from our point of view.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MediaDirection {
+    /// Audio is sent and received.
+    #[default]
+    SendRecv,
+    /// Audio is only sent, ex: playing an announcement.
+    SendOnly,
+    /// Audio is only received, ex: recording or monitoring a call.
+    RecvOnly,
+    /// No audio is exchanged.
+    Inactive,
+}
+
+impl MediaDirection {
+    fn from_flags(send: bool, receive: bool) -> Self {
+        match (send, receive) {
+            (true, true) => MediaDirection::SendRecv,
+            (true, false) => MediaDirection::SendOnly,
+            (false, true) => MediaDirection::RecvOnly,
+            (false, false) => MediaDirection::Inactive,
+        }
+    }
+
+    /// Returns `true` if audio is sent in this direction.
+    pub fn can_send(&self) -> bool {
+        matches!(self, MediaDirection::SendRecv | MediaDirection::SendOnly)
+    }
+
+    /// Returns `true` if audio is received in this direction.
+    pub fn can_receive(&self) -> bool {
+        matches!(self, MediaDirection::SendRecv | MediaDirection::RecvOnly)
+    }
+
+    /// The same direction seen from the other side.
+    pub(crate) fn reverse(&self) -> Self {
+        Self::from_flags(self.can_receive(), self.can_send())
+    }
+
+    /// The direction allowed by both `self` and `other`.
+    pub(crate) fn intersect(&self, other: MediaDirection) -> Self {
+        Self::from_flags(self.can_send() && other.can_send(), self.can_receive() && other.can_receive())
+    }
+}
+
+/// Why a call ended, see [CallControl::Finished].
+#[derive(Clone, Debug, PartialEq)]
+pub enum EndReason {
+    /// The call was hung up locally, through [Call::hangup] or by dropping the [Call].
+    LocalHangup,
+    /// The remote hung up.
+    RemoteBye,
+    /// The remote did not acknowledge the call.
+    Timeout,
+    /// No RTP was received for [Config::rtp_timeout](crate::config::Config::rtp_timeout).
+    MediaTimeout,
+    /// The session was not refreshed in time (RFC 4028 session timers).
+    SessionExpired,
+    /// The call lasted longer than its maximum duration, see [Call::set_max_duration].
+    MaxDuration,
+    /// The call ended because of an error.
+    Error(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum CallControl {
     Hangup,
+    /// Sends a DTMF digit in a SIP INFO request, see [Call::send_dtmf_info].
+    SendDtmfInfo(TelephoneEvent),
+    /// Sends an UPDATE with our current SDP, see [Call::send_update].
+    SendUpdate,
+    /// Changes the maximum duration of the call, see [Call::set_max_duration].
+    SetMaxDuration(Option<Duration>),
     AudioOutEmpty,
-    Finished,
+    /// The call is over, no more messages will be received.
+    Finished(EndReason),
+    /// No RTP was received for [Config::rtp_timeout](crate::config::Config::rtp_timeout), sent by the media task to hang up the call.
+    MediaTimeout,
+    /// The call was taken over by another dialog (attended transfer), media continues with the new remote.
+    Replaced,
+}
+
+/// Audio buffered at once by [Call::announce], at most [Config::max_audio_buffer](crate::config::Config::max_audio_buffer).
+const ANNOUNCE_BUFFER: Duration = Duration::from_millis(500);
+
+/// RTP session and channels of a call, created before the [Call] to send early media.
+struct CallMedia {
+    rtp_session: Option<RTPSession>,
+    /// Cancels the early media task when dropped.
+    early_media: Option<(DropGuard, JoinHandle<Result<RTPSession>>)>,
+    local_rtp_addr: SocketAddr,
+    negotiated_codec: Option<CodecInfo>,
+
+    call_channel_local: BidirectionalChannel<CallControl>,
+    call_channel_remote: BidirectionalChannel<CallControl>,
+    media_channel: BidirectionalChannel<Media>,
+    session_update_sender: UnboundedSender<SessionParameters>,
+    buffered_len: Arc<watch::Sender<usize>>,
+    dtmf_receiver: UnboundedReceiver<TelephoneEvent>,
+    runtime: Handle,
+}
+
+impl CallMedia {
+    /// Binds the RTP socket and negotiates the media of the session.
+    async fn new(call_session_params: &SessionParameters) -> Result<Self> {
+        let (call_channel_local, call_channel_remote) = create_mpsc_bidirectional_unbounded();
+        let (media_channel_local, media_channel_remote) = create_mpsc_bidirectional_unbounded();
+
+        let (session_update_sender, session_update_receiver) = unbounded_channel();
+        let buffered_len = Arc::new(watch::channel(0).0);
+        let (dtmf_sender, dtmf_receiver) = unbounded_channel();
+        let rtp_session = RTPSession::new(
+            media_channel_remote,
+            call_session_params.clone(),
+            session_update_receiver,
+            call_channel_local.sender.clone(),
+            buffered_len.clone(),
+            dtmf_sender,
+        ).await?;
+
+        Ok(CallMedia {
+            local_rtp_addr: rtp_session.local_addr()?,
+            negotiated_codec: rtp_session.get_negotiated_codec(),
+            rtp_session: Some(rtp_session),
+            early_media: None,
+            call_channel_local,
+            call_channel_remote,
+            media_channel: media_channel_local,
+            session_update_sender,
+            buffered_len,
+            dtmf_receiver,
+            runtime: call_session_params.local.runtime.clone(),
+        })
+    }
+
+    /// Starts sending the audio before the call is answered.
+    /// The RTP timeout is disabled until the call is answered, the remote usually does not send audio before.
+    fn start_early(&mut self) {
+        let Some(mut rtp_session) = self.rtp_session.take() else {
+            return;
+        };
+        rtp_session.set_rtp_timeout(None);
+
+        let cancellation_token = CancellationToken::new();
+        let token = cancellation_token.clone();
+        let handle = self.runtime.spawn(async move {
+            early_rtp_task(rtp_session, token).await
+        });
+        self.early_media = Some((cancellation_token.drop_guard(), handle));
+    }
+
+    /// Stops the early media, if any, and returns the RTP session.
+    async fn take_rtp_session(&mut self) -> Result<RTPSession> {
+        if let Some((drop_guard, handle)) = self.early_media.take() {
+            drop(drop_guard);
+            self.rtp_session = Some(handle.await??);
+        }
+        self.rtp_session.take().ok_or(anyhow!("RTP session already taken"))
+    }
 }
 
 /// Represents an ongoing (as been answered) call.
+///
+/// Dropping the call hangs it up, a BYE is sent on a best-effort basis.
 pub struct Call {
+    cancellation_token: CancellationToken,
     call_handle: JoinHandle<Result<()>>,
     rtp_handle: JoinHandle<Result<()>>,
-    remote_uri: Uri,
+    remote_identity: CallerIdentity,
+    negotiated_codec: Option<CodecInfo>,
+    audio_channels: u8,
+    audio_sample_rate: u32,
+    local_rtp_addr: SocketAddr,
+    rtp_ssrc: u32,
+    rtp_initial_sequence_number: u16,
+    /// Parameters of the dialog, kept up to date by the call task as the SDPs are renegotiated or the call is replaced.
+    session_params: watch::Receiver<SessionParameters>,
+    /// When the 200 OK was sent for incoming calls, or the ACK for outgoing calls.
+    answered_at: Instant,
 
     call_channel: BidirectionalChannel<CallControl>,
     media_channel: BidirectionalChannel<Media>,
+    /// Requests sent with [Call::send_in_dialog_request], to the call task.
+    in_dialog_request_sender: UnboundedSender<InDialogRequest>,
+    /// Outgoing audio samples not encoded yet, shared with the RTP session.
+    buffered_len: Arc<watch::Sender<usize>>,
+    /// Digits pressed by the remote, see [Call::recv_dtmf].
+    dtmf_receiver: UnboundedReceiver<TelephoneEvent>,
+    max_audio_buffer: Option<Duration>,
 }
 
 impl Call {
-    async fn new(call_connection: CallConnection, call_session_params: SessionParameters) -> Result<Self>
+    async fn new(
+        call_connection: CallConnection,
+        call_session_params: SessionParameters,
+        invite_transaction: InviteTransaction,
+    ) -> Result<Self>
     {
-        let (call_channel_local, call_channel_remote) = create_mpsc_bidirectional_unbounded();
-        let (media_channel_local, media_channel_remote) = create_mpsc_bidirectional_unbounded();
+        let call_media = CallMedia::new(&call_session_params).await?;
+        Self::with_media(call_connection, call_session_params, invite_transaction, call_media).await
+    }
 
-        let remote_uri = call_session_params.remote.uri.clone();
-
-        let cloned_call_session_params = call_session_params.clone();
-        let call_handle = tokio::task::spawn(async move {
-            let res = call_task(
-                call_channel_remote,
-                call_connection,
-                cloned_call_session_params
-            ).await;
-            debug!("Call task finished with {:?}", res);
-            res
-        });
+    /// Starts the call with an already negotiated media session, reused from the early media if any.
+    async fn with_media(
+        call_connection: CallConnection,
+        call_session_params: SessionParameters,
+        invite_transaction: InviteTransaction,
+        mut call_media: CallMedia,
+    ) -> Result<Self>
+    {
+        let answered_at = Instant::now();
+        let mut rtp_session = call_media.take_rtp_session().await?;
+        rtp_session.set_rtp_timeout(call_session_params.config.rtp_timeout);
 
-        let rtp_handle = tokio::task::spawn(async move {
-            let res = rtp_task(media_channel_remote, call_session_params).await;
-            debug!("RTP task finished with {:?}", res);
-            res
-        });
+        let remote_identity = call_session_params.remote.identity.clone();
+        let audio_channels = call_session_params.config.audio_channels;
+        let audio_sample_rate = call_session_params.config.audio_sample_rate;
+        let rtp_ssrc = call_session_params.local.ssrc;
+        let rtp_initial_sequence_number = call_session_params.local.initial_sequence_number;
+
+        let CallMedia {
+            local_rtp_addr,
+            negotiated_codec,
+            call_channel_local,
+            call_channel_remote,
+            media_channel: media_channel_local,
+            session_update_sender,
+            buffered_len,
+            dtmf_receiver,
+            ..
+        } = call_media;
+
+        let cancellation_token = CancellationToken::new();
+        let (in_dialog_request_sender, in_dialog_requests) = unbounded_channel();
+        let (session_params_sender, session_params) = watch::channel(call_session_params.clone());
+
+        let runtime = call_session_params.local.runtime.clone();
+        let call_token = cancellation_token.clone();
+        let call_handle = runtime.spawn(call_task(
+            call_channel_remote,
+            in_dialog_requests,
+            call_connection,
+            session_params_sender,
+            invite_transaction,
+            session_update_sender,
+            call_token,
+        ));
+
+        let rtp_token = cancellation_token.clone();
+        let rtp_handle = runtime.spawn(rtp_task(rtp_session, rtp_token));
 
         Ok(Call {
+            cancellation_token,
             call_handle,
             rtp_handle,
-            remote_uri,
+            remote_identity,
+            negotiated_codec,
+            audio_channels,
+            audio_sample_rate,
+            local_rtp_addr,
+            rtp_ssrc,
+            rtp_initial_sequence_number,
+            session_params,
+            answered_at,
             call_channel: call_channel_local,
             media_channel: media_channel_local,
+            in_dialog_request_sender,
+            buffered_len,
+            dtmf_receiver,
+            max_audio_buffer: call_session_params.config.max_audio_buffer,
         })
     }
 
     /// Blocks until the call has finished (hang up and terminated the worker thread)
     pub async fn block_for_finished(&mut self) {
-        loop {
-            match self.call_channel.recv().await {
-                None => (),
-                Some(control) => {
-                    if control == CallControl::Finished {
-                        return;
-                    }
-                }
+        while let Some(control) = self.call_channel.recv().await {
+            if matches!(control, CallControl::Finished(_)) {
+                return;
             }
         }
     }
@@ -94,39 +376,233 @@ impl Call {
     ///
     /// This is typically useful when sending already recorded sound,
     /// and you want to make sure the playback is finished before proceeding.
+    ///
+    /// Returns once the last sample sent was encoded and sent to the remote, or when the call ends.
+    /// Control and media messages are left to be received.
     pub async fn block_for_output_empty(&mut self) {
-        loop {
+        let mut buffered_len = self.buffered_len.subscribe();
+        tokio::select! {
+            _ = self.call_channel.sender.closed() => {}
+            _ = buffered_len.wait_for(|buffered_len| *buffered_len == 0) => {}
+        }
+    }
+
+    /// Plays the audio to the remote, then hangs up once it was sent and returns when the call is over.
+    /// Typically an announcement played right after answering the call.
+    ///
+    /// The audio is buffered in parts, see [send_audio_bounded](Call::send_audio_bounded), so that long audio is not dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `audio`: `f32` samples at [Config::audio_sample_rate](crate::config::Config::audio_sample_rate),
+    ///   interleaved when [Config::audio_channels](crate::config::Config::audio_channels) is 2.
+    ///
+    /// # Errors
+    /// Errors when the call ends before the audio was sent, ex: the remote hung up during the announcement.
+    pub async fn announce(mut self, audio: Vec<f32>) -> Result<()>
+    {
+        let max_buffered = self.max_audio_buffer.map_or(ANNOUNCE_BUFFER, |max| max.min(ANNOUNCE_BUFFER));
+        self.send_audio_bounded(audio, max_buffered).await?;
+        self.block_for_output_empty().await;
+        if *self.buffered_len.borrow() > 0 {
+            return Err(anyhow!("Call is over, audio was not sent."));
+        }
+        self.hangup()?;
+        self.block_for_finished().await;
+        Ok(())
+    }
+
+    /// Adds the given samples to the output audio buffer.
+    ///
+    /// # Arguments
+    ///
+    /// * `audio`: `f32` samples at [Config::audio_sample_rate](crate::config::Config::audio_sample_rate) (48000Hz by default),
+    ///   interleaved when [Config::audio_channels](crate::config::Config::audio_channels) is 2.
+    ///
+    /// # Errors
+    /// Errors when failing to send the audio to the call. Most likely because the call has already ended.
+    pub fn send_audio(&self, audio: Vec<f32>) -> Result<()>
+    {
+        queue_audio(&self.buffered_len, &self.media_channel.sender, audio).context("Failed to send audio to call. Call might be over.")
+    }
+
+    /// Adds the given samples to the output audio buffer, waiting while more than `max_buffered` of audio is buffered.
+    ///
+    /// The audio is sent in parts as the buffer drains, keeping memory and latency bounded when sending long audio.
+    /// `max_buffered` should not exceed [Config::max_audio_buffer](crate::config::Config::max_audio_buffer), the audio above it is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `audio`: `f32` samples at [Config::audio_sample_rate](crate::config::Config::audio_sample_rate),
+    ///   interleaved when [Config::audio_channels](crate::config::Config::audio_channels) is 2.
+    /// * `max_buffered`: Maximum duration of the buffered audio.
+    ///
+    /// # Errors
+    /// Errors when failing to send the audio to the call or when the call ends while waiting.
+    pub async fn send_audio_bounded(&self, mut audio: Vec<f32>, max_buffered: Duration) -> Result<()>
+    {
+        let channels = self.audio_channels as usize;
+        let max_len = (max_buffered.as_millis() as usize * self.audio_sample_rate as usize / 1000 * channels).max(channels);
+        let mut buffered_len = self.buffered_len.subscribe();
+
+        while !audio.is_empty() {
             tokio::select! {
-                call_message = self.call_channel.receiver.recv() => {
-                    if let Some(control) = call_message {
-                        if control == CallControl::Finished {
-                            return;
-                        }
-                    }
-                    return;
+                // The call task dropped its channel, the audio would never be sent
+                _ = self.call_channel.sender.closed() => {
+                    return Err(anyhow!("Call is over, audio was not sent."));
                 }
-                media = self.media_channel.receiver.recv() => {
-                    if let Some(media) = media {
-                        if let Media::OutputEmpty = media {
-                            return;
-                        }
-                    }
+                available = buffered_len.wait_for(|buffered_len| *buffered_len < max_len) => {
+                    available.context("Failed to send audio to call. Call might be over.")?;
                 }
             }
+
+            // Another clone of the call may have filled the buffer in between
+            let available = max_len.saturating_sub(*buffered_len.borrow());
+            if available == 0 {
+                continue;
+            }
+            let len = (available - available % channels).max(channels).min(audio.len());
+            let remaining = audio.split_off(len);
+            self.send_audio(audio)?;
+            audio = remaining;
         }
+        Ok(())
     }
 
-    /// Adds the given samples to the output audio buffer.
+    /// Returns the duration of the audio sent with [send_audio](Call::send_audio) not played yet.
+    pub fn buffered_duration(&self) -> Duration {
+        let samples_per_second = self.audio_sample_rate as u64 * self.audio_channels as u64;
+        Duration::from_micros(*self.buffered_len.borrow() as u64 * 1_000_000 / samples_per_second)
+    }
+
+    /// Resamples the given samples to the internal format and adds them to the output audio buffer.
     ///
     /// # Arguments
     ///
-    /// * `audio`: Interleaved stereo `f32` samples @ 48000Hz.
+    /// * `audio`: `f32` samples, interleaved when `channels` is 2.
+    /// * `sample_rate`: Sample rate of `audio` in Hz.
+    /// * `channels`: Number of channels of `audio`, 1 or 2.
     ///
     /// # Errors
-    /// Errors when failing to send the audio to the call. Most likely because the call has already ended.
-    pub fn send_audio(&self, audio: Vec<f32>) -> Result<()>
+    /// Errors when the sample rate is 0, the number of channels is not supported or failing to send the audio to the call.
+    pub fn send_audio_with_format(&self, audio: Vec<f32>, sample_rate: u32, channels: u8) -> Result<()>
     {
-        self.media_channel.sender.send(Media::Audio(audio)).context("Failed to send audio to call. Call might be over.")
+        check_audio_format(sample_rate, channels)?;
+        self.send_audio(resample_f32(audio, sample_rate, self.audio_sample_rate, channels, self.audio_channels))
+    }
+
+    /// Adds the given `i16` samples to the output audio buffer, converted by the codec.
+    ///
+    /// Mono audio at the clock rate of a PCMU or PCMA codec (8000Hz) is encoded as is, skipping the conversion to `f32`.
+    ///
+    /// # Arguments
+    ///
+    /// * `audio`: `i16` samples, interleaved when `channels` is 2.
+    /// * `sample_rate`: Sample rate of `audio` in Hz.
+    /// * `channels`: Number of channels of `audio`, 1 or 2.
+    ///
+    /// # Errors
+    /// Errors when the sample rate is 0, the number of channels is not supported or failing to send the audio to the call.
+    pub fn send_audio_i16(&self, audio: Vec<i16>, sample_rate: u32, channels: u8) -> Result<()>
+    {
+        check_audio_format(sample_rate, channels)?;
+        let media = Media::AudioI16 { samples: audio, sample_rate, channels };
+        // Counted before sending, the RTP session might encode the audio right away
+        self.buffered_len.send_modify(|buffered_len| *buffered_len += media.buffered_len(self.audio_sample_rate, self.audio_channels));
+        self.media_channel.sender.send(media).context("Failed to send audio to call. Call might be over.")
+    }
+
+    /// Sends the payload of an RTP packet as is, for a codec passed through by
+    /// [Config::rtp_passthrough](crate::config::Config::rtp_passthrough), see [Media::RawPayload].
+    ///
+    /// # Errors
+    /// Errors when failing to send the payload to the call. Most likely because the call has already ended.
+    /// Payloads of a payload type not passed through are dropped.
+    pub fn send_raw_payload(&self, payload_type: u8, payload: Vec<u8>) -> Result<()>
+    {
+        self.media_channel.sender.send(Media::RawPayload { payload_type, payload }).context("Failed to send payload to call. Call might be over.")
+    }
+
+    /// Sends the DTMF digits of `digits` as RFC 4733 telephone events in the RTP stream, ex: `"12345#"`.
+    ///
+    /// Each digit lasts `digit_duration` and is followed by `gap` of silence, the audio keeps being sent meanwhile.
+    /// Digits are queued after the ones already being sent.
+    ///
+    /// # Errors
+    /// Errors when a character is not a DTMF digit (`0-9`, `*`, `#`, `A-D`), nothing is sent then.
+    /// Also errors when the remote does not support telephone events or failing to send the digits to the call.
+    pub fn send_dtmf_sequence(&self, digits: &str, digit_duration: Duration, gap: Duration) -> Result<()>
+    {
+        let digits = digits.chars().map(TelephoneEvent::try_from_char).collect::<Result<Vec<_>>>()?;
+        if !sdp_has_telephone_events(&self.session_params.borrow().remote.sdp) {
+            return Err(anyhow!("The remote does not support telephone events"));
+        }
+        for digit in digits {
+            self.media_channel.sender.send(Media::DtmfDigit { digit, duration: digit_duration, gap })
+                .context("Failed to send DTMF to call. Call might be over.")?;
+        }
+        Ok(())
+    }
+
+    /// Sends a DTMF digit in a SIP INFO request (`application/dtmf-relay`) instead of in the RTP stream.
+    ///
+    /// Useful with gateways not supporting RFC 4733 telephone events. Digits are sent one INFO at a time, in order.
+    ///
+    /// # Errors
+    /// Errors when the remote does not allow INFO, see [Call::remote_supports].
+    /// Errors when failing to send the digit to the call. Most likely because the call has already ended.
+    pub fn send_dtmf_info(&self, digit: TelephoneEvent) -> Result<()>
+    {
+        if !self.remote_supports(Method::Info) {
+            return Err(anyhow!("The remote does not allow INFO"));
+        }
+        self.call_channel.sender.send(CallControl::SendDtmfInfo(digit)).context("Failed to send DTMF to call. Call might be over.")
+    }
+
+    /// Sends an in-dialog UPDATE (RFC 3311) offering our current SDP, also refreshing the session timer if any.
+    /// The media follows the SDP of the answer.
+    ///
+    /// Ignored unless [Config::allow_update](crate::config::Config::allow_update) is set, when the remote does not allow UPDATE
+    /// (see [Call::remote_supports]), or when a re-INVITE or UPDATE is already pending.
+    ///
+    /// # Errors
+    /// Errors when failing to send the request to the call. Most likely because the call has already ended.
+    pub fn send_update(&self) -> Result<()>
+    {
+        self.call_channel.sender.send(CallControl::SendUpdate).context("Failed to send UPDATE to call. Call might be over.")
+    }
+
+    /// Sends a request with `method` in the dialog of the call, returning the final response of the remote.
+    ///
+    /// The Call-ID, tags, CSeq and route set of the dialog are filled in, `headers` are added to them with a `Content-Length`
+    /// matching `body`. Meant for methods the crate does not support, like NOTIFY or PUBLISH.
+    ///
+    /// Waits until the call ends when the remote never answers, wrap it in a timeout if needed.
+    ///
+    /// # Errors
+    /// Errors for INVITE, ACK, BYE and CANCEL, which are sent by the crate.
+    /// Errors when the call ended before the remote answered.
+    pub async fn send_in_dialog_request(&self, method: Method, headers: Vec<Header>, body: Vec<u8>) -> Result<Response>
+    {
+        if matches!(method, Method::Invite | Method::Ack | Method::Bye | Method::Cancel) {
+            return Err(anyhow!("{} requests are sent by the crate", method));
+        }
+        let (response_sender, response_receiver) = oneshot::channel();
+        self.in_dialog_request_sender.send(InDialogRequest { method, headers, body, response_sender })
+            .context("Failed to send request to call. Call might be over.")?;
+        response_receiver.await.context("Call ended before the response was received")
+    }
+
+    /// Sets the maximum duration of the call, counted from when it was answered, after which it is hung up
+    /// with [EndReason::MaxDuration]. `None` removes the limit.
+    ///
+    /// Overrides [Config::max_call_duration](crate::config::Config::max_call_duration) for this call.
+    ///
+    /// # Errors
+    /// Errors when failing to send the duration to the call. Most likely because the call has already ended.
+    pub fn set_max_duration(&self, max_duration: Option<Duration>) -> Result<()>
+    {
+        self.call_channel.sender.send(CallControl::SetMaxDuration(max_duration)).context("Failed to send max duration to call. Call might be over.")
     }
 
     /// Tries to hang up the call. Might fail if the call is already over.
@@ -146,6 +622,14 @@ impl Call {
         self.media_channel.receiver.recv().await
     }
 
+    /// Receive the next DTMF digit pressed by the remote. Blocking until a digit is released.
+    ///
+    /// Each press is received once, when it ends, while the start and end of the press are still received
+    /// as [Media::TelephoneEvent] with the media. Returns `None` once the call is over.
+    pub async fn recv_dtmf(&mut self) -> Option<TelephoneEvent> {
+        self.dtmf_receiver.recv().await
+    }
+
     /// Receive either the next control message or the next media message.
     pub async fn recv_either(&mut self) -> Either<Option<CallControl>, Option<Media>> {
         tokio::select! {
@@ -159,10 +643,73 @@ impl Call {
 
     }
 
-    /// Returns the remote URI
-    pub fn get_remote_uri(&self) -> &String
+    /// Returns the user part of the remote URI, or its host when the URI has no user part (ex: `sip:example.com`).
+    pub fn get_remote_uri(&self) -> String
+    {
+        let session_params = self.session_params.borrow();
+        let remote_uri = &session_params.remote.uri;
+        match &remote_uri.auth {
+            Some(auth) => auth.user.clone(),
+            None => remote_uri.host_with_port.host.to_string(),
+        }
+    }
+
+    /// Returns the identity of the remote, preferring the `P-Asserted-Identity` over the `From` (or `To` for outgoing calls).
+    /// See [CallerIdentity].
+    pub fn remote_identity(&self) -> &CallerIdentity
     {
-        &self.remote_uri.auth.as_ref().unwrap().user
+        &self.remote_identity
+    }
+
+    /// Returns when the call was answered: when the 200 OK was sent for incoming calls, or the ACK for outgoing calls.
+    pub fn answered_at(&self) -> Instant {
+        self.answered_at
+    }
+
+    /// Returns the time elapsed since the call was answered.
+    ///
+    /// Keeps growing once the call is over, read it when the call ends to get its total duration.
+    pub fn duration(&self) -> Duration {
+        self.answered_at.elapsed()
+    }
+
+    /// Returns the codec used to send audio, `None` if no compatible audio codec was found.
+    pub fn negotiated_codec(&self) -> Option<&CodecInfo> {
+        self.negotiated_codec.as_ref()
+    }
+
+    /// Returns the local address the RTP socket of the call is bound to.
+    pub fn local_rtp_addr(&self) -> SocketAddr {
+        self.local_rtp_addr
+    }
+
+    /// Returns the SSRC of the outgoing RTP stream.
+    pub fn rtp_ssrc(&self) -> u32 {
+        self.rtp_ssrc
+    }
+
+    /// Returns the sequence number of the first outgoing RTP packet.
+    pub fn rtp_initial_sequence_number(&self) -> u16 {
+        self.rtp_initial_sequence_number
+    }
+
+    /// Returns the last SDP we sent: the offer for outgoing calls or the answer for incoming calls,
+    /// until a re-INVITE or UPDATE renegotiates it.
+    pub fn local_sdp(&self) -> SdpSession {
+        self.session_params.borrow().local.sdp.clone()
+    }
+
+    /// Returns the last SDP received from the remote, following re-INVITEs and UPDATEs.
+    pub fn remote_sdp(&self) -> SdpSession {
+        self.session_params.borrow().remote.sdp.clone()
+    }
+
+    /// Returns whether the remote supports `method`, from the `Allow` header of its INVITE or 200 OK,
+    /// or of the INVITE that replaced the call.
+    ///
+    /// Every method is assumed supported when the remote did not send an `Allow` header.
+    pub fn remote_supports(&self, method: Method) -> bool {
+        self.session_params.borrow().remote.supports(&method)
     }
 
     /// Returns the state of the underlying worker
@@ -173,13 +720,29 @@ impl Call {
     }
 }
 
+/// Adds audio of the application to the output buffer of the RTP session, see [Call::send_audio].
+fn queue_audio(buffered_len: &watch::Sender<usize>, media_sender: &UnboundedSender<Media>, audio: Vec<f32>) -> Result<(), SendError<Media>>
+{
+    // Counted before sending, the RTP session might encode the audio right away
+    buffered_len.send_modify(|buffered_len| *buffered_len += audio.len());
+    media_sender.send(Media::Audio(audio))
+}
+
+/// Checks the format of audio sent by the application.
+fn check_audio_format(sample_rate: u32, channels: u8) -> Result<()>
+{
+    if sample_rate == 0 {
+        return Err(anyhow!("Sample rate must be greater than 0"));
+    }
+    if !matches!(channels, 1 | 2) {
+        return Err(anyhow!("Audio channels must be either 1 or 2"));
+    }
+    Ok(())
+}
+
 impl Drop for Call {
     fn drop(&mut self) {
-        if !self.call_handle.is_finished() {
-            self.call_handle.abort();
-        }
-        if !self.rtp_handle.is_finished() {
-            self.rtp_handle.abort();
-        }
+        // The call task sends a BYE if the call is still ongoing, then both tasks exit
+        self.cancellation_token.cancel();
     }
 }
\ No newline at end of file
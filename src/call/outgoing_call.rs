@@ -1,27 +1,82 @@
 use anyhow::{anyhow, Result};
-use log::{debug, info};
+use crate::logging::{debug, info, warn};
 use crate::call::session_parameters::{SessionParameters, LocalSessionParameters};
-use crate::call::Call;
+use crate::call::call_handler::InviteTransaction;
+use crate::call::{Call, MediaDirection};
 use crate::config::Config;
 use crate::connection::call_connection::CallConnection;
 use crate::context::SipContext;
+use crate::error::SipError;
+use crate::media::CodecKind;
 use crate::sip_proto::sdp::generate_sdp_new;
-use rsip::headers::{ContentLength, MaxForwards, ToTypedHeader};
+use rsip::headers::{ContentLength, MaxForwards};
 use rsip::param::Tag;
 use rsip::prelude::{HeadersExt, UntypedHeader};
 use rsip::typed::{CSeq, ContentType, MediaType, Via};
-use rsip::{Headers, Method, Param, Request, Response, SipMessage, StatusCode, Uri};
+use rsip::{Header, Headers, Method, Param, Request, Response, SipMessage, StatusCode, StatusCodeKind, Uri};
+use std::time::Duration;
+use tokio::time::{timeout_at, Instant};
 use uuid::Uuid;
-use crate::sip_proto::register::{add_auth_header, ConfigAuth};
+use crate::sip_proto::prack::{get_rseq, rack_header, requires_100rel, supported_100rel_header};
+use crate::sip_proto::register::{add_auth_header, ConfigAuth, MAX_STALE_RETRIES};
+use crate::sip_proto::route::{get_remote_target, get_route_headers, get_route_set};
+use crate::sip_proto::{get_retry_after, token_or_quoted_string};
+use crate::sip_proto::retransmission::T1;
+use crate::sip_proto::session_timer::{get_min_se, min_se_header, supported_timer_header, SessionExpires, MIN_SE};
 
 pub enum OutgoingCallResponse {
     Accepted(Call),
-    Rejected(StatusCode)
+    /// Status code of the rejection and the delay before retrying requested by its `Retry-After` header, if any.
+    /// Typically sent with [StatusCode::ServiceUnavailable] or [StatusCode::TemporarilyUnavailable].
+    Rejected(StatusCode, Option<Duration>)
+}
+
+/// Options of an outgoing call, see [SipManager::call_with](crate::manager::SipManager::call_with).
+#[derive(Clone, Debug, Default)]
+pub struct CallOptions {
+    /// Adds a `Diversion` header to the INVITE, telling the callee the call was forwarded.
+    pub diversion: Option<Diversion>,
+    /// Direction of the audio offered for the call, ex: [MediaDirection::RecvOnly] to only listen.
+    pub direction: MediaDirection,
+}
+
+/// `Diversion` header (RFC 5806) of a forwarded call, carrying the original called party to the new destination.
+///
+/// # Examples
+/// ```
+///  use simple_sip_rs::call::outgoing_call::{CallOptions, Diversion};
+///
+///  let options = CallOptions {
+///     diversion: Some(Diversion::new("sip:1000@example.com".try_into().unwrap(), "no-answer")),
+///     ..Default::default()
+///  };
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diversion {
+    /// URI of the party the call was originally sent to, the one forwarding it.
+    pub uri: Uri,
+    /// Why the call was forwarded. Ex: `"user-busy"`, `"no-answer"`, `"unconditional"`, `"unknown"`.
+    /// Sent as a quoted string when it is not a token.
+    pub reason: String,
+}
+
+impl Diversion {
+    pub fn new(uri: Uri, reason: impl Into<String>) -> Self {
+        Self {
+            uri,
+            reason: reason.into(),
+        }
+    }
+
+    fn to_header(&self) -> Header {
+        Header::Other("Diversion".to_string(), format!("<{}>;reason={}", self.uri, token_or_quoted_string(&self.reason)))
+    }
 }
 
 pub enum PeekOutgoingCallResponse {
     Accepted,
-    Rejected(StatusCode),
+    /// See [OutgoingCallResponse::Rejected].
+    Rejected(StatusCode, Option<Duration>),
 }
 
 /// Represents an outgoing call that has yet to start.
@@ -38,7 +93,7 @@ pub enum PeekOutgoingCallResponse {
 ///         // ...
 ///         call.hangup().unwrap();
 ///         }
-///         OutgoingCallResponse::Rejected(status_code) => {
+///         OutgoingCallResponse::Rejected(status_code, _) => {
 ///             println!("Call was rejected with status code {status_code}");
 ///         }
 ///     }
@@ -55,6 +110,26 @@ pub struct OutgoingCall {
     local_call_session_params: LocalSessionParameters,
     config: Config,
 
+    /// Session interval requested in the INVITE, raised by a 422 response.
+    session_expires: Option<u32>,
+    min_se: u32,
+
+    /// CSeq of the last PRACK sent, numbered after the INVITE.
+    prack_cseq: Option<u32>,
+    /// `RSeq` of the last reliable provisional response acknowledged.
+    last_rseq: Option<u32>,
+
+    invite_sent_at: Instant,
+    ring_timeout: Option<Duration>,
+    ring_timed_out: bool,
+
+    /// The last INVITE sent carried credentials.
+    authenticated: bool,
+    /// Stale challenges answered for this call.
+    stale_retries: u32,
+
+    options: CallOptions,
+
     response: Option<Response>
 }
 
@@ -63,16 +138,22 @@ impl OutgoingCall {
         sip_context: &mut SipContext,
         call_connection: CallConnection,
         call_id: String,
-        uri: Uri
+        uri: Uri,
+        options: CallOptions,
     ) -> Result<Self>
     {
-        let local_port = sip_context.get_next_udp_port();
+        let (rtp_socket, rtp_addr) = sip_context.bind_next_rtp_socket().await?;
 
         let local_call_session_params = LocalSessionParameters {
             uri: sip_context.config.get_own_uri(),
             tag: format!("tt{}", Uuid::new_v4()),
-            sdp: generate_sdp_new(&sip_context.config, local_port)?,
-            port: local_port,
+            sdp: generate_sdp_new(&sip_context.config, rtp_addr, &CodecKind::enabled(), options.direction)?,
+            rtp_socket,
+            rtp_addr,
+            ssrc: rand::random(),
+            initial_sequence_number: rand::random(),
+            runtime: sip_context.runtime.clone(),
+            direction: options.direction,
         };
 
 
@@ -87,16 +168,57 @@ impl OutgoingCall {
             local_call_session_params,
             config: sip_context.config.clone(),
 
+            session_expires: sip_context.config.session_expires,
+            min_se: MIN_SE,
+
+            prack_cseq: None,
+            last_rseq: None,
+
+            invite_sent_at: Instant::now(),
+            ring_timeout: None,
+            ring_timed_out: false,
+
+            authenticated: false,
+            stale_retries: 0,
+
+            options,
+
             response: None
         };
         instance.send_invite().await?;
         Ok(instance)
     }
 
+    /// Sets a no-answer timeout, counted from the moment the invite was sent.
+    ///
+    /// If no final response is received in time, the call is automatically [cancelled](OutgoingCall::cancel)
+    /// and reported as rejected with [StatusCode::RequestTerminated].
+    pub fn with_ring_timeout(mut self, ring_timeout: Duration) -> Self {
+        self.ring_timeout = Some(ring_timeout);
+        self
+    }
+
+    /// Sets the SSRC of the outgoing RTP stream, random by default.
+    pub fn with_rtp_ssrc(mut self, ssrc: u32) -> Self {
+        self.local_call_session_params.ssrc = ssrc;
+        self
+    }
+
+    /// Sets the sequence number of the first outgoing RTP packet, random by default.
+    pub fn with_rtp_initial_sequence_number(mut self, initial_sequence_number: u16) -> Self {
+        self.local_call_session_params.initial_sequence_number = initial_sequence_number;
+        self
+    }
+
     /// Listens and blocks for a response to the call without consuming the [OutgoingCall].
     ///
     /// This is useful if you are not sure if you want to proceed with the call yet but still want to listen for responses.
     /// For example to [cancel](OutgoingCall::cancel) the call after a timeout.
+    /// For a plain no-answer timeout, see [with_ring_timeout](OutgoingCall::with_ring_timeout).
+    ///
+    /// # Errors
+    ///
+    /// See [into_call_response](OutgoingCall::into_call_response).
     ///
     /// # Examples
     ///
@@ -105,18 +227,42 @@ impl OutgoingCall {
     /// use simple_sip_rs::call::outgoing_call::OutgoingCall;
     ///
     ///  async fn handle_outgoing_call(mut outgoing_call: OutgoingCall) {
-    ///     if let Err(_) = tokio::time::timeout(Duration::from_secs(10), outgoing_call.peek_call_response())
+    ///     if let Err(_) = tokio::time::timeout(Duration::from_secs(10), outgoing_call.peek_call_response()).await
     ///     {
     ///         //Future has timed out after 10 seconds, we cancel the call.
-    ///         outgoing_call.cancel().unwrap();
+    ///         outgoing_call.cancel().await.unwrap();
     ///     }
     ///
     ///  }
     /// ```
-    pub async fn peek_call_response(&mut self) -> Result<PeekOutgoingCallResponse>
+    #[cfg_attr(feature = "tracing", tracing::instrument(
+        name = "outgoing_call",
+        skip_all,
+        fields(call_id = %self.call_id),
+    ))]
+    pub async fn peek_call_response(&mut self) -> Result<PeekOutgoingCallResponse, SipError>
     {
+        if self.ring_timed_out {
+            return Ok(PeekOutgoingCallResponse::Rejected(StatusCode::RequestTerminated, None));
+        }
+
         loop {
-            if let Some(message) = self.call_connection.recv().await {
+            let message = match self.ring_timeout {
+                Some(ring_timeout) => {
+                    match timeout_at(self.invite_sent_at + ring_timeout, self.call_connection.recv()).await {
+                        Ok(message) => message,
+                        Err(_) => {
+                            info!("No answer after {:?}, cancelling call", ring_timeout);
+                            self.cancel_invite().await?;
+                            self.ring_timed_out = true;
+                            return Ok(PeekOutgoingCallResponse::Rejected(StatusCode::RequestTerminated, None));
+                        }
+                    }
+                }
+                None => self.call_connection.recv().await,
+            };
+
+            if let Some(message) = message {
                 match message {
                     SipMessage::Request(r) => info!("Ignored request while waiting for answer: {:?}", r),
                     SipMessage::Response(response) => {
@@ -125,13 +271,13 @@ impl OutgoingCall {
                             if response.status_code == StatusCode::OK {
                                 return Ok(PeekOutgoingCallResponse::Accepted);
                             } else {
-                                return Ok(PeekOutgoingCallResponse::Rejected(response.status_code.clone()));
+                                return Ok(PeekOutgoingCallResponse::Rejected(response.status_code.clone(), get_retry_after(&response.headers)));
                             }
                         }
                     }
                 }
             } else {
-                return Err(anyhow!("Call connection closed unexpectedly"));
+                return Err(SipError::Transport(anyhow!("Call connection closed unexpectedly")));
             }
         }
     }
@@ -141,7 +287,8 @@ impl OutgoingCall {
     ///
     /// If the call is accepted, returns [OutgoingCallResponse::Accepted] containing the [Call].
     ///
-    /// If the call is rejected, returns [OutgoingCallResponse::Rejected] containing the received [StatusCode].
+    /// If the call is rejected, returns [OutgoingCallResponse::Rejected] containing the received [StatusCode]
+    /// and the `Retry-After` delay if any.
     ///
     /// # Errors
     ///
@@ -150,7 +297,7 @@ impl OutgoingCall {
     /// - Received a response that was not related to the invite
     /// - The received response was malformed
     /// - Connection to the SIP server was lost
-    pub async fn into_call_response(mut self) -> Result<OutgoingCallResponse> {
+    pub async fn into_call_response(mut self) -> Result<OutgoingCallResponse, SipError> {
         if let Some(response) = self.response.take() {
             return Ok(self.get_outgoing_call_response(response).await?);
         }
@@ -160,7 +307,10 @@ impl OutgoingCall {
         if let Some(response) = self.response.take() {
             return Ok(self.get_outgoing_call_response(response).await?);
         }
-        Err(anyhow!("Unable to get call from outgoing call"))
+        if self.ring_timed_out {
+            return Ok(OutgoingCallResponse::Rejected(StatusCode::RequestTerminated, None));
+        }
+        Err(SipError::Protocol(anyhow!("Unable to get call from outgoing call")))
     }
 
 
@@ -168,6 +318,8 @@ impl OutgoingCall {
     ///
     /// This will cancel the outgoing call and consume it. The remote phone will stop ringing.
     ///
+    /// If the remote answered before receiving the cancellation, the call is acknowledged and immediately hung up.
+    ///
     /// # Errors
     ///
     /// This function will return an error if the sending of the message fails,
@@ -176,17 +328,71 @@ impl OutgoingCall {
     /// # Examples
     ///
     /// See combined usage example with [peek_call_response](OutgoingCall::peek_call_response)
-    pub async fn cancel(mut self) -> Result<()> {
+    #[cfg_attr(feature = "tracing", tracing::instrument(
+        name = "outgoing_call",
+        skip_all,
+        fields(call_id = %self.call_id),
+    ))]
+    pub async fn cancel(mut self) -> Result<(), SipError> {
+        if let Some(response) = self.response.take() {
+            if response.status_code == StatusCode::OK {
+                return Ok(self.hangup_answered(response).await?);
+            }
+            return Ok(());
+        }
+        if self.ring_timed_out {
+            return Ok(());
+        }
+
+        Ok(self.cancel_invite().await?)
+    }
+
+    /// Sends a CANCEL and waits for the final response to the invite.
+    async fn cancel_invite(&mut self) -> Result<()> {
         let request = self.generate_cancel();
         self.call_connection.send_message(request.into()).await?;
-        Ok(())
+
+        // The remote might have answered before receiving the CANCEL, wait for the final response to the invite
+        let deadline = Instant::now() + T1 * 64;
+        loop {
+            let message = match timeout_at(deadline, self.call_connection.recv()).await {
+                Ok(Some(message)) => message,
+                Ok(None) => return Err(anyhow!("Call connection closed unexpectedly")),
+                Err(_) => {
+                    warn!("No final response received for cancelled invite");
+                    return Ok(());
+                }
+            };
+
+            if let SipMessage::Response(response) = message {
+                if response.cseq_header()?.method()? != Method::Invite {
+                    continue;
+                }
+                match response.status_code.kind() {
+                    StatusCodeKind::Provisional => {}
+                    StatusCodeKind::Successful => return self.hangup_answered(response).await,
+                    _ => {
+                        let ack = self.generate_ack(&response)?;
+                        self.call_connection.send_message(ack.into()).await?;
+                        return Ok(());
+                    }
+                }
+            }
+        }
     }
 
     async fn handle_response(&mut self, response: Response) -> Result<()>
     {
+        if response.cseq_header()?.method()? == Method::PRack {
+            debug!("PRACK answered with status code {}", response.status_code);
+            return Ok(());
+        }
         if response.cseq_header()?.method()? != Method::Invite {
             return Err(anyhow!("Unexpected response while waiting for answer: {:?}", response));
         }
+        if response.status_code.kind() == StatusCodeKind::Provisional && requires_100rel(&response.headers) {
+            self.send_prack(&response).await?;
+        }
         match response.status_code {
             StatusCode::Trying => info!("Remote is trying"),
             StatusCode::Ringing => info!("Remote is ringing"),
@@ -200,7 +406,9 @@ impl OutgoingCall {
             StatusCode::SessionProgress => {
                 debug!("Explicit ignore {:?}", response);
             }
-            StatusCode::Unauthorized => self.handle_invite_response_unauthorized(response).await?,
+            StatusCode::Unauthorized |
+            StatusCode::ProxyAuthenticationRequired => self.handle_invite_response_unauthorized(response).await?,
+            StatusCode::SessionIntervalTooSmall => self.handle_invite_response_interval_too_small(response).await?,
             _ => {
                 info!("Unexpected response while waiting for invite: {:?}", response);
             }
@@ -208,61 +416,168 @@ impl OutgoingCall {
         Ok(())
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(
+        name = "outgoing_call",
+        skip_all,
+        fields(call_id = %self.call_id),
+    ))]
     async fn get_outgoing_call_response(self, response: Response) -> Result<OutgoingCallResponse> {
         if response.status_code == StatusCode::OK {
-            let session_params = SessionParameters::from_response(
-                &response,
-                self.call_id.clone(),
-                self.local_call_session_params.clone(),
-                self.config.clone()
-            )?;
-
-            let mut headers = session_params.get_headers_request();
-            headers.unique_push(rsip::typed::CSeq::from((response.cseq_header()?.seq()?, Method::Ack)).into());
-
-            let response = Request {
-                method: Method::Ack,
-                uri: session_params.remote.uri.clone(),
-                version: Default::default(),
-                headers,
-                body: vec![],
-            };
+            let (session_params, ack) = self.acknowledge(&response).await?;
 
-            self.call_connection.send_message(response.into()).await?;
+            return Ok(OutgoingCallResponse::Accepted(Call::new(
+                self.call_connection,
+                session_params,
+                InviteTransaction::Ack(ack),
+            ).await?));
+        }
+        let retry_after = get_retry_after(&response.headers);
+        Ok(OutgoingCallResponse::Rejected(response.status_code, retry_after))
+    }
 
-            return Ok(OutgoingCallResponse::Accepted(Call::new(self.call_connection, session_params).await?));
+    /// Sends the ACK for the 200 OK response to the invite.
+    async fn acknowledge(&self, response: &Response) -> Result<(SessionParameters, Request)> {
+        let mut session_params = SessionParameters::from_response(
+            response,
+            self.call_id.clone(),
+            self.local_call_session_params.clone(),
+            self.config.clone()
+        )?;
+        if let Some(prack_cseq) = self.prack_cseq {
+            session_params.skip_cseq_to(prack_cseq);
         }
-        Ok(OutgoingCallResponse::Rejected(response.status_code))
+
+        let mut headers = session_params.get_headers_request();
+        headers.unique_push(rsip::typed::CSeq::from((response.cseq_header()?.seq()?, Method::Ack)).into());
+
+        let ack = Request {
+            method: Method::Ack,
+            uri: session_params.remote.target.clone(),
+            version: Default::default(),
+            headers,
+            body: vec![],
+        };
+
+        self.call_connection.send_message(ack.clone().into()).await?;
+
+        Ok((session_params, ack))
+    }
+
+    /// Acknowledges a 200 OK received for a cancelled invite and immediately hangs up.
+    async fn hangup_answered(&self, response: Response) -> Result<()> {
+        info!("Remote answered cancelled call, hanging up");
+        let (mut session_params, _) = self.acknowledge(&response).await?;
+
+        let mut headers = session_params.get_headers_request();
+        headers.unique_push(rsip::typed::CSeq::from((session_params.get_next_cseq(), Method::Bye)).into());
+
+        let bye = Request {
+            method: Method::Bye,
+            uri: session_params.remote.target.clone(),
+            version: Default::default(),
+            headers,
+            body: Vec::new(),
+        };
+
+        self.call_connection.send_message(bye.into()).await
     }
 
     async fn handle_invite_response_unauthorized(&mut self, response: Response) -> Result<()>
     {
-        let www_authenticate_header = response.www_authenticate_header()
-            .ok_or(anyhow!("Missing authenticate header"))?
-            .clone()
-            .into_typed()?;
+        if !self.config.has_credentials() {
+            return Err(SipError::Auth("Server requires authentication but no password is configured".to_string()).into());
+        }
+
+        let ack = self.generate_ack(&response)?;
+        self.call_connection.send_message(ack.into()).await?;
+
+        if self.authenticated {
+            // Credentials were already sent, only a stale nonce is worth another attempt
+            let stale = ConfigAuth::from_challenge(&self.config, &response)?.stale;
+            if !stale || self.stale_retries >= MAX_STALE_RETRIES {
+                warn!("Authentication rejected with status code {}", response.status_code);
+                self.response = Some(response);
+                return Ok(());
+            }
+            info!("Invite nonce is stale, retrying with the new nonce");
+            self.stale_retries += 1;
+        }
 
         self.cseq = self.cseq + 1;
-        let message = add_auth_header(self.generate_invite().into(), &ConfigAuth {
-            config: &self.config,
-            realm: www_authenticate_header.realm.clone(),
-            nonce: www_authenticate_header.nonce.clone()
-        })?;
+        let invite = self.generate_invite();
+        let message = add_auth_header(invite.into(), &ConfigAuth::from_challenge(&self.config, &response)?)?;
 
         self.call_connection.send_message(message).await?;
+        self.authenticated = true;
         Ok(())
     }
 
 
+    /// Acknowledges a reliable provisional response (RFC 3262) with a PRACK.
+    ///
+    /// Responses not following the last acknowledged `RSeq`, like retransmissions, are not acknowledged again.
+    async fn send_prack(&mut self, response: &Response) -> Result<()>
+    {
+        let rseq = get_rseq(&response.headers).ok_or(anyhow!("Missing RSeq header"))?;
+        if self.last_rseq.is_some_and(|last_rseq| rseq != last_rseq.wrapping_add(1)) {
+            debug!("Ignoring reliable provisional response with RSeq {}", rseq);
+            return Ok(());
+        }
+        self.last_rseq = Some(rseq);
+
+        let cseq = self.prack_cseq.unwrap_or(self.cseq) + 1;
+        self.prack_cseq = Some(cseq);
+
+        // A new transaction in the early dialog, sent to its remote target through its route set
+        let mut headers = self.get_base_headers();
+        headers.retain(|header| !matches!(header, Header::To(_) | Header::Via(_)));
+        headers.push(self.config.get_own_via().into());
+        headers.push(response.to_header()?.clone().into());
+        headers.unique_push(CSeq::from((cseq, Method::PRack)).into());
+        headers.push(rack_header(rseq, self.cseq, Method::Invite));
+        headers.extend(get_route_headers(&get_route_set(&response.headers, true)));
+        headers.unique_push(ContentLength::from(0).into());
+
+        let prack = Request {
+            method: Method::PRack,
+            uri: get_remote_target(&response.headers).unwrap_or_else(|| self.remote_uri.clone()),
+            version: Default::default(),
+            headers,
+            body: vec![],
+        };
+        debug!("Sending PRACK for RSeq {}", rseq);
+        self.call_connection.send_message(prack.into()).await
+    }
+
+    /// Sends the INVITE again with the minimum session interval required by the remote.
+    async fn handle_invite_response_interval_too_small(&mut self, response: Response) -> Result<()>
+    {
+        let min_se = get_min_se(&response.headers).ok_or(anyhow!("Missing Min-SE header"))?;
+        info!("Session interval too small, retrying with {} seconds", min_se);
+
+        let ack = self.generate_ack(&response)?;
+        self.call_connection.send_message(ack.into()).await?;
+
+        self.min_se = self.min_se.max(min_se);
+        self.session_expires = Some(self.session_expires.unwrap_or_default().max(min_se));
+        self.cseq += 1;
+        self.send_invite().await
+    }
+
     async fn send_invite(&mut self) -> Result<()>
     {
         let request = self.generate_invite();
         self.call_connection.send_message(request.into()).await?;
+        self.authenticated = false;
         Ok(())
     }
 
+    /// Generates an INVITE starting a new transaction, with a new Via branch (RFC 3261 §8.1.1.7) shared by its ACK and CANCEL.
     fn generate_invite(&mut self) -> Request
     {
+        self.own_via = self.config.get_own_via();
+        // Reliable provisional responses are numbered per INVITE transaction
+        self.last_rseq = None;
         let body = self.local_call_session_params.sdp.to_string().into_bytes();
 
         let mut headers = self.get_base_headers();
@@ -270,6 +585,15 @@ impl OutgoingCall {
         headers.unique_push(ContentType(MediaType::Sdp(Vec::new())).into());
         headers.unique_push(CSeq::from((self.cseq, Method::Invite)).into());
         headers.unique_push(self.config.get_own_contact().into());
+        headers.push(supported_100rel_header());
+        if let Some(interval) = self.session_expires {
+            headers.push(supported_timer_header());
+            headers.push(SessionExpires { interval, refresher: None }.to_header());
+            headers.push(min_se_header(self.min_se));
+        }
+        if let Some(diversion) = self.options.diversion.as_ref() {
+            headers.push(diversion.to_header());
+        }
 
         Request {
             method: Method::Invite,
@@ -295,6 +619,24 @@ impl OutgoingCall {
         }
     }
 
+    /// Generates the ACK for a non 2xx final response, part of the invite transaction.
+    fn generate_ack(&self, response: &Response) -> Result<Request>
+    {
+        let mut headers = self.get_base_headers();
+        headers.retain(|header| !matches!(header, Header::To(_)));
+        headers.push(response.to_header()?.clone().into());
+        headers.unique_push(CSeq::from((self.cseq, Method::Ack)).into());
+        headers.unique_push(ContentLength::from(0).into());
+
+        Ok(Request {
+            method: Method::Ack,
+            uri: self.remote_uri.clone(),
+            version: Default::default(),
+            headers,
+            body: vec![],
+        })
+    }
+
     fn get_base_headers(&self) -> Headers {
         Headers::from(vec![
             MaxForwards::default().into(),
@@ -315,4 +657,154 @@ impl OutgoingCall {
             rsip::headers::UserAgent::new("sip-rs").into()
         ])
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use rsip::prelude::ToTypedHeader;
+    use std::time::Duration;
+    use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+    /// Starts an outgoing call whose messages go through channels, returning the call with its sent messages,
+    /// the sender of its received messages and the INVITE.
+    async fn start() -> (OutgoingCall, Receiver<SipMessage>, Sender<SipMessage>, Request) {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 5060));
+        let config = Config::builder().server(addr).own_addr(addr).credentials("1000", "secret").build().unwrap();
+        let mut sip_context = SipContext::from_config(config, tokio::runtime::Handle::current()).unwrap();
+        let (sent_sender, mut sent) = channel(16);
+        let (received, received_receiver) = channel(16);
+        let call_connection = CallConnection::new(sent_sender, received_receiver);
+        let uri = Uri::try_from("sip:2000@127.0.0.1:5060").unwrap();
+        let outgoing_call = OutgoingCall::try_from(&mut sip_context, call_connection, "call-id".to_string(), uri, CallOptions::default()).await.unwrap();
+        let invite = match sent.recv().await {
+            Some(SipMessage::Request(invite)) => invite,
+            message => panic!("Expected an INVITE, got {:?}", message),
+        };
+        (outgoing_call, sent, received, invite)
+    }
+
+    /// Generates the response of the remote to `request`.
+    fn generate_response(request: &Request, status_code: StatusCode, body: Vec<u8>) -> SipMessage {
+        let mut headers: Headers = request.headers.iter().filter(|header| matches!(header,
+            Header::Via(_) | Header::From(_) | Header::CallId(_) | Header::CSeq(_)
+        )).cloned().collect::<Vec<_>>().into();
+        let mut to = request.to_header().unwrap().typed().unwrap();
+        to.params.push(Param::Tag(Tag::new("remote")));
+        headers.push(to.into());
+        headers.push(ContentLength::from(body.len() as u32).into());
+        Response { status_code, version: Default::default(), headers, body }.into()
+    }
+
+    async fn recv_request(sent: &mut Receiver<SipMessage>, method: Method) -> Request {
+        match sent.recv().await {
+            Some(SipMessage::Request(request)) if request.method == method => request,
+            message => panic!("Expected a {} request, got {:?}", method, message),
+        }
+    }
+
+    fn assert_same_transaction(request: &Request, invite: &Request) {
+        assert_eq!(request.via_header().unwrap().value(), invite.via_header().unwrap().value());
+        assert_eq!(request.cseq_header().unwrap().seq().unwrap(), invite.cseq_header().unwrap().seq().unwrap());
+    }
+
+    #[tokio::test]
+    async fn cancel_while_ringing_acknowledges_487() {
+        let (outgoing_call, mut sent, received, invite) = start().await;
+        received.send(generate_response(&invite, StatusCode::Ringing, vec![])).await.unwrap();
+
+        let (cancelled, _) = tokio::join!(outgoing_call.cancel(), async {
+            let cancel = recv_request(&mut sent, Method::Cancel).await;
+            assert_same_transaction(&cancel, &invite);
+            received.send(generate_response(&cancel, StatusCode::OK, vec![])).await.unwrap();
+            received.send(generate_response(&invite, StatusCode::RequestTerminated, vec![])).await.unwrap();
+            let ack = recv_request(&mut sent, Method::Ack).await;
+            assert_same_transaction(&ack, &invite);
+        });
+        cancelled.unwrap();
+        // The 487 ends the transaction, no BYE follows
+        assert!(tokio::time::timeout(Duration::from_millis(200), sent.recv()).await.ok().flatten().is_none());
+    }
+
+    #[tokio::test]
+    async fn cancel_crossing_200_ok_hangs_up() {
+        let (outgoing_call, mut sent, received, invite) = start().await;
+        received.send(generate_response(&invite, StatusCode::Ringing, vec![])).await.unwrap();
+
+        let (cancelled, _) = tokio::join!(outgoing_call.cancel(), async {
+            // The remote answered before the CANCEL reached it, the CANCEL has no effect
+            recv_request(&mut sent, Method::Cancel).await;
+            received.send(generate_response(&invite, StatusCode::OK, invite.body.clone())).await.unwrap();
+            let ack = recv_request(&mut sent, Method::Ack).await;
+            assert_eq!(ack.cseq_header().unwrap().seq().unwrap(), invite.cseq_header().unwrap().seq().unwrap());
+            let bye = recv_request(&mut sent, Method::Bye).await;
+            assert_eq!(bye.call_id_header().unwrap().value(), "call-id");
+        });
+        cancelled.unwrap();
+    }
+
+    #[tokio::test]
+    async fn invite_retried_after_422_is_a_new_transaction() {
+        let (mut outgoing_call, mut sent, received, invite) = start().await;
+        let SipMessage::Response(mut response) = generate_response(&invite, StatusCode::SessionIntervalTooSmall, vec![]) else {
+            unreachable!()
+        };
+        response.headers.push(min_se_header(1800));
+        received.send(response.into()).await.unwrap();
+
+        let (_, retry) = tokio::join!(tokio::time::timeout(Duration::from_millis(200), outgoing_call.peek_call_response()), async {
+            let ack = recv_request(&mut sent, Method::Ack).await;
+            assert_same_transaction(&ack, &invite);
+            recv_request(&mut sent, Method::Invite).await
+        });
+        assert_ne!(retry.via_header().unwrap().value(), invite.via_header().unwrap().value());
+
+        let (cancelled, _) = tokio::join!(outgoing_call.cancel(), async {
+            let cancel = recv_request(&mut sent, Method::Cancel).await;
+            assert_same_transaction(&cancel, &retry);
+            received.send(generate_response(&cancel, StatusCode::OK, vec![])).await.unwrap();
+            received.send(generate_response(&retry, StatusCode::RequestTerminated, vec![])).await.unwrap();
+            let ack = recv_request(&mut sent, Method::Ack).await;
+            assert_same_transaction(&ack, &retry);
+        });
+        cancelled.unwrap();
+    }
+
+    #[tokio::test]
+    async fn invite_retried_after_stale_nonce_is_a_new_transaction() {
+        let (mut outgoing_call, mut sent, received, invite) = start().await;
+        let challenge = |request: &Request, stale: bool| {
+            let SipMessage::Response(mut response) = generate_response(request, StatusCode::Unauthorized, vec![]) else {
+                unreachable!()
+            };
+            response.headers.push(Header::WwwAuthenticate(
+                format!("Digest realm=\"example.com\", nonce=\"{}\", algorithm=MD5, stale={}", stale, stale).into()
+            ));
+            response
+        };
+
+        let (_, invites) = tokio::join!(tokio::time::timeout(Duration::from_millis(200), outgoing_call.peek_call_response()), async {
+            received.send(challenge(&invite, false).into()).await.unwrap();
+            recv_request(&mut sent, Method::Ack).await;
+            let authenticated = recv_request(&mut sent, Method::Invite).await;
+            received.send(challenge(&authenticated, true).into()).await.unwrap();
+            let ack = recv_request(&mut sent, Method::Ack).await;
+            assert_same_transaction(&ack, &authenticated);
+            (authenticated, recv_request(&mut sent, Method::Invite).await)
+        });
+        let (authenticated, stale_retry) = invites;
+        let branch = |request: &Request| request.via_header().unwrap().value().to_string();
+        assert_ne!(branch(&authenticated), branch(&invite));
+        assert_ne!(branch(&stale_retry), branch(&authenticated));
+    }
+
+    #[test]
+    fn diversion_reason_is_quoted_unless_a_token() {
+        let uri: Uri = "sip:1000@example.com".try_into().unwrap();
+        let header = Diversion::new(uri.clone(), "no-answer").to_header();
+        assert_eq!(header.to_string(), "Diversion: <sip:1000@example.com>;reason=no-answer");
+
+        let header = Diversion::new(uri, "away \"on\" holiday\r\nX-Injected: 1").to_header();
+        assert_eq!(header.to_string(), "Diversion: <sip:1000@example.com>;reason=\"away \\\"on\\\" holidayX-Injected: 1\"");
+    }
+}
@@ -0,0 +1,173 @@
+use crate::logging::{debug, info};
+use rtp::packet::Packet;
+
+/// Maximum amount of packets held while waiting for a missing one.
+const MAX_PENDING: usize = 3;
+/// Packets further behind are considered a restart of the sequence (RFC 3550).
+const MAX_MISORDER: i16 = 100;
+
+/// Reorders incoming RTP packets by sequence number and drops duplicates and late packets.
+///
+/// Packets are only held when one is missing, giving up on it after [MAX_PENDING] newer packets arrived.
+#[derive(Default)]
+pub struct RtpReorderBuffer {
+    ssrc: Option<u32>,
+    last_sequence_number: Option<u16>,
+    pending: Vec<Packet>,
+
+    reordered: u64,
+    dropped: u64,
+    lost: u64,
+}
+
+impl RtpReorderBuffer {
+    /// Adds a received packet, returns the packets ready to be decoded in order.
+    pub fn push(&mut self, packet: Packet) -> Vec<Packet> {
+        if self.ssrc != Some(packet.header.ssrc) {
+            // New stream, sequence numbers are not related to the previous ones
+            self.ssrc = Some(packet.header.ssrc);
+            self.last_sequence_number = None;
+            self.pending.clear();
+        }
+
+        let last = match self.last_sequence_number {
+            Some(last) => last,
+            None => {
+                self.last_sequence_number = Some(packet.header.sequence_number);
+                return vec![packet];
+            }
+        };
+
+        let distance = packet.header.sequence_number.wrapping_sub(last) as i16;
+        if distance < -MAX_MISORDER {
+            debug!("RTP sequence restarted at {}", packet.header.sequence_number);
+            self.last_sequence_number = Some(packet.header.sequence_number);
+            self.pending.clear();
+            return vec![packet];
+        }
+        if distance <= 0 || self.is_pending(packet.header.sequence_number) {
+            debug!("Dropping duplicate or late RTP packet {}", packet.header.sequence_number);
+            self.dropped += 1;
+            return Vec::new();
+        }
+
+        if distance == 1 {
+            if !self.pending.is_empty() {
+                // Fills the gap in front of the pending packets
+                self.reordered += 1;
+            }
+            self.last_sequence_number = Some(packet.header.sequence_number);
+            let mut ready = vec![packet];
+            ready.extend(self.pop_consecutive());
+            return ready;
+        }
+
+        self.pending.push(packet);
+        self.pending.sort_by_key(|p| p.header.sequence_number.wrapping_sub(last));
+
+        let mut ready = Vec::new();
+        while self.pending.len() > MAX_PENDING {
+            // Give up on the packets missing before the first pending one
+            let (Some(last), Some(first)) = (self.last_sequence_number, self.pending.first()) else {
+                break;
+            };
+            let sequence_number = first.header.sequence_number;
+            self.lost += sequence_number.wrapping_sub(last).wrapping_sub(1) as u64;
+            self.last_sequence_number = Some(sequence_number.wrapping_sub(1));
+            ready.extend(self.pop_consecutive());
+        }
+        ready
+    }
+
+    fn is_pending(&self, sequence_number: u16) -> bool {
+        self.pending.iter().any(|p| p.header.sequence_number == sequence_number)
+    }
+
+    /// Removes the pending packets directly following the last one.
+    fn pop_consecutive(&mut self) -> Vec<Packet> {
+        let mut ready = Vec::new();
+        while let Some(last) = self.last_sequence_number {
+            match self.pending.first() {
+                Some(packet) if packet.header.sequence_number == last.wrapping_add(1) => {
+                    let packet = self.pending.remove(0);
+                    self.last_sequence_number = Some(packet.header.sequence_number);
+                    ready.push(packet);
+                }
+                _ => break,
+            }
+        }
+        ready
+    }
+}
+
+impl Drop for RtpReorderBuffer {
+    fn drop(&mut self) {
+        info!(
+            "RTP receive stats: {} reordered, {} duplicate or late dropped, {} lost",
+            self.reordered, self.dropped, self.lost
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rtp::header::Header;
+
+    fn packet(ssrc: u32, sequence_number: u16) -> Packet {
+        Packet {
+            header: Header { ssrc, sequence_number, ..Default::default() },
+            payload: Default::default(),
+        }
+    }
+
+    fn push_all(buffer: &mut RtpReorderBuffer, sequence_numbers: &[u16]) -> Vec<u16> {
+        sequence_numbers.iter()
+            .flat_map(|sequence_number| buffer.push(packet(1, *sequence_number)))
+            .map(|packet| packet.header.sequence_number)
+            .collect()
+    }
+
+    #[test]
+    fn reorders_packets_arriving_out_of_order() {
+        let mut buffer = RtpReorderBuffer::default();
+        assert_eq!(push_all(&mut buffer, &[1, 3, 2, 5, 4, 6]), vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(buffer.reordered, 2);
+        assert_eq!(buffer.lost, 0);
+    }
+
+    #[test]
+    fn reorders_across_sequence_number_wraparound() {
+        let mut buffer = RtpReorderBuffer::default();
+        assert_eq!(push_all(&mut buffer, &[65534, 0, 65535, 1]), vec![65534, 65535, 0, 1]);
+    }
+
+    #[test]
+    fn drops_duplicate_and_late_packets() {
+        let mut buffer = RtpReorderBuffer::default();
+        // 3 is a duplicate of a pending packet, 2 a duplicate of a delivered one, 1 late
+        assert_eq!(push_all(&mut buffer, &[1, 2, 4, 4, 3, 2, 1]), vec![1, 2, 3, 4]);
+        assert_eq!(buffer.dropped, 3);
+    }
+
+    #[test]
+    fn gives_up_on_missing_packet_after_max_pending() {
+        let mut buffer = RtpReorderBuffer::default();
+        assert_eq!(push_all(&mut buffer, &[1, 3, 4, 5]), vec![1]);
+        assert_eq!(push_all(&mut buffer, &[6]), vec![3, 4, 5, 6]);
+        assert_eq!(buffer.lost, 1);
+
+        // Too late once given up on
+        assert!(push_all(&mut buffer, &[2]).is_empty());
+    }
+
+    #[test]
+    fn restarts_on_new_ssrc_or_large_jump_back() {
+        let mut buffer = RtpReorderBuffer::default();
+        assert_eq!(push_all(&mut buffer, &[1000, 1002]), vec![1000]);
+        assert_eq!(push_all(&mut buffer, &[10, 11]), vec![10, 11]);
+
+        let sequence_numbers = buffer.push(packet(2, 500)).into_iter().map(|packet| packet.header.sequence_number).collect::<Vec<_>>();
+        assert_eq!(sequence_numbers, vec![500]);
+    }
+}
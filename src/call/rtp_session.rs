@@ -1,89 +1,194 @@
 use anyhow::{anyhow, Result};
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::{Duration};
-use crate::media::{get_codecs_from_sdp_session, RTPCodec};
-use log::{error, info};
+use crate::media::comfort_noise::ComfortNoiseCodec;
+use crate::media::telephone_events::TelephoneEvent;
+use crate::media::{get_codec_kinds_from_sdp_session, get_codecs_from_sdp_session, get_sdp_media_ptime, rms_level, CodecInfo, RTPCodec, RtpPacketInfo, RtpStream};
+use crate::logging::{debug, error, info, warn};
+use rtp::header::Header;
 use rtp::packet::Packet;
-use tokio::net::UdpSocket;
-use tokio::time::{interval, Interval};
-use webrtc_sdp::address::ExplicitlyTypedAddress::Ip;
-use webrtc_sdp::attribute_type::{SdpAttribute, SdpAttributeType};
+use socket2::SockRef;
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::watch;
+use tokio::time::{interval, Instant, Interval, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use webrtc_sdp::address::{AddressType, ExplicitlyTypedAddress};
+use webrtc_sdp::attribute_type::SdpAttribute;
+use webrtc_sdp::media_type::SdpMedia;
+use webrtc_sdp::SdpSession;
 use webrtc_util::{Conn, Marshal, Unmarshal};
 use crate::call::session_parameters::SessionParameters;
-use crate::call::Media;
+use crate::call::{CallControl, Media, MediaDirection};
+use crate::call::frame_chunker::FrameChunker;
+use crate::call::rtp_reorder::RtpReorderBuffer;
+use crate::config::Config;
+use crate::connection::stun::get_mapped_address;
+use crate::error::SipError;
+use crate::sip_proto::sdp::{get_audio_media_index, get_media_direction};
 use crate::utils::BidirectionalChannel;
 
+/// Maximum size of a received RTP datagram, the usual ethernet MTU.
+const RTP_MAX_PACKET_SIZE: usize = 1500;
+/// Larger gaps in the received sequence numbers are a restart of the sequence rather than lost packets.
+const MAX_LOST_PACKETS: u16 = 100;
+/// Number of keep-alive packets sent to open the NAT mapping when the session starts.
+const NAT_KEEPALIVE_PACKETS: u16 = 3;
+
 pub struct RTPSession {
     audio_interval: Interval,
 
-    udp_socket: UdpSocket,
+    udp_socket: Arc<UdpSocket>,
     remote_addr: SocketAddr,
+    /// Symmetric RTP is enabled and the remote address is not learned yet.
+    latch_remote_addr: bool,
+    /// Received audio is sent with its RTP header, see [Config::rtp_packet_info].
+    rtp_packet_info: bool,
+    /// The level of the received audio is sent, see [Config::audio_level].
+    audio_level: bool,
+    /// Splits the received audio in frames of the same duration, see [Config::received_frame_duration].
+    frame_chunker: Option<FrameChunker>,
+    rtp_stream: RtpStream,
 
+    reorder_buffer: RtpReorderBuffer,
+    /// SSRC and sequence number of the last decoded packet, detecting the lost packets.
+    last_decoded: Option<(u32, u16)>,
     codecs: Vec<Box<dyn RTPCodec + Send>>,
+    comfort_noise: Option<ComfortNoiseCodec>,
+    /// Negotiated direction of the audio, nothing is sent or received outside of it.
+    direction: MediaDirection,
 
+    /// Call-ID of the dialog, prefixing the log lines.
+    call_id: String,
     media_channel: BidirectionalChannel<Media>,
+    /// Receives the new session when the call is replaced by another dialog.
+    session_update_receiver: Option<UnboundedReceiver<SessionParameters>>,
+    /// Sends [CallControl::MediaTimeout] to the call handler.
+    call_control_sender: UnboundedSender<CallControl>,
+    /// Outgoing audio samples sent by the [Call](crate::call::Call) and not encoded yet, see [Call::buffered_duration](crate::call::Call::buffered_duration).
+    buffered_len: Arc<watch::Sender<usize>>,
+    /// Sends the released DTMF digits, see [Call::recv_dtmf](crate::call::Call::recv_dtmf).
+    dtmf_sender: UnboundedSender<TelephoneEvent>,
+    /// Format of the outgoing audio, counting the buffered samples.
+    audio_channels: u8,
+    audio_sample_rate: u32,
+    rtp_timeout: Option<Duration>,
+    last_received_at: Instant,
+    timed_out: bool,
 
     notified_empty: bool,
+    notified_full: bool,
+    /// [Media::ReceivingStarted] was sent.
+    notified_receiving: bool,
+    /// [Media::SendingStarted] was sent.
+    notified_sending: bool,
+}
+
+/// Media negotiated with the remote.
+struct NegotiatedMedia {
+    remote_addr: SocketAddr,
+    codecs: Vec<Box<dyn RTPCodec + Send>>,
+    comfort_noise: Option<ComfortNoiseCodec>,
+    ptime: u64,
+    direction: MediaDirection,
 }
 
 impl RTPSession {
+    #[cfg_attr(feature = "tracing", tracing::instrument(
+        name = "rtp_setup",
+        skip_all,
+        fields(call_id = %call_session_params.call_id),
+    ))]
     pub async fn new(
         media_channel: BidirectionalChannel<Media>,
         call_session_params: SessionParameters,
+        session_update_receiver: UnboundedReceiver<SessionParameters>,
+        call_control_sender: UnboundedSender<CallControl>,
+        buffered_len: Arc<watch::Sender<usize>>,
+        dtmf_sender: UnboundedSender<TelephoneEvent>,
     ) -> Result<RTPSession> {
-        let codecs = get_codecs_from_sdp_session(&call_session_params.remote.sdp)?;
-
-        let udp_socket =
-            UdpSocket::bind(
-                SocketAddr::new(
-                    IpAddr::V4(
-                        Ipv4Addr::new(0, 0, 0, 0)
-                    ),
-                    call_session_params.local.port // TODO: Handle multiple media with multiple ports
-                )
-            ).await?;
-        let media = call_session_params.remote.sdp.media.get(0).ok_or(anyhow!("no media found"))?;
-
-        let remote_addr = if let Ip(ip) = call_session_params.remote.sdp.connection.as_ref().unwrap().address {
-            Ok(SocketAddr::new(ip, media.get_port() as u16))
-        } else {
-            Err(anyhow!("Remote rtp ip address is not valid"))
-        }?;
+        let udp_socket = call_session_params.local.rtp_socket.clone();
 
-        let ptime = media.get_attribute(SdpAttributeType::Ptime).unwrap_or(&SdpAttribute::Ptime(20));
-        let ptime = if let SdpAttribute::Ptime(ptime) = ptime {
-            *ptime
-        } else {
-            20
-        };
+        let local = &call_session_params.local;
+        let rtp_stream = RtpStream::new(local.ssrc, local.initial_sequence_number);
+        let media = negotiate_media(&call_session_params, &rtp_stream, &udp_socket).await?;
 
         Ok(RTPSession {
-            audio_interval: interval(Duration::from_millis(ptime)),
+            audio_interval: audio_interval(media.ptime),
 
             udp_socket,
-            remote_addr,
+            remote_addr: media.remote_addr,
+            latch_remote_addr: call_session_params.config.symmetric_rtp,
+            rtp_packet_info: call_session_params.config.rtp_packet_info,
+            audio_level: call_session_params.config.audio_level,
+            frame_chunker: call_session_params.config.received_frame_duration.map(|frame_duration| {
+                FrameChunker::new(frame_duration, call_session_params.config.audio_sample_rate, call_session_params.config.audio_channels)
+            }),
+            rtp_stream,
 
-            codecs,
+            reorder_buffer: RtpReorderBuffer::default(),
+            last_decoded: None,
+            codecs: media.codecs,
+            comfort_noise: media.comfort_noise,
+            direction: media.direction,
 
+            call_id: call_session_params.call_id.clone(),
             media_channel,
+            session_update_receiver: Some(session_update_receiver),
+            call_control_sender,
+            buffered_len,
+            dtmf_sender,
+            audio_channels: call_session_params.config.audio_channels,
+            audio_sample_rate: call_session_params.config.audio_sample_rate,
+            rtp_timeout: call_session_params.config.rtp_timeout,
+            last_received_at: Instant::now(),
+            timed_out: false,
             notified_empty: true,
+            notified_full: false,
+            notified_receiving: false,
+            notified_sending: false,
         })
     }
 
     pub async fn handle_next(&mut self) -> Result<()>
     {
-        let mut buff = [0; 512];
+        // One extra byte to detect datagrams truncated by recv_from
+        let mut buff = [0; RTP_MAX_PACKET_SIZE + 1];
         tokio::select! {
             _ = self.audio_interval.tick() => {
                 self.send_next_packet().await?;
+                self.play_comfort_noise()?;
+                self.poll_codecs()?;
+                self.check_rtp_timeout();
             },
             read_udp = self.udp_socket.recv_from(&mut buff) => {
                 match read_udp {
-                    Ok((len, _)) => {
+                    Ok((len, _)) if len > RTP_MAX_PACKET_SIZE => {
+                        warn!("Dropping RTP datagram larger than {} bytes", RTP_MAX_PACKET_SIZE);
+                    }
+                    Ok((len, source)) => {
                         let mut b = bytes::Bytes::from(buff[..len].to_vec());
                         let packet = Packet::unmarshal(&mut b)?;
-                        if let Some(media) = self.receive_packet(packet).await? {
-                            self.media_channel.sender.send(media)?;
+                        self.last_received_at = Instant::now();
+                        if self.latch_remote_addr {
+                            self.latch_remote_addr = false;
+                            if source != self.remote_addr {
+                                info!("Latching RTP remote address to {} instead of {}", source, self.remote_addr);
+                                self.remote_addr = source;
+                            }
+                        }
+                        // Packets are still used to latch the address when we only send
+                        if self.direction.can_receive() {
+                            if !self.notified_receiving {
+                                self.media_channel.sender.send(Media::ReceivingStarted)?;
+                                self.notified_receiving = true;
+                            }
+                            for packet in self.reorder_buffer.push(packet) {
+                                if let Some(media) = self.receive_packet(packet).await? {
+                                    self.send_media(media)?;
+                                }
+                            }
                         }
                     }
                     Err(e) => {
@@ -96,27 +201,117 @@ impl RTPSession {
                     self.receive_media(media_message).await?;
                 }
             }
+            session_update = next_session_update(&mut self.session_update_receiver) => {
+                match session_update {
+                    Some(call_session_params) => self.update_session(call_session_params).await?,
+                    None => self.session_update_receiver = None,
+                }
+            }
         }
         Ok(())
     }
 
+    /// Local address the RTP socket is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.udp_socket.local_addr()?)
+    }
+
+    /// Info of the codec used for outgoing audio.
+    pub fn get_negotiated_codec(&self) -> Option<CodecInfo> {
+        self.codecs
+            .iter()
+            .find(|codec| codec.can_handle_media(&Media::Audio(Vec::new())))
+            .map(|codec| codec.get_codec_info())
+    }
+
+    /// Switches to the media of a new session, keeping the socket and the outgoing RTP stream.
+    async fn update_session(&mut self, call_session_params: SessionParameters) -> Result<()> {
+        let media = negotiate_media(&call_session_params, &self.rtp_stream, &self.udp_socket).await?;
+        info!("RTP session updated, remote address {}", media.remote_addr);
+
+        self.call_id = call_session_params.call_id.clone();
+        self.audio_interval = audio_interval(media.ptime);
+        self.remote_addr = media.remote_addr;
+        self.latch_remote_addr = call_session_params.config.symmetric_rtp;
+        self.last_received_at = Instant::now();
+        self.reorder_buffer = RtpReorderBuffer::default();
+        self.last_decoded = None;
+        // The audio buffered by the previous codecs is dropped
+        let dropped = self.codecs.iter().map(|codec| codec.buffered_len()).sum();
+        self.release_buffered(dropped);
+        self.codecs = media.codecs;
+        self.comfort_noise = media.comfort_noise;
+        self.direction = media.direction;
+        Ok(())
+    }
+
     async fn receive_media(&mut self, media: Media) -> Result<()>
     {
+        let audio_len = media.buffered_len(self.audio_sample_rate, self.audio_channels);
         for codec in self.codecs.iter_mut() {
             if codec.can_handle_media(&media) {
-                codec.append_to_buffer(media)?;
+                let buffered_len = codec.buffered_len();
+                let fits = codec.append_to_buffer(media)?;
+                let dropped = audio_len.saturating_sub(codec.buffered_len().saturating_sub(buffered_len));
+                self.release_buffered(dropped);
+
+                if fits {
+                    self.notified_full = false;
+                } else if !self.notified_full {
+                    self.media_channel.sender.send(Media::OutputFull)?;
+                    self.notified_full = true;
+                }
                 return Ok(());
             }
         }
+        self.release_buffered(audio_len);
         Ok(())
     }
 
+    /// Removes samples encoded or dropped from the buffered audio count.
+    fn release_buffered(&self, len: usize) {
+        if len > 0 {
+            self.buffered_len.send_modify(|buffered_len| *buffered_len = buffered_len.saturating_sub(len));
+        }
+    }
+
     async fn receive_packet(&mut self, packet: Packet) -> Result<Option<Media>>
     {
+        let lost_packets = match self.last_decoded {
+            Some((ssrc, sequence_number)) if ssrc == packet.header.ssrc => {
+                packet.header.sequence_number.wrapping_sub(sequence_number).wrapping_sub(1)
+            }
+            _ => 0,
+        };
+        self.last_decoded = Some((packet.header.ssrc, packet.header.sequence_number));
+
+        if lost_packets > 0 && lost_packets < MAX_LOST_PACKETS {
+            let concealed = self.codecs.iter_mut()
+                .find(|codec| codec.get_payload_type() == packet.header.payload_type)
+                .and_then(|codec| codec.conceal_lost_packets(lost_packets));
+            if let Some(concealed) = concealed {
+                self.send_media(concealed)?;
+            }
+        }
+
         for codec in self.codecs.iter_mut() {
             if codec.get_payload_type() == packet.header.payload_type {
-                let media = codec.decode_payload(packet.payload.clone())?;
-                return Ok(media);
+                let media = codec.decode_payload(packet.payload.clone(), packet.header.timestamp)?;
+                if let (Some(Media::Audio(_)), Some(comfort_noise)) = (&media, self.comfort_noise.as_mut()) {
+                    comfort_noise.stop_noise();
+                }
+                return Ok(match media {
+                    Some(Media::Audio(audio)) if self.rtp_packet_info => Some(Media::ReceivedAudio {
+                        audio,
+                        info: RtpPacketInfo::from(&packet.header),
+                    }),
+                    media => media,
+                });
+            }
+        }
+        if let Some(comfort_noise) = self.comfort_noise.as_mut() {
+            if comfort_noise.get_payload_type() == packet.header.payload_type {
+                return comfort_noise.decode_payload(packet.payload.clone(), packet.header.timestamp);
             }
         }
         info!("Ignoring RTP Packet type {}", packet.header.payload_type);
@@ -124,16 +319,33 @@ impl RTPSession {
     }
 
     async fn send_next_packet(&mut self) -> Result<()> {
-        let mut did_send_packets = false;
-
+        let mut packets = Vec::new();
+        let mut encoded_len = 0;
         for codec in self.codecs.iter_mut() {
-            let packets = codec.get_next_packet()?;
-            if !packets.is_empty() {
-                did_send_packets = true;
+            let buffered_len = codec.buffered_len();
+            packets.extend(codec.get_next_packet()?);
+            encoded_len += buffered_len.saturating_sub(codec.buffered_len());
+        }
+        self.release_buffered(encoded_len);
+
+        let did_send_packets = !packets.is_empty();
+        if !self.direction.can_send() {
+            // The audio is still consumed so that the output buffer drains and its events are sent
+            packets.clear();
+        } else if let Some(comfort_noise) = self.comfort_noise.as_mut() {
+            if did_send_packets {
+                comfort_noise.reset_silence();
+            } else {
+                packets = comfort_noise.get_silence_packets(self.audio_interval.period())?;
             }
-            for packet in packets {
-                let b = packet.marshal()?;
-                self.udp_socket.send_to(b.iter().as_slice(), self.remote_addr).await?;
+        }
+
+        for packet in packets {
+            let b = packet.marshal()?;
+            self.udp_socket.send_to(b.iter().as_slice(), self.remote_addr).await?;
+            if !self.notified_sending {
+                self.media_channel.sender.send(Media::SendingStarted)?;
+                self.notified_sending = true;
             }
         }
 
@@ -148,6 +360,79 @@ impl RTPSession {
 
         Ok(())
     }
+
+    /// Forwards the media produced by the codecs between packets, like timed out telephone events.
+    fn poll_codecs(&mut self) -> Result<()> {
+        let media: Vec<Media> = self.codecs.iter_mut().flat_map(|codec| codec.poll_media()).collect();
+        for media in media {
+            self.send_media(media)?;
+        }
+        Ok(())
+    }
+
+    /// Sends received media to the call, and the digit of the ended telephone events to the DTMF channel.
+    fn send_media(&mut self, media: Media) -> Result<()> {
+        match media {
+            Media::Audio(audio) => return self.send_audio(audio, None),
+            Media::ReceivedAudio { audio, info } => return self.send_audio(audio, Some(info)),
+            Media::TelephoneEvent((digit, true)) => {
+                // The call might only be reading the media
+                let _ = self.dtmf_sender.send(digit);
+            }
+            _ => {}
+        }
+        self.media_channel.sender.send(media)?;
+        Ok(())
+    }
+
+    /// Sends received audio in frames of [Config::received_frame_duration] if set, preceded by their level if enabled.
+    /// The frames carry the header of the packet completing them, if any.
+    fn send_audio(&mut self, audio: Vec<f32>, info: Option<RtpPacketInfo>) -> Result<()> {
+        let frames = match self.frame_chunker.as_mut() {
+            Some(frame_chunker) => frame_chunker.push(audio),
+            None => vec![audio],
+        };
+        for audio in frames {
+            if self.audio_level {
+                self.media_channel.sender.send(Media::AudioLevel(rms_level(&audio)))?;
+            }
+            self.media_channel.sender.send(match info {
+                Some(info) => Media::ReceivedAudio { audio, info },
+                None => Media::Audio(audio),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Sets the RTP receive timeout, restarting the watchdog.
+    pub fn set_rtp_timeout(&mut self, rtp_timeout: Option<Duration>) {
+        self.rtp_timeout = rtp_timeout;
+        self.last_received_at = Instant::now();
+        self.timed_out = false;
+    }
+
+    /// Asks the call handler to hang up once no RTP was received for the configured timeout.
+    /// The remote is not expected to send while we do not receive.
+    fn check_rtp_timeout(&mut self) {
+        let Some(rtp_timeout) = self.rtp_timeout else {
+            return;
+        };
+        if !self.direction.can_receive() {
+            return;
+        }
+        if !self.timed_out && self.last_received_at.elapsed() > rtp_timeout {
+            self.timed_out = true;
+            let _ = self.call_control_sender.send(CallControl::MediaTimeout);
+        }
+    }
+
+    /// Plays noise while the remote sends comfort noise.
+    fn play_comfort_noise(&mut self) -> Result<()> {
+        if let Some(noise) = self.comfort_noise.as_ref().and_then(|cn| cn.generate_noise(self.audio_interval.period())) {
+            self.send_audio(noise, None)?;
+        }
+        Ok(())
+    }
 }
 
 impl Drop for RTPSession {
@@ -156,16 +441,202 @@ impl Drop for RTPSession {
     }
 }
 
-pub async fn rtp_task(
-    media_channel: BidirectionalChannel<Media>,
-    call_session_params: SessionParameters
-) -> Result<()> {
-    let mut session = RTPSession::new(media_channel, call_session_params).await?;
+#[cfg_attr(feature = "tracing", tracing::instrument(
+    name = "rtp",
+    skip_all,
+    fields(call_id = %session.call_id, codec = ?session.get_negotiated_codec().map(|codec| codec.name)),
+))]
+pub async fn rtp_task(mut session: RTPSession, cancellation_token: CancellationToken) -> Result<()> {
+    loop {
+        tokio::select! {
+            _ = cancellation_token.cancelled() => {
+                debug!("RTP task finished");
+                return Ok(());
+            }
+            res = session.handle_next() => {
+                if let Err(err) = res {
+                    error!("rtp session error: {:?}", err);
+                }
+            }
+        }
+    }
+}
+
+/// Marks the packets sent from the socket with the DSCP value, in the upper 6 bits of the ToS / traffic class byte.
+fn set_dscp(udp_socket: &UdpSocket, dscp: u8) -> std::io::Result<()> {
+    let socket = SockRef::from(udp_socket);
+    let tos = (dscp as u32) << 2;
+    if udp_socket.local_addr()?.is_ipv4() {
+        return socket.set_tos(tos);
+    }
+    #[cfg(any(target_os = "android", target_os = "freebsd", target_os = "linux", target_os = "macos", target_os = "netbsd", target_os = "openbsd"))]
+    return socket.set_tclass_v6(tos);
+    #[cfg(not(any(target_os = "android", target_os = "freebsd", target_os = "linux", target_os = "macos", target_os = "netbsd", target_os = "openbsd")))]
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Binds the RTP socket of a call and returns it with the address to advertise in our SDP.
+///
+/// The socket is bound on own address so that outgoing packets originate from the address in our SDP.
+/// The advertised address is discovered with STUN when [Config::stun_server] is set.
+pub async fn bind_rtp_socket(config: &Config, port: u16) -> Result<(Arc<UdpSocket>, SocketAddr)> {
+    let udp_socket = UdpSocket::bind(SocketAddr::new(config.own_addr.ip(), port)).await?;
+    if let Some(dscp) = config.rtp_dscp {
+        if let Err(e) = set_dscp(&udp_socket, dscp) {
+            warn!("Failed to set DSCP {} on RTP port {}: {:?}", dscp, port, e);
+        }
+    }
+    let advertised_addr = SocketAddr::new(config.advertised_addr().ip(), port);
 
+    let Some(stun_server) = config.stun_server else {
+        return Ok((Arc::new(udp_socket), advertised_addr));
+    };
+    match get_mapped_address(&udp_socket, stun_server).await {
+        Ok(mapped_addr) => {
+            info!("RTP port {} is mapped to {}", port, mapped_addr);
+            Ok((Arc::new(udp_socket), mapped_addr))
+        }
+        Err(e) => {
+            warn!("STUN discovery failed, advertising {}: {:?}", advertised_addr, e);
+            Ok((Arc::new(udp_socket), advertised_addr))
+        }
+    }
+}
+
+/// Runs the session before the call is answered (early media), returning it once cancelled so that the call reuses it.
+#[cfg_attr(feature = "tracing", tracing::instrument(
+    name = "early_rtp",
+    skip_all,
+    fields(call_id = %session.call_id, codec = ?session.get_negotiated_codec().map(|codec| codec.name)),
+))]
+pub async fn early_rtp_task(mut session: RTPSession, cancellation_token: CancellationToken) -> Result<RTPSession> {
     loop {
-        let res = session.handle_next().await;
-        if let Err(err) = res {
-            error!("rtp session error: {:?}", err);
+        tokio::select! {
+            _ = cancellation_token.cancelled() => return Ok(session),
+            res = session.handle_next() => {
+                if let Err(err) = res {
+                    error!("rtp session error: {:?}", err);
+                }
+            }
         }
     }
-}
\ No newline at end of file
+}
+
+/// Negotiates the media of the session with the remote, sending the NAT keep-alive packets if enabled.
+async fn negotiate_media(
+    call_session_params: &SessionParameters,
+    rtp_stream: &RtpStream,
+    udp_socket: &UdpSocket,
+) -> Result<NegotiatedMedia> {
+    // Only use the codecs we advertised, from the selected audio media
+    let allowed_codecs = get_codec_kinds_from_sdp_session(&call_session_params.local.sdp);
+    let media_index = get_audio_media_index(&call_session_params.remote.sdp, &allowed_codecs, call_session_params.config.rtp_passthrough)
+        .ok_or(SipError::NoCompatibleCodec)?;
+    let media = &call_session_params.remote.sdp.media[media_index];
+
+    let mut media_sdp = call_session_params.remote.sdp.clone();
+    media_sdp.media = vec![media.clone()];
+    let codecs = get_codecs_from_sdp_session(&media_sdp, &call_session_params.config, &allowed_codecs, rtp_stream)?;
+    let comfort_noise = ComfortNoiseCodec::try_from_sdp(&media_sdp, &call_session_params.config, rtp_stream);
+
+    let remote_addr = get_remote_addr(&call_session_params.remote.sdp, media).await?;
+    if remote_addr.is_ipv4() != udp_socket.local_addr()?.is_ipv4() {
+        return Err(anyhow!("Remote RTP address {} does not match the address family of own address", remote_addr));
+    }
+
+    if call_session_params.config.rtp_nat_keepalive {
+        send_nat_keepalive(udp_socket, remote_addr, media).await;
+    }
+
+    // Our answer already restricts our direction to the offer, an answer may restrict our offer
+    let local_direction = call_session_params.local.sdp.media.get(media_index)
+        .map(|local_media| get_media_direction(&call_session_params.local.sdp, local_media))
+        .unwrap_or(call_session_params.local.direction);
+    let direction = local_direction.intersect(get_media_direction(&call_session_params.remote.sdp, media).reverse());
+
+    // Paces the packets on the framing of the audio codec
+    let ptime = codecs.iter()
+        .find_map(|codec| codec.get_ptime())
+        .unwrap_or_else(|| get_sdp_media_ptime(media)) as u64;
+
+    Ok(NegotiatedMedia {
+        remote_addr,
+        codecs,
+        comfort_noise,
+        ptime,
+        direction,
+    })
+}
+
+/// Interval pacing the outgoing packets.
+///
+/// Missed ticks are delayed instead of sent in a burst, evenly spaced packets matter more to the remote jitter buffer than catching up.
+fn audio_interval(ptime: u64) -> Interval {
+    let mut audio_interval = interval(Duration::from_millis(ptime));
+    audio_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    audio_interval
+}
+
+/// Waits for the next session update while the call handler is running, never completes otherwise.
+async fn next_session_update(receiver: &mut Option<UnboundedReceiver<SessionParameters>>) -> Option<SessionParameters> {
+    match receiver {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Sends empty RTP packets with a payload type unused by the media (RFC 6263),
+/// so that the remote audio is not dropped by our NAT before we start sending audio.
+async fn send_nat_keepalive(udp_socket: &UdpSocket, remote_addr: SocketAddr, media: &SdpMedia) {
+    let used_payload_types: Vec<u8> = media.get_attributes().iter().filter_map(|attr| match attr {
+        SdpAttribute::Rtpmap(rtpmap) => Some(rtpmap.payload_type),
+        _ => None,
+    }).collect();
+    let Some(payload_type) = (96..=127).find(|pt| !used_payload_types.contains(pt)) else {
+        return;
+    };
+
+    let ssrc = rand::random::<u32>();
+    let sequence_start = rand::random::<u16>();
+    for i in 0..NAT_KEEPALIVE_PACKETS {
+        let packet = Packet {
+            header: Header {
+                version: 2,
+                payload_type,
+                sequence_number: sequence_start.wrapping_add(i),
+                ssrc,
+                ..Default::default()
+            },
+            payload: Default::default(),
+        };
+        let sent = match packet.marshal() {
+            Ok(b) => udp_socket.send_to(&b, remote_addr).await.map_err(anyhow::Error::from),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = sent {
+            warn!("Failed to send RTP NAT keep-alive: {}", e);
+            return;
+        }
+    }
+}
+
+/// Resolves the remote RTP address of the media, either `IN IP4` or `IN IP6`.
+///
+/// The connection line of the media takes precedence over the one of the session (RFC 4566).
+async fn get_remote_addr(sdp: &SdpSession, media: &SdpMedia) -> Result<SocketAddr> {
+    let port = media.get_port() as u16;
+    let connection = media.get_connection().as_ref()
+        .or(sdp.connection.as_ref())
+        .ok_or(anyhow!("Remote SDP has no connection address"))?;
+    match &connection.address {
+        ExplicitlyTypedAddress::Ip(ip) => Ok(SocketAddr::new(*ip, port)),
+        ExplicitlyTypedAddress::Fqdn { address_type, domain } => {
+            lookup_host((domain.as_str(), port)).await?
+                .find(|addr| match address_type {
+                    AddressType::IpV4 => addr.is_ipv4(),
+                    AddressType::IpV6 => addr.is_ipv6(),
+                })
+                .ok_or(anyhow!("Could not resolve remote RTP address {}", domain))
+        }
+    }
+}
@@ -2,28 +2,66 @@ use anyhow::{Context, Result};
 use rsip::headers::{ContentLength, MaxForwards};
 use rsip::param::Tag;
 use rsip::prelude::*;
-use rsip::{Header, Headers, Request, Response, Uri};
+use rsip::{Header, Headers, Method, Request, Response, StatusCode, Uri};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::runtime::Handle;
 use uuid::Uuid;
-use webrtc_sdp::{parse_sdp, SdpSession};
+use webrtc_sdp::SdpSession;
 
+use crate::call::identity::CallerIdentity;
+use crate::call::MediaDirection;
 use crate::config::Config;
+use crate::sip_proto::session_timer::{get_session_expires, SessionTimer};
 use crate::context::SipContext;
-use crate::sip_proto::get_allow_header;
-use crate::sip_proto::sdp::generate_sdp_new;
+use crate::sip_proto::{get_allow_header, get_allowed_methods, RequestError};
+use crate::media::{get_codec_kinds_from_sdp_session, CodecKind};
+use crate::sip_proto::multipart::parse_sdp_body;
+use crate::sip_proto::route::{get_record_route_headers, get_remote_target, get_route_headers, get_route_set};
+use crate::sip_proto::sdp::generate_sdp_answer;
 
 #[derive(Clone)]
 pub struct LocalSessionParameters {
     pub uri: Uri,
+    /// Our tag in the dialog, generated once with the parameters and never regenerated:
+    /// every request and response of the dialog, provisional or final, carries this same tag.
     pub tag: String,
     pub sdp: SdpSession,
-    pub port: u16,
+    /// RTP socket of the call, bound before generating our SDP.
+    pub rtp_socket: Arc<UdpSocket>,
+    /// Address advertised for the RTP socket in our SDP.
+    pub rtp_addr: SocketAddr,
+    /// SSRC of the outgoing RTP stream.
+    pub ssrc: u32,
+    /// Sequence number of the first outgoing RTP packet.
+    pub initial_sequence_number: u16,
+    /// Runtime the call and RTP tasks are spawned on.
+    pub runtime: Handle,
+    /// Direction of the audio we want for the call, our SDP may restrict it further to what the remote offered.
+    pub direction: MediaDirection,
 }
 
 #[derive(Clone)]
 pub struct RemoteSessionParameters {
     pub uri: Uri,
+    /// Remote target of the dialog, the URI of the remote `Contact` (RFC 3261 12.2.1.1), Request-URI of our in-dialog requests.
+    pub target: Uri,
+    /// Identity of the remote, asserted by the network or taken from `uri`.
+    pub identity: CallerIdentity,
     pub tag: String,
     pub sdp: SdpSession,
+    /// Route set of the dialog recorded by the proxies, followed by our in-dialog requests.
+    pub route_set: Vec<String>,
+    /// Methods advertised in the `Allow` header of the INVITE or 200 OK, `None` when the remote sent none.
+    pub allow: Option<Vec<Method>>,
+}
+
+impl RemoteSessionParameters {
+    /// Returns whether the remote supports `method`, assumed when it did not advertise its methods.
+    pub fn supports(&self, method: &Method) -> bool {
+        self.allow.as_ref().is_none_or(|allow| allow.contains(method))
+    }
 }
 
 #[derive(Clone)]
@@ -34,12 +72,57 @@ pub struct SessionParameters
 
     pub remote: RemoteSessionParameters,
     pub local: LocalSessionParameters,
+    /// Session timer of the dialog, `None` if the session does not expire.
+    pub session_timer: Option<SessionTimer>,
 
     pub config: Config,
 }
 
 impl SessionParameters {
-    pub fn from_request(context: &mut SipContext, request: &Request) -> Result<Self> {
+    pub async fn from_request(context: &mut SipContext, request: &Request) -> Result<Self> {
+        // Busy rather than broken, the remote can retry later or elsewhere
+        let (rtp_socket, rtp_addr) = context.bind_next_rtp_socket().await
+            .map_err(|e| RequestError::new(StatusCode::ServiceUnavailable, format!("No RTP socket for the call: {}", e)))?;
+        Self::from_request_with_socket(
+            &context.config,
+            request,
+            rtp_socket,
+            rtp_addr,
+            &CodecKind::enabled(),
+            MediaDirection::default(),
+            context.runtime.clone(),
+        )
+    }
+
+    /// Parameters of the dialog created by an INVITE replacing this one.
+    ///
+    /// The RTP socket and stream are kept so that media continues, the answer only advertises the codecs we previously advertised
+    /// and keeps our direction.
+    pub fn replaced_by(&self, request: &Request) -> Result<Self> {
+        let codecs = get_codec_kinds_from_sdp_session(&self.local.sdp);
+        let mut params = Self::from_request_with_socket(
+            &self.config,
+            request,
+            self.local.rtp_socket.clone(),
+            self.local.rtp_addr,
+            &codecs,
+            self.local.direction,
+            self.local.runtime.clone(),
+        )?;
+        params.local.ssrc = self.local.ssrc;
+        params.local.initial_sequence_number = self.local.initial_sequence_number;
+        Ok(params)
+    }
+
+    fn from_request_with_socket(
+        config: &Config,
+        request: &Request,
+        rtp_socket: Arc<UdpSocket>,
+        rtp_addr: SocketAddr,
+        codecs: &[CodecKind],
+        direction: MediaDirection,
+        runtime: Handle,
+    ) -> Result<Self> {
         let from = request.headers.iter().find_map(|i| {
             if let Header::From(from) = i {
                 let typed = from.clone().into_typed().unwrap();
@@ -49,30 +132,39 @@ impl SessionParameters {
         }).context("Remote uri not found")?;
         let call_id = request.call_id_header()?.value().to_string();
 
-        let body = String::from_utf8(request.body().clone())?;
         let remote_uri = from.uri.clone();
-        let remote_sdp = parse_sdp(body.as_str(), false)?;
+        let remote_sdp = parse_sdp_body(&request.headers, request.body())?;
         let remote_tag = from.tag().context("Remote tag not found")?.value().to_string();
 
-        let local_port = context.get_next_udp_port();
+        let local_sdp = generate_sdp_answer(config, rtp_addr, codecs, &remote_sdp, direction)?;
 
         Ok(Self {
             cseq: request.cseq_header()?.seq()?,
             call_id,
 
             remote: RemoteSessionParameters {
+                identity: CallerIdentity::from_headers(&request.headers, from.display_name.clone(), &remote_uri),
+                target: get_remote_target(&request.headers).unwrap_or_else(|| remote_uri.clone()),
                 uri: remote_uri,
                 tag: remote_tag,
                 sdp: remote_sdp,
+                route_set: get_route_set(&request.headers, false),
+                allow: get_allowed_methods(&request.headers),
             },
             local: LocalSessionParameters {
-                uri: context.config.get_own_uri(),
+                uri: config.get_own_uri(),
                 tag: format!("tt{}", Uuid::new_v4()),
-                sdp: generate_sdp_new(&context.config, local_port)?,
-                port: local_port,
+                sdp: local_sdp,
+                rtp_socket,
+                rtp_addr,
+                ssrc: rand::random(),
+                initial_sequence_number: rand::random(),
+                runtime,
+                direction,
             },
+            session_timer: SessionTimer::from_request(&request.headers)?,
 
-            config: context.config.clone(),
+            config: config.clone(),
         })
     }
 
@@ -91,20 +183,25 @@ impl SessionParameters {
         }).context("Remote uri not found")?;
         let remote_tag = to.tag().context("To tag not found")?.value().to_string();
 
-        let body = String::from_utf8(response.body().clone())?;
-        let remote_sdp = parse_sdp(body.as_str(), false)?;
+        let remote_sdp = parse_sdp_body(&response.headers, response.body())?;
 
         let cseq = response.cseq_header()?.seq()?;
+        let session_timer = get_session_expires(&response.headers)?.map(SessionTimer::from_response);
 
         Ok(Self {
             cseq,
             call_id,
             remote: RemoteSessionParameters {
+                identity: CallerIdentity::from_headers(&response.headers, to.display_name.clone(), &to.uri),
+                target: get_remote_target(&response.headers).unwrap_or_else(|| to.uri.clone()),
                 uri: to.uri,
                 tag: remote_tag,
                 sdp: remote_sdp,
+                route_set: get_route_set(&response.headers, true),
+                allow: get_allowed_methods(&response.headers),
             },
             local,
+            session_timer,
             config,
         })
     }
@@ -114,7 +211,7 @@ impl SessionParameters {
         let mut params = Vec::new();
         params.push(rsip::Param::Tag(Tag::new(&self.remote.tag)));
 
-        let headers: Vec<Header> = vec![
+        let mut headers: Vec<Header> = vec![
             self.config.get_own_via().into(),
             MaxForwards::default().into(),
             rsip::headers::CallId::from(self.call_id.clone()).into(),
@@ -134,6 +231,7 @@ impl SessionParameters {
             ContentLength::default().into(),
             rsip::headers::UserAgent::new("sip-rs").into()
         ];
+        headers.extend(get_route_headers(&self.remote.route_set));
 
         rsip::Headers::from(headers)
     }
@@ -143,10 +241,13 @@ impl SessionParameters {
         let mut params = Vec::new();
         params.push(rsip::Param::Tag(Tag::new(&self.remote.tag)));
 
-        let headers: Vec<Header> = vec![
-            get_allow_header().into(),
+        let mut headers: Vec<Header> = vec![
+            get_allow_header(&self.config).into(),
             MaxForwards::default().into(),
-            request.via_header().unwrap().clone().into(),
+        ];
+        // Every Via of the request is returned so that the response goes back through the proxies
+        headers.extend(request.headers.iter().filter(|header| matches!(header, Header::Via(_))).cloned());
+        headers.extend([
             rsip::headers::CallId::from(self.call_id.clone()).into(),
             rsip::typed::From {
                 display_name: None,
@@ -163,11 +264,25 @@ impl SessionParameters {
             request.cseq_header().unwrap().typed().unwrap().into(),
             ContentLength::default().into(),
             rsip::headers::UserAgent::new("sip-rs").into()
-        ];
+        ]);
+        headers.extend(get_record_route_headers(request));
 
         rsip::Headers::from(headers)
     }
 
+    /// Updates the remote target from the `Contact` of a target refresh request, or of the response to ours (RFC 3261 12.2),
+    /// kept when there is none.
+    pub fn update_remote_target(&mut self, headers: &Headers) {
+        if let Some(target) = get_remote_target(headers) {
+            self.remote.target = target;
+        }
+    }
+
+    /// Skips the CSeq numbers already used in the dialog before it was established, by PRACK requests.
+    pub fn skip_cseq_to(&mut self, cseq: u32) {
+        self.cseq = self.cseq.max(cseq);
+    }
+
     pub fn get_next_cseq(&mut self) -> u32 {
         self.cseq += 1;
         self.cseq
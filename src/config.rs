@@ -1,44 +1,263 @@
-use rsip::param::OtherParam;
+use rsip::param::{OtherParam, OtherParamValue};
 use rsip::typed::{Contact, Via};
 use rsip::Transport::Tcp;
 use rsip::{HostWithPort, Scheme, Uri, Version};
+use std::fmt::{Debug, Display, Formatter};
 use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::time::Duration;
 use uuid::Uuid;
+use crate::sip_proto::session_timer::MIN_SE;
 
+/// Sample rates supported for [Config::audio_sample_rate], the rates Opus encodes and decodes natively.
+pub const AUDIO_SAMPLE_RATES: [u32; 5] = [8000, 12000, 16000, 24000, 48000];
+
+/// Bitrates accepted by the Opus encoder for [OpusConfig::bitrate], in bits per second.
+pub const OPUS_BITRATES: RangeInclusive<i32> = 500..=512000;
+
+/// Highest value of [Config::rtp_dscp], the DSCP being the upper 6 bits of the ToS / traffic class byte.
+const MAX_DSCP: u8 = 63;
+
+/// Opus encoder settings, only used when the `opus` feature is enabled.
+#[derive(Clone, Debug)]
+pub struct OpusConfig {
+    /// Target bitrate in bits per second, in [OPUS_BITRATES], also advertised as `maxaveragebitrate`.
+    /// `None` to let the encoder decide.
+    pub bitrate: Option<i32>,
+    /// Enables inband forward error correction.
+    pub inband_fec: bool,
+    /// Asks the remote to use discontinuous transmission (`usedtx`) during silence.
+    pub use_dtx: bool,
+    /// Number of channels we prefer to receive and send, 1 or 2, advertised with the `stereo` and `sprop-stereo` fmtp parameters.
+    /// Stereo is only sent when the remote also advertises `stereo=1`.
+    /// The rtpmap always advertises 2 channels as required by RFC 7587.
+    pub channels: u8,
+}
+
+impl Default for OpusConfig {
+    fn default() -> Self {
+        Self {
+            bitrate: None,
+            inband_fec: true,
+            use_dtx: false,
+            channels: 1,
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Config {
     /// SIP Server address with port
     pub server_addr: SocketAddr,
-    /// Address used to be reached for RTP session, usually the current IP
+    /// Address used to be reached for RTP session, usually the current IP.
+    /// RTP sockets are bound to this IP.
     pub own_addr: SocketAddr,
+    /// Public address advertised to the remote instead of `own_addr` in the SDP, Contact and Via headers,
+    /// when behind a NAT forwarding the SIP and RTP ports to `own_addr`. Sockets are still bound to `own_addr`.
+    pub public_addr: Option<SocketAddr>,
 
     /// SIP Username
     pub username: String,
-    /// SIP Password
+    /// SIP Password, leave empty for servers not requiring authentication (trusted networks).
     pub password: String,
+    /// User part of the From URI of our calls when it differs from the [username](Config::username), e.g. an alias number
+    /// for the caller ID. Registration and authentication keep the username.
+    pub from_user: Option<String>,
+    /// User part of the Contact URI of our calls, the [username](Config::username) if not set.
+    pub contact_user: Option<String>,
 
-    /// Start of the RTP port range
+    /// Start of the RTP port range.
+    /// Ports already bound, by another call or another [SipManager](crate::manager::SipManager) using an overlapping range, are skipped.
     pub rtp_port_start: u16,
     /// End of the RTP port range, must be > to `rtp_port_start`
     pub rtp_port_end: u16,
+
+    /// Registration lifetime in seconds advertised in the `Expires` header of REGISTER requests.
+    /// The registration is refreshed before it expires.
+    pub register_expires: u32,
+    /// Interval at which a CRLF keep-alive ping is sent on the SIP connection, `None` to disable.
+    /// Useful to keep NAT mappings open when the connection is otherwise idle.
+    pub keepalive_interval: Option<Duration>,
+
+    /// Maximum duration of outgoing audio buffered per call, audio sent beyond it is dropped.
+    /// `None` for no limit.
+    pub max_audio_buffer: Option<Duration>,
+    /// Number of channels of the audio exchanged with a [Call](crate::call::Call), 1 for mono or 2 for interleaved stereo.
+    pub audio_channels: u8,
+    /// Instance ID advertised in our Contact with the `+sip.instance` parameter (RFC 5626), identifying this user agent
+    /// across restarts. Required by registrars assigning GRUUs.
+    ///
+    /// Should be a stable URN, ex: `urn:uuid:f81d4fae-7dec-11d0-a765-00a0c91e6bf6`.
+    pub sip_instance: Option<String>,
+    /// Capacity of the queue of SIP messages received for each call, waiting to be handled by the call.
+    /// Reading from the SIP connection pauses while a queue is full.
+    pub call_channel_capacity: usize,
+    /// Capacity of the queue of SIP messages waiting to be sent on the SIP connection.
+    pub message_channel_capacity: usize,
+    /// Sample rate in Hz of the audio exchanged with a [Call](crate::call::Call), one of 8000, 12000, 16000, 24000 or 48000.
+    ///
+    /// Codecs running at this rate skip resampling, ex: 8000 for PCMU / PCMA only applications.
+    pub audio_sample_rate: u32,
+    /// Username of the origin (`o=` line) of our SDP, without spaces.
+    pub sdp_username: String,
+    /// Session name (`s=` line) of our SDP.
+    pub sdp_session_name: String,
+    /// Opus encoder settings
+    pub opus: OpusConfig,
+    /// Advertises comfort noise (RFC 3389), sent during outgoing silence and played during incoming silence.
+    pub comfort_noise: bool,
+    /// Sends a few RTP packets to the remote as soon as the RTP session starts, so that our NAT
+    /// has a mapping for the incoming audio before we send any.
+    pub rtp_nat_keepalive: bool,
+    /// Symmetric RTP, sends the audio to the address the remote RTP comes from instead of the address in its SDP.
+    /// Fixes one-way audio with remotes behind a NAT.
+    pub symmetric_rtp: bool,
+    /// DSCP value marked on the outgoing RTP packets for QoS, e.g. 46 (Expedited Forwarding) for voice, `None` to keep the system default.
+    pub rtp_dscp: Option<u8>,
+    /// Receives the decoded audio as [Media::ReceivedAudio](crate::call::Media::ReceivedAudio), with the header of its RTP packet,
+    /// instead of [Media::Audio](crate::call::Media::Audio).
+    pub rtp_packet_info: bool,
+    /// Sends the level of each received audio frame as [Media::AudioLevel](crate::call::Media::AudioLevel), before the frame.
+    pub audio_level: bool,
+    /// Duration of the received audio frames, the decoded audio being split or joined whatever the packet duration of the remote,
+    /// ex: 20ms frames while the remote sends 60ms packets. `None` to receive the audio of each packet as decoded.
+    pub received_frame_duration: Option<Duration>,
+    /// Answers the offered audio codecs the crate can not decode, their payloads are then received and sent as is
+    /// in [Media::RawPayload](crate::call::Media::RawPayload), ex: to relay or record them.
+    /// Our offers still only advertise the codecs of the crate.
+    pub rtp_passthrough: bool,
+    /// Hangs up the call when no RTP was received for this duration, `None` to disable.
+    pub rtp_timeout: Option<Duration>,
+    /// Hangs up calls lasting longer than this duration since they were answered, `None` for no limit.
+    /// See [Call::set_max_duration](crate::call::Call::set_max_duration) to change it per call.
+    pub max_call_duration: Option<Duration>,
+    /// Session interval in seconds requested in our INVITEs (RFC 4028 session timers), `None` to not request one.
+    /// The session is then refreshed with a re-INVITE, by us or the remote, so that the call is not dropped by the server.
+    pub session_expires: Option<u32>,
+    /// Supports the UPDATE method (RFC 3311), advertised in our `Allow` header.
+    /// Incoming UPDATE requests are rejected otherwise, and sessions are then refreshed with UPDATE instead of re-INVITE.
+    pub allow_update: bool,
+    /// Answers incoming INVITEs with `180 Ringing` as soon as they are received.
+    /// Otherwise no provisional response is sent until [IncomingCall::ring](crate::call::incoming_call::IncomingCall::ring)
+    /// or [IncomingCall::progress](crate::call::incoming_call::IncomingCall::progress), e.g. to answer without ringing.
+    pub auto_ringing: bool,
+    /// Maximum number of calls at once, incoming or outgoing, ringing or answered. Incoming INVITEs over it are rejected
+    /// with `486 Busy Here` without being reported as an [IncomingCall](crate::call::incoming_call::IncomingCall)
+    /// ("busy on busy"), `None` for no limit.
+    pub max_concurrent_calls: Option<usize>,
+    /// STUN server used to discover the public address of the RTP socket of each call, advertised in our SDP.
+    /// Falls back to the [advertised address](Config::advertised_addr) when the server does not answer.
+    pub stun_server: Option<SocketAddr>,
 }
 
 impl Config {
-    pub fn get_own_uri(&self) -> Uri {
-        Uri {
-            scheme: Some(Scheme::Sip),
-            auth: Some((self.username.clone(), Option::<String>::None).into()),
-            host_with_port: HostWithPort::from(self.own_addr),
-            ..Default::default()
+    /// Returns a [ConfigBuilder] to build a validated [Config].
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Returns `true` if a password is configured to answer authentication challenges.
+    pub(crate) fn has_credentials(&self) -> bool {
+        !self.password.is_empty()
+    }
+
+    /// Address advertised to the remote, [public_addr](Config::public_addr) if set, [own_addr](Config::own_addr) otherwise.
+    pub fn advertised_addr(&self) -> SocketAddr {
+        self.public_addr.unwrap_or(self.own_addr)
+    }
+
+    /// Checks that the configuration is usable.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.username.is_empty() {
+            return Err(ConfigError::MissingUsername);
+        }
+        if self.advertised_addr().ip().is_unspecified() {
+            return Err(ConfigError::UnspecifiedOwnAddress);
+        }
+        if self.rtp_port_start == 0 || self.rtp_port_start >= self.rtp_port_end {
+            return Err(ConfigError::InvalidRtpPortRange(self.rtp_port_start, self.rtp_port_end));
+        }
+        if self.register_expires == 0 {
+            return Err(ConfigError::InvalidRegisterExpires);
+        }
+        if !matches!(self.audio_channels, 1 | 2) {
+            return Err(ConfigError::InvalidAudioChannels(self.audio_channels));
+        }
+        if self.call_channel_capacity == 0 || self.message_channel_capacity == 0 {
+            return Err(ConfigError::InvalidChannelCapacity);
+        }
+        if self.sdp_username.is_empty() || self.sdp_username.contains(char::is_whitespace)
+            || self.sdp_session_name.is_empty() || self.sdp_session_name.contains(['\r', '\n']) {
+            return Err(ConfigError::InvalidSdpOrigin);
+        }
+        if let Some(dscp) = self.rtp_dscp {
+            if dscp > MAX_DSCP {
+                return Err(ConfigError::InvalidDscp(dscp));
+            }
         }
+        if self.received_frame_duration.is_some_and(|duration| duration.as_millis() == 0) {
+            return Err(ConfigError::InvalidReceivedFrameDuration);
+        }
+        if !AUDIO_SAMPLE_RATES.contains(&self.audio_sample_rate) {
+            return Err(ConfigError::InvalidAudioSampleRate(self.audio_sample_rate));
+        }
+        if !matches!(self.opus.channels, 1 | 2) {
+            return Err(ConfigError::InvalidAudioChannels(self.opus.channels));
+        }
+        if let Some(bitrate) = self.opus.bitrate {
+            if !OPUS_BITRATES.contains(&bitrate) {
+                return Err(ConfigError::InvalidOpusBitrate(bitrate));
+            }
+        }
+        if let Some(session_expires) = self.session_expires {
+            if session_expires < MIN_SE {
+                return Err(ConfigError::InvalidSessionExpires(session_expires));
+            }
+        }
+        Ok(())
+    }
+
+    /// Our URI in the From header of our calls, using [from_user](Config::from_user) if set.
+    pub fn get_own_uri(&self) -> Uri {
+        self.get_uri(self.from_user.as_ref().unwrap_or(&self.username))
     }
 
+    /// Our Contact in calls, using [contact_user](Config::contact_user) if set.
     pub fn get_own_contact(&self) -> Contact {
+        self.get_contact(self.contact_user.as_ref().unwrap_or(&self.username))
+    }
+
+    /// Our Contact in registrations.
+    pub(crate) fn get_register_contact(&self) -> Contact {
+        self.get_contact(&self.username)
+    }
+
+    /// Contact with the transport we use and our [sip_instance](Config::sip_instance) if set.
+    fn get_contact(&self, user: &str) -> Contact {
+        let mut uri = self.get_uri(user);
+        uri.params.push(rsip::Param::Transport(Tcp));
+
+        let mut params = vec![];
+        if let Some(sip_instance) = self.sip_instance.as_ref() {
+            params.push(rsip::Param::Other(
+                OtherParam::new("+sip.instance"),
+                Some(OtherParamValue::new(format!("\"<{}>\"", sip_instance))),
+            ));
+        }
+
         Contact {
             display_name: None,
-            uri: self.get_own_uri(),
-            params: vec![],
+            uri,
+            params,
+        }
+    }
+
+    fn get_uri(&self, user: &str) -> Uri {
+        Uri {
+            scheme: Some(Scheme::Sip),
+            auth: Some((user.to_string(), Option::<String>::None).into()),
+            host_with_port: HostWithPort::from(self.advertised_addr()),
+            ..Default::default()
         }
     }
 
@@ -47,7 +266,7 @@ impl Config {
             version: Version::V2,
             transport: Tcp,
             uri: Uri {
-                host_with_port: HostWithPort::from(self.own_addr),
+                host_with_port: HostWithPort::from(self.advertised_addr()),
                 ..Default::default()
             },
             params: vec![
@@ -56,4 +275,452 @@ impl Config {
             ],
         }
     }
-}
\ No newline at end of file
+}
+
+/// Error returned when a [Config] is invalid.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// No SIP server address was provided.
+    MissingServerAddress,
+    /// No own address was provided.
+    MissingOwnAddress,
+    /// The advertised address is unspecified (e.g. `0.0.0.0`) and cannot be advertised to the remote.
+    /// The own address may only be unspecified when a public address is set.
+    UnspecifiedOwnAddress,
+    /// The username is empty.
+    MissingUsername,
+    /// The RTP port range starts at 0, or its end is not above its start.
+    InvalidRtpPortRange(u16, u16),
+    /// The registration lifetime is 0.
+    InvalidRegisterExpires,
+    /// The amount of audio channels is neither 1 or 2.
+    InvalidAudioChannels(u8),
+    /// The audio sample rate is not one of the rates supported by Opus.
+    InvalidAudioSampleRate(u32),
+    /// A channel capacity is 0.
+    InvalidChannelCapacity,
+    /// The SDP username is empty or contains spaces, or the SDP session name is empty or contains line breaks.
+    InvalidSdpOrigin,
+    /// The received frame duration is shorter than a millisecond.
+    InvalidReceivedFrameDuration,
+    /// The DSCP value does not fit in 6 bits.
+    InvalidDscp(u8),
+    /// The session interval is below the minimum of 90 seconds.
+    InvalidSessionExpires(u32),
+    /// The Opus bitrate is outside of [OPUS_BITRATES].
+    InvalidOpusBitrate(i32),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::MissingServerAddress => write!(f, "SIP server address is missing"),
+            ConfigError::MissingOwnAddress => write!(f, "Own address is missing"),
+            ConfigError::UnspecifiedOwnAddress => write!(f, "Own address must not be unspecified"),
+            ConfigError::MissingUsername => write!(f, "Username is missing"),
+            ConfigError::InvalidRtpPortRange(start, end) => write!(f, "Invalid RTP port range {}-{}, the start must be above 0 and below the end", start, end),
+            ConfigError::InvalidRegisterExpires => write!(f, "Register expires must be greater than 0"),
+            ConfigError::InvalidAudioChannels(channels) => write!(f, "Audio channels must be either 1 or 2, got {}", channels),
+            ConfigError::InvalidAudioSampleRate(sample_rate) => write!(f, "Audio sample rate must be one of {:?}, got {}", AUDIO_SAMPLE_RATES, sample_rate),
+            ConfigError::InvalidChannelCapacity => write!(f, "Channel capacities must be greater than 0"),
+            ConfigError::InvalidSdpOrigin => write!(f, "SDP username must not be empty or contain spaces, SDP session name must not be empty"),
+            ConfigError::InvalidReceivedFrameDuration => write!(f, "Received frame duration must be at least 1ms"),
+            ConfigError::InvalidDscp(dscp) => write!(f, "DSCP must be at most {}, got {}", MAX_DSCP, dscp),
+            ConfigError::InvalidSessionExpires(session_expires) => write!(f, "Session expires must be at least {} seconds, got {}", MIN_SE, session_expires),
+            ConfigError::InvalidOpusBitrate(bitrate) => write!(f, "Opus bitrate must be in {:?} bits per second, got {}", OPUS_BITRATES, bitrate),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Builder for [Config], validated when calling [build](ConfigBuilder::build).
+///
+/// # Examples
+/// ```
+///  use std::net::SocketAddr;
+///  use std::str::FromStr;
+///  use simple_sip_rs::config::Config;
+///
+///  let config = Config::builder()
+///     .server(SocketAddr::from_str("192.168.1.100:5060").unwrap())
+///     .own_addr(SocketAddr::from_str("192.168.1.2:5060").unwrap())
+///     .credentials("username", "password")
+///     .rtp_ports(20480, 20490)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone)]
+pub struct ConfigBuilder {
+    server_addr: Option<SocketAddr>,
+    own_addr: Option<SocketAddr>,
+    public_addr: Option<SocketAddr>,
+    username: String,
+    password: String,
+    from_user: Option<String>,
+    contact_user: Option<String>,
+    rtp_port_start: u16,
+    rtp_port_end: u16,
+    register_expires: u32,
+    keepalive_interval: Option<Duration>,
+    max_audio_buffer: Option<Duration>,
+    audio_channels: u8,
+    audio_sample_rate: u32,
+    call_channel_capacity: usize,
+    message_channel_capacity: usize,
+    sip_instance: Option<String>,
+    sdp_username: String,
+    sdp_session_name: String,
+    opus: OpusConfig,
+    comfort_noise: bool,
+    rtp_nat_keepalive: bool,
+    symmetric_rtp: bool,
+    rtp_dscp: Option<u8>,
+    rtp_packet_info: bool,
+    audio_level: bool,
+    received_frame_duration: Option<Duration>,
+    rtp_passthrough: bool,
+    rtp_timeout: Option<Duration>,
+    max_call_duration: Option<Duration>,
+    session_expires: Option<u32>,
+    allow_update: bool,
+    auto_ringing: bool,
+    max_concurrent_calls: Option<usize>,
+    stun_server: Option<SocketAddr>,
+}
+
+/// Redacts the password.
+impl Debug for ConfigBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConfigBuilder")
+            .field("server_addr", &self.server_addr)
+            .field("own_addr", &self.own_addr)
+            .field("public_addr", &self.public_addr)
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .field("from_user", &self.from_user)
+            .field("contact_user", &self.contact_user)
+            .field("rtp_port_start", &self.rtp_port_start)
+            .field("rtp_port_end", &self.rtp_port_end)
+            .field("register_expires", &self.register_expires)
+            .field("keepalive_interval", &self.keepalive_interval)
+            .field("max_audio_buffer", &self.max_audio_buffer)
+            .field("audio_channels", &self.audio_channels)
+            .field("audio_sample_rate", &self.audio_sample_rate)
+            .field("call_channel_capacity", &self.call_channel_capacity)
+            .field("message_channel_capacity", &self.message_channel_capacity)
+            .field("sip_instance", &self.sip_instance)
+            .field("sdp_username", &self.sdp_username)
+            .field("sdp_session_name", &self.sdp_session_name)
+            .field("opus", &self.opus)
+            .field("comfort_noise", &self.comfort_noise)
+            .field("rtp_nat_keepalive", &self.rtp_nat_keepalive)
+            .field("symmetric_rtp", &self.symmetric_rtp)
+            .field("rtp_dscp", &self.rtp_dscp)
+            .field("rtp_packet_info", &self.rtp_packet_info)
+            .field("audio_level", &self.audio_level)
+            .field("received_frame_duration", &self.received_frame_duration)
+            .field("rtp_passthrough", &self.rtp_passthrough)
+            .field("rtp_timeout", &self.rtp_timeout)
+            .field("max_call_duration", &self.max_call_duration)
+            .field("session_expires", &self.session_expires)
+            .field("allow_update", &self.allow_update)
+            .field("auto_ringing", &self.auto_ringing)
+            .field("max_concurrent_calls", &self.max_concurrent_calls)
+            .field("stun_server", &self.stun_server)
+            .finish()
+    }
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        Self {
+            server_addr: None,
+            own_addr: None,
+            public_addr: None,
+            username: String::new(),
+            password: String::new(),
+            from_user: None,
+            contact_user: None,
+            rtp_port_start: 20480,
+            rtp_port_end: 20580,
+            register_expires: 3600,
+            keepalive_interval: None,
+            max_audio_buffer: Some(Duration::from_secs(1)),
+            audio_channels: 2,
+            audio_sample_rate: 48000,
+            call_channel_capacity: 32,
+            message_channel_capacity: 64,
+            sip_instance: None,
+            sdp_username: "Z".to_string(),
+            sdp_session_name: "Z".to_string(),
+            opus: OpusConfig::default(),
+            comfort_noise: false,
+            rtp_nat_keepalive: false,
+            symmetric_rtp: false,
+            rtp_dscp: None,
+            rtp_packet_info: false,
+            audio_level: false,
+            received_frame_duration: None,
+            rtp_passthrough: false,
+            rtp_timeout: None,
+            max_call_duration: None,
+            session_expires: None,
+            allow_update: false,
+            auto_ringing: true,
+            max_concurrent_calls: None,
+            stun_server: None,
+        }
+    }
+}
+
+impl ConfigBuilder {
+    /// SIP Server address with port
+    pub fn server(mut self, server_addr: SocketAddr) -> Self {
+        self.server_addr = Some(server_addr);
+        self
+    }
+
+    /// Address used to be reached for RTP session, see [Config::own_addr].
+    pub fn own_addr(mut self, own_addr: SocketAddr) -> Self {
+        self.own_addr = Some(own_addr);
+        self
+    }
+
+    /// Public address advertised to the remote when behind a NAT, see [Config::public_addr].
+    pub fn public_addr(mut self, public_addr: SocketAddr) -> Self {
+        self.public_addr = Some(public_addr);
+        self
+    }
+
+    /// SIP Username, for servers not requiring authentication. See [credentials](ConfigBuilder::credentials) otherwise.
+    pub fn username(mut self, username: impl Into<String>) -> Self {
+        self.username = username.into();
+        self
+    }
+
+    /// SIP Username and password
+    pub fn credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.username = username.into();
+        self.password = password.into();
+        self
+    }
+
+    /// User part of the From URI of our calls, the username by default. See [Config::from_user].
+    pub fn from_user(mut self, from_user: impl Into<String>) -> Self {
+        self.from_user = Some(from_user.into());
+        self
+    }
+
+    /// User part of the Contact URI of our calls, the username by default. See [Config::contact_user].
+    pub fn contact_user(mut self, contact_user: impl Into<String>) -> Self {
+        self.contact_user = Some(contact_user.into());
+        self
+    }
+
+    /// RTP port range, defaults to 20480-20580.
+    pub fn rtp_ports(mut self, start: u16, end: u16) -> Self {
+        self.rtp_port_start = start;
+        self.rtp_port_end = end;
+        self
+    }
+
+    /// Registration lifetime in seconds, defaults to 3600.
+    pub fn register_expires(mut self, register_expires: u32) -> Self {
+        self.register_expires = register_expires;
+        self
+    }
+
+    /// Keep-alive interval on the SIP connection, disabled by default. See [Config::keepalive_interval].
+    pub fn keepalive_interval(mut self, keepalive_interval: Option<Duration>) -> Self {
+        self.keepalive_interval = keepalive_interval;
+        self
+    }
+
+    /// Maximum duration of outgoing audio buffered per call, defaults to 1 second.
+    pub fn max_audio_buffer(mut self, max_audio_buffer: Option<Duration>) -> Self {
+        self.max_audio_buffer = max_audio_buffer;
+        self
+    }
+
+    /// Number of audio channels exchanged with a call, defaults to 2.
+    pub fn audio_channels(mut self, audio_channels: u8) -> Self {
+        self.audio_channels = audio_channels;
+        self
+    }
+
+    /// Sample rate of the audio exchanged with a call, defaults to 48000. See [Config::audio_sample_rate].
+    pub fn audio_sample_rate(mut self, audio_sample_rate: u32) -> Self {
+        self.audio_sample_rate = audio_sample_rate;
+        self
+    }
+
+    /// Capacity of the queue of SIP messages of each call, defaults to 32. See [Config::call_channel_capacity].
+    pub fn call_channel_capacity(mut self, call_channel_capacity: usize) -> Self {
+        self.call_channel_capacity = call_channel_capacity;
+        self
+    }
+
+    /// Capacity of the queue of SIP messages to send, defaults to 64. See [Config::message_channel_capacity].
+    pub fn message_channel_capacity(mut self, message_channel_capacity: usize) -> Self {
+        self.message_channel_capacity = message_channel_capacity;
+        self
+    }
+
+    /// Instance ID advertised in our Contact, disabled by default. See [Config::sip_instance].
+    pub fn sip_instance(mut self, sip_instance: Option<String>) -> Self {
+        self.sip_instance = sip_instance;
+        self
+    }
+
+    /// Username of the origin of our SDP, defaults to `"Z"`. See [Config::sdp_username].
+    pub fn sdp_username(mut self, sdp_username: impl Into<String>) -> Self {
+        self.sdp_username = sdp_username.into();
+        self
+    }
+
+    /// Session name of our SDP, defaults to `"Z"`. See [Config::sdp_session_name].
+    pub fn sdp_session_name(mut self, sdp_session_name: impl Into<String>) -> Self {
+        self.sdp_session_name = sdp_session_name.into();
+        self
+    }
+
+    /// Opus encoder settings
+    pub fn opus(mut self, opus: OpusConfig) -> Self {
+        self.opus = opus;
+        self
+    }
+
+    /// Enables comfort noise, disabled by default.
+    pub fn comfort_noise(mut self, comfort_noise: bool) -> Self {
+        self.comfort_noise = comfort_noise;
+        self
+    }
+
+    /// Sends NAT keep-alive RTP packets when a call starts, disabled by default. See [Config::rtp_nat_keepalive].
+    pub fn rtp_nat_keepalive(mut self, rtp_nat_keepalive: bool) -> Self {
+        self.rtp_nat_keepalive = rtp_nat_keepalive;
+        self
+    }
+
+    /// Enables symmetric RTP, disabled by default.
+    pub fn symmetric_rtp(mut self, symmetric_rtp: bool) -> Self {
+        self.symmetric_rtp = symmetric_rtp;
+        self
+    }
+
+    /// DSCP value of the outgoing RTP packets, system default by default. See [Config::rtp_dscp].
+    pub fn rtp_dscp(mut self, rtp_dscp: Option<u8>) -> Self {
+        self.rtp_dscp = rtp_dscp;
+        self
+    }
+
+    /// Attaches the RTP header to the received audio, disabled by default. See [Config::rtp_packet_info].
+    pub fn rtp_packet_info(mut self, rtp_packet_info: bool) -> Self {
+        self.rtp_packet_info = rtp_packet_info;
+        self
+    }
+
+    /// Sends the level of the received audio, disabled by default. See [Config::audio_level].
+    pub fn audio_level(mut self, audio_level: bool) -> Self {
+        self.audio_level = audio_level;
+        self
+    }
+
+    /// Duration of the received audio frames, as decoded by default. See [Config::received_frame_duration].
+    pub fn received_frame_duration(mut self, received_frame_duration: Option<Duration>) -> Self {
+        self.received_frame_duration = received_frame_duration;
+        self
+    }
+
+    /// Passes the payloads of the codecs the crate can not decode through, disabled by default. See [Config::rtp_passthrough].
+    pub fn rtp_passthrough(mut self, rtp_passthrough: bool) -> Self {
+        self.rtp_passthrough = rtp_passthrough;
+        self
+    }
+
+    /// RTP receive timeout after which the call is hung up, disabled by default.
+    pub fn rtp_timeout(mut self, rtp_timeout: Option<Duration>) -> Self {
+        self.rtp_timeout = rtp_timeout;
+        self
+    }
+
+    /// Maximum duration of the calls, unlimited by default. See [Config::max_call_duration].
+    pub fn max_call_duration(mut self, max_call_duration: Option<Duration>) -> Self {
+        self.max_call_duration = max_call_duration;
+        self
+    }
+
+    /// Session interval requested in our INVITEs in seconds, disabled by default. See [Config::session_expires].
+    pub fn session_expires(mut self, session_expires: Option<u32>) -> Self {
+        self.session_expires = session_expires;
+        self
+    }
+
+    /// Supports the UPDATE method, disabled by default. See [Config::allow_update].
+    pub fn allow_update(mut self, allow_update: bool) -> Self {
+        self.allow_update = allow_update;
+        self
+    }
+
+    /// Sends `180 Ringing` on incoming INVITEs, enabled by default. See [Config::auto_ringing].
+    pub fn auto_ringing(mut self, auto_ringing: bool) -> Self {
+        self.auto_ringing = auto_ringing;
+        self
+    }
+
+    /// Maximum number of calls at once, unlimited by default. See [Config::max_concurrent_calls].
+    pub fn max_concurrent_calls(mut self, max_concurrent_calls: Option<usize>) -> Self {
+        self.max_concurrent_calls = max_concurrent_calls;
+        self
+    }
+
+    /// STUN server used to discover the public RTP address, see [Config::stun_server].
+    pub fn stun_server(mut self, stun_server: SocketAddr) -> Self {
+        self.stun_server = Some(stun_server);
+        self
+    }
+
+    /// Validates and builds the [Config].
+    pub fn build(self) -> Result<Config, ConfigError> {
+        let config = Config {
+            server_addr: self.server_addr.ok_or(ConfigError::MissingServerAddress)?,
+            own_addr: self.own_addr.ok_or(ConfigError::MissingOwnAddress)?,
+            public_addr: self.public_addr,
+            username: self.username,
+            password: self.password,
+            from_user: self.from_user,
+            contact_user: self.contact_user,
+            rtp_port_start: self.rtp_port_start,
+            rtp_port_end: self.rtp_port_end,
+            register_expires: self.register_expires,
+            keepalive_interval: self.keepalive_interval,
+            max_audio_buffer: self.max_audio_buffer,
+            audio_channels: self.audio_channels,
+            audio_sample_rate: self.audio_sample_rate,
+            call_channel_capacity: self.call_channel_capacity,
+            message_channel_capacity: self.message_channel_capacity,
+            sip_instance: self.sip_instance,
+            sdp_username: self.sdp_username,
+            sdp_session_name: self.sdp_session_name,
+            opus: self.opus,
+            comfort_noise: self.comfort_noise,
+            rtp_nat_keepalive: self.rtp_nat_keepalive,
+            symmetric_rtp: self.symmetric_rtp,
+            rtp_dscp: self.rtp_dscp,
+            rtp_packet_info: self.rtp_packet_info,
+            audio_level: self.audio_level,
+            received_frame_duration: self.received_frame_duration,
+            rtp_passthrough: self.rtp_passthrough,
+            rtp_timeout: self.rtp_timeout,
+            max_call_duration: self.max_call_duration,
+            session_expires: self.session_expires,
+            allow_update: self.allow_update,
+            auto_ringing: self.auto_ringing,
+            max_concurrent_calls: self.max_concurrent_calls,
+            stun_server: self.stun_server,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
@@ -1,3 +1,4 @@
 pub mod call_connection;
 pub mod sip_socket;
-pub mod socket_data;
\ No newline at end of file
+pub mod socket_data;
+pub mod stun;
\ No newline at end of file
@@ -1,24 +1,38 @@
 use crate::call::incoming_call::IncomingCall;
 use crate::connection::call_connection::CallConnection;
 use crate::context::SipContext;
+use crate::sip_proto::{generate_error_response, generate_response};
 use crate::sip_proto::options::generate_options_response;
-use crate::sip_proto::register::{add_auth_header, generate_register_request, ConfigAuth};
+use crate::sip_proto::register::{add_auth_header, generate_register_request, ConfigAuth, MAX_STALE_RETRIES};
+use crate::sip_proto::replaces::{get_replaces, Replaces};
 use anyhow::{anyhow, Result};
-use log::{error, info, warn};
-use rsip::headers::ToTypedHeader;
+use crate::logging::{error, info, warn};
 use rsip::prelude::{HeadersExt, UntypedHeader};
-use rsip::{Method, Request, SipMessage, StatusCode};
+use rsip::{Method, Request, Response, SipMessage, StatusCode};
 use std::ops::DerefMut;
 use std::sync::Arc;
+use std::time::Duration;
 use futures_util::StreamExt;
 use tokio::io::{AsyncWriteExt};
 use tokio::net::{TcpStream, ToSocketAddrs};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio::sync::Mutex;
+use tokio::sync::{oneshot, watch, Mutex};
+use tokio::time::{interval_at, timeout, Instant, Interval};
 use tokio_util::codec::FramedRead;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
 use crate::connection::socket_data::SocketData;
+use crate::error::SipError;
+use crate::observer::{MessageDirection, MessageObserver};
 use crate::sip_proto::sip_message_decoder::SipMessageDecoder;
+use crate::utils::tick_optional;
+
+/// Time given to the server to answer the unregistration when stopping.
+const UNREGISTER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Notified with the outcome of the next registration, see [SipManager::reregister](crate::manager::SipManager::reregister).
+pub(crate) type RegisterWaiter = oneshot::Sender<Result<(), SipError>>;
 
 pub struct SipSocket {
     sip_message_reader: FramedRead<OwnedReadHalf, SipMessageDecoder>,
@@ -30,6 +44,23 @@ pub struct SipSocket {
 
     sip_context: Arc<Mutex<SipContext>>,
     socket_data: Arc<Mutex<SocketData>>,
+
+    register_call_id: String,
+    register_cseq: u32,
+    register_authenticated: bool,
+    /// Stale challenges answered since the last successful registration.
+    register_stale_retries: u32,
+    unregistering: bool,
+    register_interval: Interval,
+    /// Receives the requests to register immediately.
+    reregister_receiver: Receiver<RegisterWaiter>,
+    reregister_sender: Sender<RegisterWaiter>,
+    /// Waiting for the response to the REGISTER in progress.
+    register_waiters: Vec<RegisterWaiter>,
+    keepalive_interval: Option<Interval>,
+    message_observer: Option<Arc<dyn MessageObserver>>,
+    /// Registration state shared with the [SipManager](crate::manager::SipManager).
+    registered: Arc<watch::Sender<bool>>,
 }
 
 impl SipSocket {
@@ -37,10 +68,17 @@ impl SipSocket {
         addr: A,
         sip_context: Arc<Mutex<SipContext>>,
         incoming_call_sender: Sender<IncomingCall>,
+        message_observer: Option<Arc<dyn MessageObserver>>,
+        registered: Arc<watch::Sender<bool>>,
     ) -> Result<Self> {
         let stream = TcpStream::connect(addr).await?;
         let (stream_read, stream_write) = stream.into_split();
-        let (sender, receiver) = channel(64);
+        let (register_expires, keepalive_interval, message_channel_capacity, call_channel_capacity) = {
+            let config = &sip_context.lock().await.config;
+            (config.register_expires, config.keepalive_interval, config.message_channel_capacity, config.call_channel_capacity)
+        };
+        let (sender, receiver) = channel(message_channel_capacity);
+        let (reregister_sender, reregister_receiver) = channel(1);
 
         let mut instance = Self {
             sip_message_reader: FramedRead::new(stream_read, SipMessageDecoder::new()),
@@ -51,29 +89,58 @@ impl SipSocket {
             incoming_call_sender,
 
             sip_context,
-            socket_data: Arc::new(Mutex::new(SocketData::default())),
+            socket_data: Arc::new(Mutex::new(SocketData::new(call_channel_capacity))),
+
+            register_call_id: Uuid::new_v4().to_string(),
+            register_cseq: 0,
+            register_authenticated: false,
+            register_stale_retries: 0,
+            unregistering: false,
+            register_interval: get_register_interval(register_expires),
+            reregister_receiver,
+            reregister_sender,
+            register_waiters: Vec::new(),
+            keepalive_interval: keepalive_interval.map(|period| interval_at(Instant::now() + period, period)),
+            message_observer,
+            registered,
         };
 
         instance.register().await?;
         Ok(instance)
     }
 
-    pub async fn run(&mut self) -> Result<()> {
+    /// Handles SIP messages until the token is cancelled, then unregisters.
+    #[cfg_attr(feature = "tracing", tracing::instrument(
+        name = "sip_socket",
+        skip_all,
+        fields(local_addr = ?self.stream_write.local_addr().ok(), server_addr = ?self.stream_write.peer_addr().ok()),
+    ))]
+    pub async fn run(&mut self, cancellation_token: CancellationToken) -> Result<()> {
         loop {
             tokio::select! {
+                _ = cancellation_token.cancelled() => {
+                    return self.unregister().await;
+                }
                 read = self.sip_message_reader.next() => {
-                    if let Some(message) = read {
-                        match message {
-                            Ok(message) => {
-                                if self.handle_call_message(&message).await {
-                                    continue;
-                                }
-                                self.handle_message(message).await?;
+                    match read {
+                        Some(Ok(message)) => {
+                            self.observe(MessageDirection::Inbound, &message);
+                            if self.handle_call_message(&message).await {
+                                continue;
                             }
-                            Err(e) => {
-                                error!("SIP message read error: {:?}", e);
+                            if let Err(e) = self.handle_message(message).await {
+                                // Only a broken connection ends the loop, not a message we could not handle
+                                if matches!(e.downcast_ref::<SipError>(), Some(SipError::Transport(_))) {
+                                    return Err(e);
+                                }
+                                error!("Failed to handle SIP message: {:?}", e);
                             }
                         }
+                        Some(Err(e)) => {
+                            error!("SIP message read error: {:?}", e);
+                        }
+                        // Also reached after a read error, the stream can not be parsed further
+                        None => return Err(SipError::Transport(anyhow!("Connection closed by the server")).into()),
                     }
                 }
                 message = self.message_receiver.recv() => {
@@ -82,6 +149,22 @@ impl SipSocket {
                         Some(message) => self.send_message(message).await?,
                     }
                 }
+                _ = self.register_interval.tick() => {
+                    if let Err(e) = self.refresh_registration().await {
+                        error!("Failed to refresh registration: {:?}", e);
+                    }
+                }
+                Some(waiter) = self.reregister_receiver.recv() => {
+                    self.register_waiters.push(waiter);
+                    if let Err(e) = self.refresh_registration().await {
+                        error!("Failed to register: {:?}", e);
+                        let message = e.to_string();
+                        self.notify_register_waiters(|| Err(SipError::Transport(anyhow!("{}", message))));
+                    }
+                }
+                _ = tick_optional(&mut self.keepalive_interval) => {
+                    self.send_keepalive().await?;
+                }
             }
         }
     }
@@ -94,86 +177,225 @@ impl SipSocket {
         self.message_sender.clone()
     }
 
+    pub(crate) fn get_reregister_sender(&self) -> Sender<RegisterWaiter> {
+        self.reregister_sender.clone()
+    }
+
     async fn register(&mut self) -> Result<()> {
         info!("Registering SIP");
 
-        let config = self.sip_context.lock().await.config.clone();
-
-        let req = generate_register_request(&config);
-        self.send_message(req.clone().into()).await?;
+        let req = self.generate_register_request().await;
+        self.send_message(req.clone()).await?;
         info!("Sent SIP REGISTER request");
 
-        let response = self.read_next_message().await?;
-        info!("Received SIP REGISTER response");
+        loop {
+            let SipMessage::Response(response) = self.read_next_message().await? else {
+                return Err(anyhow!("Did not get expected response"));
+            };
+            info!("Received SIP REGISTER response");
 
-        if let SipMessage::Response(response) = response {
             match response.status_code {
-                StatusCode::Unauthorized => {
-                    let www_authenticate_header = response
-                        .www_authenticate_header()
-                        .unwrap()
-                        .clone()
-                        .into_typed()?;
-
-                    let register_auth_payload = ConfigAuth {
-                        config: &config,
-                        realm: www_authenticate_header.realm,
-                        nonce: www_authenticate_header.nonce,
-                    };
-
-                    let mut req = add_auth_header(req, &register_auth_payload)?;
-                    req.cseq_header_mut()?.mut_seq(2)?;
-
-                    self.send_message(req.into()).await?;
-                    let response = self.read_next_message().await?;
-
-                    if let SipMessage::Response(response) = response {
-                        if response.status_code == StatusCode::OK {
-                            info!("Successfully registered");
-                            return Ok(());
-                        }
-                        return Err(anyhow!(
-                            "Failed to register with status code: {}",
-                            response.status_code
-                        ));
-                    }
-
-                    Err(anyhow!("Did not get expected response"))
+                StatusCode::Unauthorized | StatusCode::ProxyAuthenticationRequired if !self.sip_context.lock().await.config.has_credentials() => {
+                    return Err(SipError::Auth("Server requires authentication but no password is configured".to_string()).into());
+                }
+                StatusCode::Unauthorized | StatusCode::ProxyAuthenticationRequired if self.should_answer_challenge(&response).await => {
+                    let req = self.generate_authenticated_register_request(&response).await?;
+                    self.send_message(req).await?;
                 }
                 StatusCode::OK => {
                     info!("Successfully registered");
-                    Ok(())
+                    self.register_stale_retries = 0;
+                    self.registered.send_replace(true);
+                    self.reset_register_interval(&response).await;
+                    return Ok(());
                 }
-                _ => Err(anyhow!(
+                StatusCode::Unauthorized | StatusCode::ProxyAuthenticationRequired | StatusCode::Forbidden => {
+                    return Err(SipError::Auth(format!(
+                        "Failed to register with status code: {}",
+                        response.status_code
+                    )).into());
+                }
+                _ => return Err(anyhow!(
                     "Got unexpected status code {}",
                     response.status_code
                 )),
             }
-        } else {
-            Err(anyhow!("Did not get expected response"))
         }
     }
 
+    /// Returns `true` if the challenge should be answered: our request was not authenticated,
+    /// or the nonce we used is stale and the retries are not exhausted.
+    async fn should_answer_challenge(&mut self, response: &Response) -> bool {
+        if !self.register_authenticated {
+            return true;
+        }
+        let config = self.sip_context.lock().await.config.clone();
+        let stale = ConfigAuth::from_challenge(&config, response).is_ok_and(|auth| auth.stale);
+        if stale && self.register_stale_retries < MAX_STALE_RETRIES {
+            info!("Registration nonce is stale, retrying with the new nonce");
+            self.register_stale_retries += 1;
+            return true;
+        }
+        false
+    }
+
+    async fn refresh_registration(&mut self) -> Result<()> {
+        info!("Refreshing SIP registration");
+        let req = self.generate_register_request().await;
+        self.send_message(req).await
+    }
+
+    async fn unregister(&mut self) -> Result<()> {
+        // Flush the messages queued by the calls, like BYE requests
+        while let Ok(message) = self.message_receiver.try_recv() {
+            self.send_message(message).await?;
+        }
+
+        info!("Unregistering SIP");
+        self.unregistering = true;
+        self.registered.send_replace(false);
+        let req = self.generate_register_request().await;
+        self.send_message(req).await?;
+        timeout(UNREGISTER_TIMEOUT, self.wait_unregister_response()).await?
+    }
+
+    async fn wait_unregister_response(&mut self) -> Result<()> {
+        loop {
+            let SipMessage::Response(response) = self.read_next_message().await? else {
+                continue;
+            };
+            if response.cseq_header()?.method()? != Method::Register {
+                continue;
+            }
+            match response.status_code {
+                StatusCode::Unauthorized | StatusCode::ProxyAuthenticationRequired if self.should_answer_challenge(&response).await => {
+                    let req = self.generate_authenticated_register_request(&response).await?;
+                    self.send_message(req).await?;
+                }
+                StatusCode::OK => {
+                    info!("Successfully unregistered");
+                    return Ok(());
+                }
+                StatusCode::Trying => {}
+                _ => return Err(anyhow!("Failed to unregister with status code: {}", response.status_code)),
+            }
+        }
+    }
+
+    async fn handle_register_response(&mut self, response: Response) -> Result<()> {
+        match response.status_code {
+            StatusCode::Unauthorized | StatusCode::ProxyAuthenticationRequired
+                if self.sip_context.lock().await.config.has_credentials() && self.should_answer_challenge(&response).await => {
+                let req = self.generate_authenticated_register_request(&response).await?;
+                self.send_message(req).await?;
+            }
+            StatusCode::OK => {
+                info!("Successfully refreshed registration");
+                self.register_stale_retries = 0;
+                self.registered.send_replace(true);
+                self.reset_register_interval(&response).await;
+                self.notify_register_waiters(|| Ok(()));
+            }
+            StatusCode::Trying => {}
+            _ => {
+                error!("Failed to refresh registration with status code: {}", response.status_code);
+                self.registered.send_replace(false);
+                let message = format!("Failed to register with status code: {}", response.status_code);
+                let is_auth = matches!(
+                    response.status_code,
+                    StatusCode::Unauthorized | StatusCode::ProxyAuthenticationRequired | StatusCode::Forbidden
+                );
+                self.notify_register_waiters(|| Err(if is_auth {
+                    SipError::Auth(message.clone())
+                } else {
+                    SipError::Protocol(anyhow!("{}", message))
+                }));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the outcome of the registration to the [reregister](crate::manager::SipManager::reregister) callers waiting for it.
+    fn notify_register_waiters(&mut self, result: impl Fn() -> Result<(), SipError>) {
+        for waiter in self.register_waiters.drain(..) {
+            let _ = waiter.send(result());
+        }
+    }
+
+    async fn generate_register_request(&mut self) -> SipMessage {
+        self.register_cseq += 1;
+        self.register_authenticated = false;
+        let config = self.sip_context.lock().await.config.clone();
+        let expires = if self.unregistering { 0 } else { config.register_expires };
+        generate_register_request(&config, &self.register_call_id, self.register_cseq, expires)
+    }
+
+    async fn generate_authenticated_register_request(&mut self, response: &Response) -> Result<SipMessage> {
+        let req = self.generate_register_request().await;
+        let config = self.sip_context.lock().await.config.clone();
+        let register_auth_payload = ConfigAuth::from_challenge(&config, response)?;
+
+        self.register_authenticated = true;
+        add_auth_header(req, &register_auth_payload)
+    }
+
+    async fn reset_register_interval(&mut self, response: &Response) {
+        let expires = match response.expires_header().map(|expires| expires.seconds()) {
+            Some(Ok(expires)) if expires > 0 => expires,
+            _ => self.sip_context.lock().await.config.register_expires,
+        };
+        self.register_interval = get_register_interval(expires);
+    }
+
     async fn send_message(&mut self, message: SipMessage) -> Result<()> {
+        self.observe(MessageDirection::Outbound, &message);
         self.stream_write
             .write_all(message.to_string().as_bytes())
-            .await?;
+            .await
+            .map_err(|e| SipError::Transport(e.into()))?;
+        Ok(())
+    }
+
+    fn observe(&self, direction: MessageDirection, message: &SipMessage) {
+        if let Some(message_observer) = &self.message_observer {
+            message_observer.on_message(direction, message);
+        }
+    }
+
+    async fn send_keepalive(&mut self) -> Result<()> {
+        self.stream_write.write_all(b"\r\n\r\n").await?;
         Ok(())
     }
 
     async fn read_next_message(&mut self) -> Result<SipMessage> {
-        loop {
-            if let Some(message) = self.sip_message_reader.next().await {
-                return Ok(message?)
+        match self.sip_message_reader.next().await {
+            Some(message) => {
+                let message = message?;
+                self.observe(MessageDirection::Inbound, &message);
+                Ok(message)
             }
+            None => Err(SipError::Transport(anyhow!("Connection closed by the server")).into()),
         }
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(
+        name = "sip_message",
+        skip_all,
+        fields(call_id = message.call_id_header().ok().map(|call_id| call_id.value())),
+    ))]
     async fn handle_message(&mut self, message: SipMessage) -> Result<()> {
         match message {
-            SipMessage::Request(request) => self.handle_sip_request(request).await?,
+            SipMessage::Request(request) => {
+                if let Err(e) = self.handle_sip_request(request.clone()).await {
+                    self.answer_request_error(&request, e).await?;
+                }
+            }
             SipMessage::Response(response) => {
-                warn!("Ignored SIP response {:?}", response);
+                if response.cseq_header()?.method()? == Method::Register {
+                    self.handle_register_response(response).await?;
+                } else {
+                    warn!("Ignored SIP response {:?}", response);
+                }
             }
         }
         Ok(())
@@ -187,13 +409,31 @@ impl SipSocket {
                 self.send_message(response).await?;
             }
             Method::Invite => {
+                match get_replaces(&request) {
+                    Ok(Some(replaces)) => return self.route_replacing_invite(request, replaces).await,
+                    Ok(None) => {}
+                    Err(e) => {
+                        warn!("Invalid Replaces header: {:?}", e);
+                        let response = generate_response(&request, StatusCode::BadRequest)?;
+                        return self.send_message(response.into()).await;
+                    }
+                }
+
                 let call_id = request.call_id_header()?.value().to_string();
+                let max_concurrent_calls = self.sip_context.lock().await.config.max_concurrent_calls;
+                let active_calls = self.socket_data.lock().await.active_call_count();
+                if max_concurrent_calls.is_some_and(|max| active_calls >= max) {
+                    info!("Maximum number of calls reached, rejecting the INVITE with 486 Busy Here");
+                    let response = generate_response(&request, StatusCode::BusyHere)?;
+                    return self.send_message(response.into()).await;
+                }
+
                 let call_connection = CallConnection::new(
                     self.message_sender.clone(),
                     self.socket_data
                         .lock()
                         .await
-                        .create_call_channel(call_id)
+                        .create_call_channel(call_id.clone())
                         .await?,
                 );
                 let call = IncomingCall::try_from_request(
@@ -201,7 +441,15 @@ impl SipSocket {
                     request,
                     call_connection,
                 )
-                .await?;
+                .await;
+                let call = match call {
+                    Ok(call) => call,
+                    Err(e) => {
+                        // The INVITE is answered with an error, no call is created
+                        self.socket_data.lock().await.call_channels.remove(&call_id);
+                        return Err(e);
+                    }
+                };
                 self.incoming_call_sender.send(call).await?;
             }
             _ => {
@@ -211,15 +459,62 @@ impl SipSocket {
         Ok(())
     }
 
+    /// Answers a request that could not be handled with an error response, see [generate_error_response].
+    /// Transport errors are returned as they are, the connection can not answer anymore.
+    async fn answer_request_error(&mut self, request: &Request, err: anyhow::Error) -> Result<()> {
+        if matches!(err.downcast_ref::<SipError>(), Some(SipError::Transport(_))) {
+            return Err(err);
+        }
+        warn!("Failed to handle SIP {} request: {:?}", request.method, err);
+        // An ACK is never answered
+        if request.method == Method::Ack {
+            return Ok(());
+        }
+        let response = generate_error_response(request, &err)?;
+        self.send_message(response.into()).await
+    }
+
+    /// Hands an INVITE with a `Replaces` header to the call it replaces, which takes over the new dialog.
+    async fn route_replacing_invite(&mut self, request: Request, replaces: Replaces) -> Result<()> {
+        let call_id = request.call_id_header()?.value().to_string();
+        let channel = {
+            let mut socket_data = self.socket_data.lock().await;
+            let channel = socket_data.call_channels.get(&replaces.call_id).filter(|channel| !channel.is_closed()).cloned();
+            if let Some(channel) = &channel {
+                socket_data.call_channels.insert(call_id.clone(), channel.clone());
+            }
+            channel
+        };
+
+        if let Some(channel) = channel {
+            if channel.send(request.clone().into()).await.is_ok() {
+                return Ok(());
+            }
+            // The call ended in between
+            self.socket_data.lock().await.call_channels.remove(&call_id);
+        }
+        // No dialog matches the Replaces header (RFC 3891 §3)
+        warn!("No call to replace with Call-ID {}", replaces.call_id);
+        let response = generate_response(&request, StatusCode::CallTransactionDoesNotExist)?;
+        self.send_message(response.into()).await
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(
+        name = "sip_message",
+        skip_all,
+        fields(call_id = message.call_id_header().ok().map(|call_id| call_id.value())),
+    ))]
     async fn handle_call_message(&mut self, message: &SipMessage) -> bool {
         if let Ok(call_id) = message.call_id_header() {
             let id = call_id.value().to_string();
-            let mut socket_data = self.socket_data.lock().await;
+            // Not locked while sending, a full channel must not prevent new calls from being created
+            let channel = self.socket_data.lock().await.call_channels.get(&id).cloned();
 
-            if let Some(channel) = socket_data.call_channels.get_mut(&id) {
+            if let Some(channel) = channel {
+                // Only fails once the call is over, a full channel waits for the call to catch up
                 if channel.send(message.clone()).await.is_err() {
-                    warn!("Sent to call channel failed, dropping");
-                    socket_data.call_channels.remove(&id);
+                    warn!("Call channel closed, dropping");
+                    self.socket_data.lock().await.call_channels.remove(&id);
                 }
                 return true;
             }
@@ -227,3 +522,16 @@ impl SipSocket {
         false
     }
 }
+
+impl Drop for SipSocket {
+    fn drop(&mut self) {
+        // The connection is gone, whether unregistered or not
+        self.registered.send_replace(false);
+    }
+}
+
+/// Refresh the registration halfway through its lifetime so it never lapses.
+fn get_register_interval(expires: u32) -> Interval {
+    let period = Duration::from_secs((expires as u64 / 2).max(1));
+    interval_at(Instant::now() + period, period)
+}
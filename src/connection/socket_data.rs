@@ -7,19 +7,38 @@ use tokio::sync::mpsc;
 
 // type WaitedIncomingMap = HashMap<String, oneshot::Sender<SipMessage>>;
 
-#[derive(Default)]
 pub struct SocketData {
     pub call_channels: HashMap<String, Sender<SipMessage>>,
+    /// Capacity of each call channel, see [Config::call_channel_capacity](crate::config::Config::call_channel_capacity).
+    call_channel_capacity: usize,
 }
 
 impl SocketData {
+    pub fn new(call_channel_capacity: usize) -> Self {
+        Self {
+            call_channels: HashMap::new(),
+            call_channel_capacity,
+        }
+    }
+
     pub async fn create_call_channel(&mut self, call_id: String) -> anyhow::Result<Receiver<SipMessage>>
     {
         if self.call_channels.contains_key(&call_id) {
             return Err(anyhow!("A channel for this call id already exists: {}", call_id));
         }
-        let (tx, rx) = mpsc::channel(32);
+        let (tx, rx) = mpsc::channel(self.call_channel_capacity);
         self.call_channels.insert(call_id, tx);
         Ok(rx)
     }
+
+    /// Returns the number of calls not over yet, a call taking over another dialog (`Replaces`) counting once.
+    pub fn active_call_count(&self) -> usize {
+        let mut channels: Vec<&Sender<SipMessage>> = Vec::new();
+        for channel in self.call_channels.values().filter(|channel| !channel.is_closed()) {
+            if !channels.iter().any(|counted| counted.same_channel(channel)) {
+                channels.push(channel);
+            }
+        }
+        channels.len()
+    }
 }
\ No newline at end of file
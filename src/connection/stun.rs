@@ -0,0 +1,116 @@
+use anyhow::{anyhow, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::{timeout_at, Instant};
+
+/// STUN magic cookie (RFC 5389).
+const MAGIC_COOKIE: u32 = 0x2112A442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS_RESPONSE: u16 = 0x0101;
+const ATTRIBUTE_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTRIBUTE_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const HEADER_SIZE: usize = 20;
+/// Initial retransmission timeout, doubled after each attempt.
+const INITIAL_RTO: Duration = Duration::from_millis(500);
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Queries the STUN server for the public address of the socket, as seen from outside of our NAT.
+///
+/// The binding request is retransmitted over UDP until a response arrives or the attempts are exhausted.
+pub async fn get_mapped_address(udp_socket: &UdpSocket, stun_server: SocketAddr) -> Result<SocketAddr> {
+    let transaction_id: [u8; 12] = rand::random();
+
+    let mut request = Vec::with_capacity(HEADER_SIZE);
+    request.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(&transaction_id);
+
+    let mut rto = INITIAL_RTO;
+    let mut buff = [0; 1500];
+    for _ in 0..MAX_ATTEMPTS {
+        udp_socket.send_to(&request, stun_server).await?;
+
+        let deadline = Instant::now() + rto;
+        while let Ok(received) = timeout_at(deadline, udp_socket.recv_from(&mut buff)).await {
+            let (len, source) = received?;
+            if source != stun_server {
+                continue;
+            }
+            if let Some(addr) = parse_binding_response(&buff[..len], &transaction_id)? {
+                return Ok(addr);
+            }
+        }
+        rto *= 2;
+    }
+    Err(anyhow!("No response from STUN server {}", stun_server))
+}
+
+/// Returns the mapped address of a binding success response to our request, `None` for unrelated messages.
+fn parse_binding_response(message: &[u8], transaction_id: &[u8; 12]) -> Result<Option<SocketAddr>> {
+    if message.len() < HEADER_SIZE
+        || message[4..8] != MAGIC_COOKIE.to_be_bytes()
+        || &message[8..20] != transaction_id {
+        return Ok(None);
+    }
+    let message_type = u16::from_be_bytes([message[0], message[1]]);
+    if message_type != BINDING_SUCCESS_RESPONSE {
+        return Err(anyhow!("STUN server replied with message type {:#06x}", message_type));
+    }
+
+    let length = u16::from_be_bytes([message[2], message[3]]) as usize;
+    let attributes = message.get(HEADER_SIZE..HEADER_SIZE + length)
+        .ok_or(anyhow!("Truncated STUN response"))?;
+
+    let mut mapped_address = None;
+    let mut offset = 0;
+    while offset + 4 <= attributes.len() {
+        let attribute_type = u16::from_be_bytes([attributes[offset], attributes[offset + 1]]);
+        let attribute_length = u16::from_be_bytes([attributes[offset + 2], attributes[offset + 3]]) as usize;
+        let value = attributes.get(offset + 4..offset + 4 + attribute_length)
+            .ok_or(anyhow!("Truncated STUN attribute"))?;
+
+        match attribute_type {
+            ATTRIBUTE_XOR_MAPPED_ADDRESS => return Ok(Some(parse_address(value, Some(transaction_id))?)),
+            ATTRIBUTE_MAPPED_ADDRESS => mapped_address = Some(parse_address(value, None)?),
+            _ => {}
+        }
+        // Attributes are padded to a multiple of 4 bytes
+        offset += 4 + attribute_length.div_ceil(4) * 4;
+    }
+
+    mapped_address.map(Some).ok_or(anyhow!("STUN response without mapped address"))
+}
+
+/// Parses a (XOR-)MAPPED-ADDRESS attribute, XOR-ed with the magic cookie and transaction id when given.
+fn parse_address(value: &[u8], xor_transaction_id: Option<&[u8; 12]>) -> Result<SocketAddr> {
+    if value.len() < 4 {
+        return Err(anyhow!("Invalid STUN address attribute"));
+    }
+    let mut xor_mask = [0u8; 16];
+    if let Some(transaction_id) = xor_transaction_id {
+        xor_mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        xor_mask[4..].copy_from_slice(transaction_id);
+    }
+
+    let port = u16::from_be_bytes([value[2] ^ xor_mask[0], value[3] ^ xor_mask[1]]);
+    let ip = match (value[1], value.len()) {
+        (0x01, 8) => {
+            let mut octets = [0u8; 4];
+            for (i, octet) in octets.iter_mut().enumerate() {
+                *octet = value[4 + i] ^ xor_mask[i];
+            }
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        (0x02, 20) => {
+            let mut octets = [0u8; 16];
+            for (i, octet) in octets.iter_mut().enumerate() {
+                *octet = value[4 + i] ^ xor_mask[i];
+            }
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return Err(anyhow!("Invalid STUN address attribute")),
+    };
+    Ok(SocketAddr::new(ip, port))
+}
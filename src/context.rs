@@ -1,31 +1,77 @@
-use anyhow::{anyhow, Result};
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use anyhow::Result;
+use crate::logging::debug;
+use tokio::net::UdpSocket;
+use tokio::runtime::Handle;
+use crate::call::rtp_session::bind_rtp_socket;
 use crate::config::Config;
 
 pub struct SipContext {
     pub config: Config,
+    /// Runtime the tasks of the manager and its calls are spawned on.
+    pub runtime: Handle,
     next_udp_port: u16,
 }
 
 impl SipContext {
-    pub fn from_config(config: Config) -> Result<Self>
+    pub fn from_config(config: Config, runtime: Handle) -> Result<Self>
     {
-        if config.rtp_port_start > config.rtp_port_end {
-            return Err(anyhow!("RTP start port is greater than RTP port end"));
-        }
+        config.validate()?;
 
         Ok(SipContext {
             next_udp_port: config.rtp_port_start,
             config,
+            runtime,
         })
     }
 
-    pub fn get_next_udp_port(&mut self) -> u16 {
-        // TODO: check if the port is available first
+    fn get_next_udp_port(&mut self) -> u16 {
         let port = self.next_udp_port;
-        self.next_udp_port += 2;
-        if self.next_udp_port > self.config.rtp_port_end {
-            self.next_udp_port = self.config.rtp_port_start;
-        }
+        // Wraps around at the end of the range, which may end at 65535
+        self.next_udp_port = match port.checked_add(2) {
+            Some(next_port) if next_port <= self.config.rtp_port_end => next_port,
+            _ => self.config.rtp_port_start,
+        };
         port
     }
-}
\ No newline at end of file
+
+    /// Binds the RTP socket of a new call on the next available port of the range, see [bind_rtp_socket].
+    ///
+    /// Binding claims the port, ports still used by a call, another [SipManager](crate::manager::SipManager)
+    /// or another process are skipped. Errors when every port of the range is in use.
+    pub async fn bind_next_rtp_socket(&mut self) -> Result<(Arc<UdpSocket>, SocketAddr)> {
+        let port_count = (self.config.rtp_port_end - self.config.rtp_port_start) / 2 + 1;
+        for _ in 0..port_count {
+            let port = self.get_next_udp_port();
+            match bind_rtp_socket(&self.config, port).await {
+                Err(e) if e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == ErrorKind::AddrInUse) => {
+                    debug!("RTP port {} is in use, trying the next one", port);
+                }
+                res => return res,
+            }
+        }
+        // Kept as an io error so that it is reported as a transient transport error
+        Err(std::io::Error::new(ErrorKind::AddrInUse, format!(
+            "No RTP port available in the range {}-{}",
+            self.config.rtp_port_start,
+            self.config.rtp_port_end
+        )).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn udp_ports_wrap_around_at_the_end_of_the_range() {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 5060));
+        let config = Config::builder().server(addr).own_addr(addr).username("1000").rtp_ports(65530, 65535).build().unwrap();
+        let mut sip_context = SipContext::from_config(config, Handle::current()).unwrap();
+
+        let ports = (0..4).map(|_| sip_context.get_next_udp_port()).collect::<Vec<_>>();
+        assert_eq!(ports, vec![65530, 65532, 65534, 65530]);
+    }
+}
@@ -0,0 +1,82 @@
+use crate::config::ConfigError;
+use std::fmt::{Display, Formatter};
+use tokio::time::error::Elapsed;
+
+/// Error returned by the public API.
+#[derive(Debug)]
+pub enum SipError {
+    /// The configuration is invalid.
+    Config(ConfigError),
+    /// Not connected to the SIP server, see [start](crate::manager::SipManager::start).
+    NotConnected,
+    /// The SIP server rejected our credentials.
+    Auth(String),
+    /// The connection to the SIP server or the RTP socket failed.
+    Transport(anyhow::Error),
+    /// No response was received in time.
+    Timeout,
+    /// Unexpected or malformed SIP / SDP message.
+    Protocol(anyhow::Error),
+    /// No codec is shared with the remote.
+    NoCompatibleCodec,
+}
+
+impl SipError {
+    /// Returns `true` for errors that might succeed when retried.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, SipError::NotConnected | SipError::Transport(_) | SipError::Timeout)
+    }
+}
+
+impl Display for SipError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SipError::Config(err) => write!(f, "Invalid configuration: {}", err),
+            SipError::NotConnected => write!(f, "Not connected"),
+            SipError::Auth(reason) => write!(f, "Authentication failed: {}", reason),
+            SipError::Transport(err) => write!(f, "Transport error: {}", err),
+            SipError::Timeout => write!(f, "Timed out"),
+            SipError::Protocol(err) => write!(f, "Protocol error: {}", err),
+            SipError::NoCompatibleCodec => write!(f, "No compatible codec"),
+        }
+    }
+}
+
+impl std::error::Error for SipError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SipError::Config(err) => Some(err),
+            SipError::Transport(err) | SipError::Protocol(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConfigError> for SipError {
+    fn from(err: ConfigError) -> Self {
+        SipError::Config(err)
+    }
+}
+
+/// Converts internal errors, errors not explicitly raised as a [SipError] are classified from their cause.
+impl From<anyhow::Error> for SipError {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<SipError>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<ConfigError>() {
+            Ok(err) => return SipError::Config(err),
+            Err(err) => err,
+        };
+
+        if err.chain().any(|cause| cause.is::<Elapsed>()) {
+            SipError::Timeout
+        } else if err.chain().any(|cause| cause.is::<std::io::Error>()
+            || cause.is::<tokio::sync::mpsc::error::SendError<rsip::SipMessage>>()) {
+            SipError::Transport(err)
+        } else {
+            SipError::Protocol(err)
+        }
+    }
+}
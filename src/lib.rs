@@ -15,13 +15,26 @@
 //! - `opus`: Enables the Opus codec (default)
 //! - `pcmu`: Enables the PCMU codec (default)
 //! - `pcma`: Enables the PCMA codec
+//! - `ilbc`: Enables the iLBC codec, links against the system libilbc found with pkg-config or in `ILBC_LIB_DIR`
+//! - `blocking`: Enables the [blocking] wrappers for non-async applications
+//! - `testing`: Enables the [testing] module, a mock SIP server to test applications without a live server
+//! - `rubato`: Uses a band-limited resampler (rubato) instead of linear interpolation for the PCMU and PCMA codecs
+//! - `tracing`: Logs with [tracing](https://docs.rs/tracing) instead of [log](https://docs.rs/log), in spans of the SIP
+//!   connection and of each call with structured fields like the Call-ID
 
+#[cfg(feature = "blocking")]
+pub mod blocking;
 pub mod call;
 pub mod config;
+pub mod error;
 pub mod manager;
+pub mod media;
+pub mod observer;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 mod connection;
 mod context;
+mod logging;
 mod sip_proto;
 mod utils;
-mod media;
@@ -0,0 +1,9 @@
+//! Logging macros used across the crate.
+//!
+//! The [log] macros, or the `tracing` macros when the `tracing` feature is enabled so that the events are recorded
+//! in the spans of the SIP socket and of the calls, with their structured fields.
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use log::{debug, error, info, warn};
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::{debug, error, info, warn};
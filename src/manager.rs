@@ -1,23 +1,35 @@
 use crate::call::incoming_call::IncomingCall;
-use crate::call::outgoing_call::OutgoingCall;
+use crate::call::outgoing_call::{CallOptions, OutgoingCall, OutgoingCallResponse};
 use crate::config::Config;
 use crate::connection::call_connection::CallConnection;
-use crate::connection::sip_socket::SipSocket;
+use crate::connection::sip_socket::{RegisterWaiter, SipSocket};
 use crate::context::SipContext;
 
 use crate::connection::socket_data::SocketData;
+use crate::error::SipError;
+use crate::observer::MessageObserver;
 use anyhow::{anyhow, Result};
+use futures_util::Stream;
+use crate::logging::error;
 use rsip::Scheme::Sip;
 use rsip::{HostWithPort, SipMessage, Uri};
 use std::ops::DerefMut;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::runtime::Handle;
 use tokio::sync::mpsc::{Receiver, Sender};
-use tokio::sync::Mutex;
+use tokio::sync::{oneshot, watch, Mutex};
 use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 
 /// Receives incoming calls from the SIP server.
+///
+/// Also a [Stream] of incoming calls, ending when the underlying connection was closed.
 pub struct IncomingCallReceiver {
     receiver: Receiver<IncomingCall>,
 }
@@ -40,6 +52,17 @@ impl IncomingCallReceiver {
     }
 }
 
+impl Stream for IncomingCallReceiver {
+    type Item = IncomingCall;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// Time given to the server to answer a REGISTER sent by [SipManager::reregister], the SIP transaction timeout (64 * T1).
+const REGISTER_TIMEOUT: Duration = Duration::from_secs(32);
+
 /// Represents an SIP session.
 /// SipManager is used to instantiate the SIP connection make / receive calls.
 ///
@@ -53,14 +76,13 @@ impl IncomingCallReceiver {
 ///  use simple_sip_rs::manager::SipManager;
 ///
 ///  async fn start_sip() {
-///     let config = Config {
-///         server_addr: SocketAddr::from_str("192.168.1.100:5060").unwrap(),
-///         own_addr: SocketAddr::from_str("192.168.1.2").unwrap(),
-///         username: "username".to_string(),
-///         password: "password".to_string(),
-///         rtp_port_start: 20480,
-///         rtp_port_end: 20490,
-///     };
+///     let config = Config::builder()
+///         .server(SocketAddr::from_str("192.168.1.100:5060").unwrap())
+///         .own_addr(SocketAddr::from_str("192.168.1.2:5060").unwrap())
+///         .credentials("username", "password")
+///         .rtp_ports(20480, 20490)
+///         .build()
+///         .unwrap();
 ///
 ///
 ///     let mut sip_manager = SipManager::from_config(config).await.unwrap();
@@ -74,19 +96,35 @@ pub struct SipManager {
 
     incoming_call_receiver: Option<Receiver<IncomingCall>>,
     incoming_call_sender: Sender<IncomingCall>,
+    message_observer: Option<Arc<dyn MessageObserver>>,
+    /// `true` while registered on the server.
+    registered: Arc<watch::Sender<bool>>,
 
     inner: Option<InnerSipManager>
 }
 
 impl SipManager {
     /// Create SipManager from the config
-    pub async fn from_config(config: Config) -> Result<Self> {
+    ///
+    /// The tasks of the manager and its calls are spawned on the current runtime,
+    /// see [from_config_with_handle](SipManager::from_config_with_handle) to use another one.
+    pub async fn from_config(config: Config) -> Result<Self, SipError> {
+        Self::from_config_with_handle(config, Handle::current())
+    }
+
+    /// Create SipManager from the config, spawning the tasks of the manager and its calls on `runtime`.
+    ///
+    /// Ex: a dedicated multi-threaded runtime keeps the RTP tasks away from the rest of the application.
+    /// Sockets are still created on the runtime calling the API.
+    pub fn from_config_with_handle(config: Config, runtime: Handle) -> Result<Self, SipError> {
         let (sender, receiver) = tokio::sync::mpsc::channel(32);
         Ok(SipManager {
-            context: Arc::new(Mutex::new(SipContext::from_config(config.clone())?)),
+            context: Arc::new(Mutex::new(SipContext::from_config(config.clone(), runtime)?)),
 
             incoming_call_receiver: Some(receiver),
             incoming_call_sender: sender,
+            message_observer: None,
+            registered: Arc::new(watch::channel(false).0),
 
             inner: None
         })
@@ -99,21 +137,41 @@ impl SipManager {
     /// This function will return an error in the following cases:
     /// - Failed to establish the underlying TCP connection
     /// - Failed to authenticate
-    pub async fn start(&mut self) -> Result<()> {
-        self.stop();
+    pub async fn start(&mut self) -> Result<(), SipError> {
+        self.shutdown().await;
 
         let inner = InnerSipManager::connect(
             self.context.clone(),
-            self.incoming_call_sender.clone()
+            self.incoming_call_sender.clone(),
+            self.message_observer.clone(),
+            self.registered.clone(),
         ).await?;
         self.inner = Some(inner);
 
         Ok(())
     }
 
+    /// Sets an observer receiving every SIP message sent to and received from the server, see [MessageObserver].
+    ///
+    /// Takes effect on the next [start](SipManager::start).
+    pub fn set_message_observer(&mut self, message_observer: impl MessageObserver + 'static) {
+        self.message_observer = Some(Arc::new(message_observer));
+    }
+
     /// Stops the underlying SIP socket. This effectively disconnects you from the server.
+    ///
+    /// The socket unregisters in the background, see [shutdown](SipManager::shutdown) to wait for it.
     pub fn stop(&mut self) {
-        drop(self.inner.take());
+        if let Some(inner) = self.inner.take() {
+            inner.stop();
+        }
+    }
+
+    /// Stops the underlying SIP socket and waits for it to unregister from the server.
+    pub async fn shutdown(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            inner.shutdown().await;
+        }
     }
 
     /// Checks if the connection is alive.
@@ -124,6 +182,39 @@ impl SipManager {
         false
     }
 
+    /// Checks if we are currently registered on the server.
+    ///
+    /// Unlike [is_running](SipManager::is_running), turns `false` as soon as a registration refresh fails.
+    pub fn is_registered(&self) -> bool {
+        *self.registered.borrow()
+    }
+
+    /// Waits until we are registered on the server, returns immediately if already registered.
+    ///
+    /// Keeps waiting while the manager is stopped, until it is [started](SipManager::start) again.
+    pub async fn wait_registered(&self) {
+        let mut receiver = self.registered.subscribe();
+        // The sender lives as long as the manager
+        let _ = receiver.wait_for(|registered| *registered).await;
+    }
+
+    /// Sends a REGISTER immediately and waits for its outcome, without reconnecting.
+    ///
+    /// Useful when the network changed (e.g. a handover) to make sure the server still reaches us,
+    /// the periodic refresh then restarts from the new registration.
+    ///
+    /// # Errors
+    ///
+    /// - [SipError::NotConnected] if the manager is not started
+    /// - [SipError::Auth] if the server rejects our credentials
+    /// - [SipError::Timeout] if the server does not answer in time
+    pub async fn reregister(&self) -> Result<(), SipError> {
+        match self.inner.as_ref() {
+            Some(inner) => inner.reregister().await,
+            None => Err(SipError::NotConnected),
+        }
+    }
+
     /// Takes the incoming call receiver.
     /// This is useful if you want to handle incoming calls in another task / thread.
     ///
@@ -157,20 +248,76 @@ impl SipManager {
     ///
     /// # Arguments
     ///
-    /// * `to`: Extension number to call. Ex: `"1000"`.
+    /// * `to`: Extension number to call on the server, ex: `"1000"`.
+    ///   Or a full SIP URI, ex: `"sip:alice@example.com:5080"`, the request is still sent through the server.
     ///
     /// # Errors
     ///
     /// This function will return an error in the following cases:
     /// - You are not connected to the server
+    /// - `to` is an invalid SIP URI
     /// - Failure to send the Invite message
-    pub async fn call(&self, to: String) -> Result<OutgoingCall>
+    pub async fn call(&self, to: String) -> Result<OutgoingCall, SipError>
+    {
+        self.call_with(to, CallOptions::default()).await
+    }
+
+    /// Calls `to` with the given options, see [call](SipManager::call).
+    ///
+    /// # Examples
+    /// ```
+    ///  use simple_sip_rs::call::outgoing_call::{CallOptions, Diversion, OutgoingCall};
+    ///  use simple_sip_rs::manager::SipManager;
+    ///
+    ///  // Forwards a call for 1000 to voicemail
+    ///  async fn forward_to_voicemail(sip_manager: &SipManager) -> OutgoingCall {
+    ///     let options = CallOptions {
+    ///         diversion: Some(Diversion::new("sip:1000@example.com".try_into().unwrap(), "no-answer")),
+    ///         ..Default::default()
+    ///     };
+    ///     sip_manager.call_with("*97".to_string(), options).await.unwrap()
+    ///  }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// See [call](SipManager::call).
+    pub async fn call_with(&self, to: String, options: CallOptions) -> Result<OutgoingCall, SipError>
     {
         if let Some(inner) = self.inner.as_ref() {
-            return inner.call(to).await;
+            return Ok(inner.call(to, options).await?);
         }
 
-        Err(anyhow!("Not connected"))
+        Err(SipError::NotConnected)
+    }
+
+    /// Calls `to` without waiting for the answer, the [OutgoingCallResponse] is sent to the returned receiver
+    /// once the remote answered or rejected the call.
+    ///
+    /// Returns as soon as the INVITE was sent, so that many calls can be started and tracked concurrently.
+    /// The answer is waited for on the runtime of the manager. Dropping the receiver before the answer hangs up the call once answered.
+    ///
+    /// # Errors
+    ///
+    /// See [call](SipManager::call), errors while waiting for the answer are sent to the receiver,
+    /// see [OutgoingCall::into_call_response].
+    pub async fn call_async(&self, to: String) -> Result<oneshot::Receiver<Result<OutgoingCallResponse, SipError>>, SipError>
+    {
+        self.call_async_with(to, CallOptions::default()).await
+    }
+
+    /// Calls `to` with the given options without waiting for the answer, see [call_async](SipManager::call_async)
+    /// and [call_with](SipManager::call_with).
+    pub async fn call_async_with(&self, to: String, options: CallOptions) -> Result<oneshot::Receiver<Result<OutgoingCallResponse, SipError>>, SipError>
+    {
+        let outgoing_call = self.call_with(to, options).await?;
+
+        let (sender, receiver) = oneshot::channel();
+        let runtime = self.context.lock().await.runtime.clone();
+        runtime.spawn(async move {
+            let _ = sender.send(outgoing_call.into_call_response().await);
+        });
+        Ok(receiver)
     }
 }
 
@@ -179,7 +326,9 @@ struct InnerSipManager {
 
     socket_data: Arc<Mutex<SocketData>>,
     message_sender: Sender<SipMessage>,
+    reregister_sender: Sender<RegisterWaiter>,
 
+    cancellation_token: CancellationToken,
     handle: JoinHandle<Result<()>>,
 }
 
@@ -187,15 +336,27 @@ impl InnerSipManager {
     pub async fn connect(
         context: Arc<Mutex<SipContext>>,
         incoming_call_sender: Sender<IncomingCall>,
+        message_observer: Option<Arc<dyn MessageObserver>>,
+        registered: Arc<watch::Sender<bool>>,
     ) -> Result<Self> {
-        let addr = context.lock().await.config.server_addr.clone();
-        let mut sip_socket = SipSocket::connect(addr, context.clone(), incoming_call_sender).await?;
+        let (addr, runtime) = {
+            let context = context.lock().await;
+            (context.config.server_addr.clone(), context.runtime.clone())
+        };
+        let mut sip_socket = SipSocket::connect(addr, context.clone(), incoming_call_sender, message_observer, registered).await?;
 
         let socket_data = sip_socket.get_socket_data();
         let message_sender = sip_socket.get_message_sender();
-
-        let handle = tokio::task::spawn(async move {
-            sip_socket.run().await
+        let reregister_sender = sip_socket.get_reregister_sender();
+
+        let cancellation_token = CancellationToken::new();
+        let run_token = cancellation_token.clone();
+        let handle = runtime.spawn(async move {
+            let res = sip_socket.run(run_token).await;
+            if let Err(e) = &res {
+                error!("SIP socket stopped with error: {:?}", e);
+            }
+            res
         });
 
         Ok(Self {
@@ -203,7 +364,9 @@ impl InnerSipManager {
 
             socket_data,
             message_sender,
+            reregister_sender,
 
+            cancellation_token,
             handle,
         })
     }
@@ -212,26 +375,41 @@ impl InnerSipManager {
         !self.handle.is_finished()
     }
 
-    pub fn stop(&mut self) {
-        if !self.is_running() {
-            self.handle.abort();
-        }
+    pub fn stop(&self) {
+        self.cancellation_token.cancel();
     }
 
-    pub async fn call(&self, to: String) -> Result<OutgoingCall> {
+    pub async fn shutdown(mut self) {
+        self.stop();
+        let _ = (&mut self.handle).await;
+    }
+
+    pub async fn reregister(&self) -> Result<(), SipError> {
+        let (sender, receiver) = oneshot::channel();
+        self.reregister_sender.send(sender).await.map_err(|_| SipError::NotConnected)?;
+        timeout(REGISTER_TIMEOUT, receiver).await
+            .map_err(|_| SipError::Timeout)?
+            .map_err(|_| SipError::NotConnected)?
+    }
+
+    pub async fn call(&self, to: String, options: CallOptions) -> Result<OutgoingCall> {
         let mut context_lock = self.context.lock().await;
-        let to_uri = Uri {
-            scheme: Some(Sip),
-            auth: Some((to, Option::<String>::None).into()),
-            host_with_port: HostWithPort::from(context_lock.config.server_addr),
-            ..Default::default()
+        let to_uri = if is_sip_uri(&to) {
+            Uri::try_from(to.as_str()).map_err(|e| anyhow!("Invalid SIP URI {}: {}", to, e))?
+        } else {
+            Uri {
+                scheme: Some(Sip),
+                auth: Some((to, Option::<String>::None).into()),
+                host_with_port: HostWithPort::from(context_lock.config.server_addr),
+                ..Default::default()
+            }
         };
 
         let call_id = Uuid::new_v4().to_string();
         let receiver = self.socket_data.lock().await.create_call_channel(call_id.clone()).await?;
         let call_connection = CallConnection::new(self.message_sender.clone(), receiver);
 
-        OutgoingCall::try_from(context_lock.deref_mut(), call_connection, call_id, to_uri).await
+        OutgoingCall::try_from(context_lock.deref_mut(), call_connection, call_id, to_uri, options).await
     }
 }
 
@@ -239,4 +417,10 @@ impl Drop for InnerSipManager {
     fn drop(&mut self) {
         self.stop();
     }
-}
\ No newline at end of file
+}
+
+/// Returns `true` if the destination is a full `sip:` or `sips:` URI rather than an extension.
+fn is_sip_uri(to: &str) -> bool {
+    let to = to.to_ascii_lowercase();
+    to.starts_with("sip:") || to.starts_with("sips:")
+}
@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+/// Outgoing audio waiting to be encoded, capped to a maximum duration.
+pub struct AudioBuffer<T = f32> {
+    samples: Vec<T>,
+    max_len: Option<usize>,
+}
+
+impl<T> AudioBuffer<T> {
+    pub fn new(max_duration: Option<Duration>, sample_rate: u32, channels: u8) -> Self {
+        let max_len = max_duration.map(|duration| {
+            (duration.as_millis() as usize * sample_rate as usize / 1000) * channels as usize
+        });
+
+        Self {
+            samples: Vec::new(),
+            max_len,
+        }
+    }
+
+    /// Appends the samples to the buffer.
+    ///
+    /// Returns `false` if some samples were dropped because the buffer is full.
+    pub fn append(&mut self, mut samples: Vec<T>) -> bool {
+        let mut fits = true;
+        if let Some(max_len) = self.max_len {
+            let available = max_len.saturating_sub(self.samples.len());
+            if samples.len() > available {
+                samples.truncate(available);
+                fits = false;
+            }
+        }
+        self.samples.append(&mut samples);
+        fits
+    }
+
+    /// Removes and returns up to `len` samples from the front of the buffer.
+    pub fn drain(&mut self, len: usize) -> Vec<T> {
+        let len = len.min(self.samples.len());
+        self.samples.drain(0..len).collect()
+    }
+
+    /// Number of samples in the buffer, interleaved channels counted separately.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+}
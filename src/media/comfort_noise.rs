@@ -0,0 +1,158 @@
+use std::time::Duration;
+use anyhow::Result;
+use bytes::Bytes;
+use rtp::codecs::g7xx::G711Payloader;
+use rtp::packet::Packet;
+use rtp::packetizer::Packetizer;
+use webrtc_sdp::attribute_type::{SdpAttribute, SdpAttributeRtpmap};
+use webrtc_sdp::media_type::{SdpMedia, SdpMediaValue};
+use webrtc_sdp::SdpSession;
+use crate::call::Media;
+use crate::config::Config;
+use crate::media::{CodecInfo, RTPCodec, RtpStream};
+
+/// Static payload type of comfort noise (RFC 3389).
+const PAYLOAD_TYPE: u8 = 13;
+/// Noise level sent in our SID packets, in -dBov.
+const NOISE_LEVEL: u8 = 70;
+/// Interval between two SID packets while silent.
+const SID_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Returns `true` if the sdp media advertises comfort noise.
+#[cfg(any(feature = "pcmu", feature = "pcma"))]
+pub(crate) fn sdp_media_has_comfort_noise(sdp_media: &SdpMedia) -> bool {
+    sdp_media.get_attributes().iter().any(|attr| {
+        matches!(attr, SdpAttribute::Rtpmap(rtpmap) if rtpmap.codec_name.eq_ignore_ascii_case("cn"))
+    })
+}
+
+/// Comfort noise (RFC 3389), sends SID packets during outgoing silence and generates noise during incoming silence.
+pub(crate) struct ComfortNoiseCodec {
+    payload_type: u8,
+    /// Sample rate of the audio exchanged with the call.
+    audio_rate: u32,
+    channels: u8,
+
+    packetizer: Box<dyn Packetizer + Send + Sync>,
+    /// Time since the last SID packet, `None` when outgoing audio is not silent.
+    since_last_sid: Option<Duration>,
+
+    /// Amplitude of the noise to play, `None` when the remote is not silent.
+    remote_noise_amplitude: Option<f32>,
+}
+
+impl ComfortNoiseCodec {
+    pub fn try_from_sdp(sdp_session: &SdpSession, config: &Config, rtp_stream: &RtpStream) -> Option<ComfortNoiseCodec> {
+        if !config.comfort_noise {
+            return None;
+        }
+
+        for media in sdp_session.media.iter() {
+            if media.get_type() != &SdpMediaValue::Audio {
+                continue;
+            }
+            for attr in media.get_attributes() {
+                if let SdpAttribute::Rtpmap(attr) = attr {
+                    if attr.codec_name.eq_ignore_ascii_case("cn") {
+                        return Some(ComfortNoiseCodec {
+                            payload_type: attr.payload_type,
+                            audio_rate: config.audio_sample_rate,
+                            channels: config.audio_channels,
+                            packetizer: rtp_stream.new_packetizer(300, attr.payload_type, Box::new(G711Payloader::default()), attr.frequency),
+                            since_last_sid: None,
+                            remote_noise_amplitude: None,
+                        });
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the SID packets to send, called on each packet interval while there is no outgoing audio.
+    pub fn get_silence_packets(&mut self, ptime: Duration) -> Result<Vec<Packet>> {
+        let elapsed = match self.since_last_sid {
+            None => Duration::ZERO,
+            Some(since_last_sid) if since_last_sid + ptime >= SID_INTERVAL => since_last_sid + ptime,
+            Some(since_last_sid) => {
+                self.since_last_sid = Some(since_last_sid + ptime);
+                return Ok(Vec::new());
+            }
+        };
+        self.since_last_sid = Some(Duration::ZERO);
+
+        // Clock rate of comfort noise is 8000Hz
+        let samples = (elapsed.as_millis() * 8) as u32;
+        Ok(self.packetizer.packetize(&Bytes::from(vec![NOISE_LEVEL]), samples)?)
+    }
+
+    /// Outgoing audio resumed, the next silence starts with a new SID packet.
+    pub fn reset_silence(&mut self) {
+        self.since_last_sid = None;
+    }
+
+    /// Returns `ptime` worth of noise while the remote is silent.
+    pub fn generate_noise(&self, ptime: Duration) -> Option<Vec<f32>> {
+        let amplitude = self.remote_noise_amplitude?;
+        let samples_count = self.audio_rate as usize / 1000 * ptime.as_millis() as usize * self.channels as usize;
+        Some(
+            (0..samples_count)
+                .map(|_| (rand::random::<f32>() * 2.0 - 1.0) * amplitude)
+                .collect()
+        )
+    }
+
+    /// Incoming audio resumed.
+    pub fn stop_noise(&mut self) {
+        self.remote_noise_amplitude = None;
+    }
+}
+
+impl RTPCodec for ComfortNoiseCodec {
+    fn populate_sdp_media(sdp_media: &mut SdpMedia, config: &Config) -> Result<()>
+    where
+        Self: Sized
+    {
+        if config.comfort_noise {
+            sdp_media.add_codec(SdpAttributeRtpmap {
+                payload_type: PAYLOAD_TYPE,
+                codec_name: "CN".to_string(),
+                frequency: 8000,
+                channels: None,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn get_payload_type(&self) -> u8 {
+        self.payload_type
+    }
+
+    fn get_codec_info(&self) -> CodecInfo {
+        CodecInfo {
+            name: "CN".to_string(),
+            clock_rate: 8000,
+            payload_type: self.payload_type,
+        }
+    }
+
+    fn can_handle_media(&self, _: &Media) -> bool {
+        false
+    }
+
+    fn decode_payload(&mut self, payload: Bytes, _timestamp: u32) -> Result<Option<Media>> {
+        // Level is expressed in -dBov, missing level is treated as the quietest
+        let level = payload.first().copied().unwrap_or(127) & 0x7F;
+        self.remote_noise_amplitude = Some(10f32.powf(-(level as f32) / 20.0));
+        Ok(None)
+    }
+
+    fn append_to_buffer(&mut self, _: Media) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn get_next_packet(&mut self) -> Result<Vec<Packet>> {
+        Ok(Vec::new())
+    }
+}
@@ -0,0 +1,385 @@
+use crate::media::{get_sdp_media_ptime, outgoing_audio, CodecInfo, RTPCodec, RtpStream};
+use crate::media::audio_buffer::AudioBuffer;
+use crate::media::resample::PacketResampler;
+use crate::call::Media;
+use crate::config::Config;
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use rtp::packet::Packet;
+use rtp::packetizer::{Packetizer, Payloader};
+use std::ptr;
+use webrtc_sdp::attribute_type::{SdpAttribute, SdpAttributeFmtp, SdpAttributeFmtpParameters, SdpAttributeRtpmap};
+use webrtc_sdp::media_type::{SdpMedia, SdpMediaValue};
+use webrtc_sdp::SdpSession;
+
+// Bindings to libilbc, the iLBC implementation extracted from WebRTC, linked by the build script
+// https://github.com/TimothyGu/libilbc
+mod ffi {
+    #[repr(C)]
+    pub struct IlbcEncoderInstance {
+        _private: [u8; 0],
+    }
+
+    #[repr(C)]
+    pub struct IlbcDecoderInstance {
+        _private: [u8; 0],
+    }
+
+    extern "C" {
+        pub fn WebRtcIlbcfix_EncoderCreate(encoder: *mut *mut IlbcEncoderInstance) -> i16;
+        pub fn WebRtcIlbcfix_EncoderFree(encoder: *mut IlbcEncoderInstance) -> i16;
+        pub fn WebRtcIlbcfix_EncoderInit(encoder: *mut IlbcEncoderInstance, frame_len: i16) -> i16;
+        pub fn WebRtcIlbcfix_Encode(encoder: *mut IlbcEncoderInstance, speech_in: *const i16, len: usize, encoded: *mut u8) -> i32;
+
+        pub fn WebRtcIlbcfix_DecoderCreate(decoder: *mut *mut IlbcDecoderInstance) -> i16;
+        pub fn WebRtcIlbcfix_DecoderFree(decoder: *mut IlbcDecoderInstance) -> i16;
+        pub fn WebRtcIlbcfix_DecoderInit(decoder: *mut IlbcDecoderInstance, frame_len: i16) -> i16;
+        pub fn WebRtcIlbcfix_Decode(decoder: *mut IlbcDecoderInstance, encoded: *const u8, len: usize, decoded: *mut i16, speech_type: *mut i16) -> i32;
+    }
+}
+
+const PAYLOAD_TYPE: u8 = 97;
+const SAMPLE_RATE: u32 = 8000;
+/// Frame mode we advertise, the remote may still require 30 ms frames (RFC 3952).
+const ADVERTISED_MODE: FrameMode = FrameMode::Ms20;
+
+/// iLBC frame duration, negotiated with the `mode` fmtp parameter.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum FrameMode {
+    Ms20,
+    Ms30,
+}
+
+impl FrameMode {
+    /// Uses 30 ms frames unless both sides ask for 20 ms, the default mode being 30 ms.
+    fn from_sdp_media(media: &SdpMedia, payload_type: u8) -> FrameMode {
+        let remote_mode = media.get_attributes().iter().find_map(|attr| match attr {
+            SdpAttribute::Fmtp(fmtp) if fmtp.payload_type == payload_type => {
+                fmtp.parameters.unknown_tokens.iter().find_map(|token| match token.split_once('=') {
+                    Some((name, value)) if name.trim().eq_ignore_ascii_case("mode") => Some(value.trim().to_string()),
+                    _ => None,
+                })
+            }
+            _ => None,
+        });
+        match (remote_mode.as_deref(), ADVERTISED_MODE) {
+            (Some("20"), FrameMode::Ms20) => FrameMode::Ms20,
+            _ => FrameMode::Ms30,
+        }
+    }
+
+    fn from_payload_len(len: usize) -> Option<FrameMode> {
+        if len.is_multiple_of(FrameMode::Ms20.bytes()) {
+            Some(FrameMode::Ms20)
+        } else if len.is_multiple_of(FrameMode::Ms30.bytes()) {
+            Some(FrameMode::Ms30)
+        } else {
+            None
+        }
+    }
+
+    fn duration_ms(&self) -> u32 {
+        match self {
+            FrameMode::Ms20 => 20,
+            FrameMode::Ms30 => 30,
+        }
+    }
+
+    /// Samples per frame @ 8000Hz.
+    fn samples(&self) -> usize {
+        (SAMPLE_RATE / 1000 * self.duration_ms()) as usize
+    }
+
+    /// Encoded frame size in bytes.
+    fn bytes(&self) -> usize {
+        match self {
+            FrameMode::Ms20 => 38,
+            FrameMode::Ms30 => 50,
+        }
+    }
+}
+
+struct Encoder {
+    instance: *mut ffi::IlbcEncoderInstance,
+}
+
+impl Encoder {
+    fn new(mode: FrameMode) -> Result<Self> {
+        let mut instance = ptr::null_mut();
+        // SAFETY: the instance is allocated by libilbc and only freed on drop
+        unsafe {
+            if ffi::WebRtcIlbcfix_EncoderCreate(&mut instance) != 0 || instance.is_null() {
+                return Err(anyhow!("Failed to create iLBC encoder"));
+            }
+            let encoder = Encoder { instance };
+            if ffi::WebRtcIlbcfix_EncoderInit(encoder.instance, mode.duration_ms() as i16) != 0 {
+                return Err(anyhow!("Failed to initialize iLBC encoder"));
+            }
+            Ok(encoder)
+        }
+    }
+
+    /// Encodes a whole number of frames.
+    fn encode(&mut self, samples: &[i16], mode: FrameMode) -> Result<Vec<u8>> {
+        let mut encoded = vec![0u8; samples.len() / mode.samples() * mode.bytes()];
+        // SAFETY: `encoded` fits the frames of `samples`
+        let len = unsafe {
+            ffi::WebRtcIlbcfix_Encode(self.instance, samples.as_ptr(), samples.len(), encoded.as_mut_ptr())
+        };
+        if len < 0 {
+            return Err(anyhow!("Failed to encode iLBC frame"));
+        }
+        encoded.truncate(len as usize);
+        Ok(encoded)
+    }
+}
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        // SAFETY: the instance was created by WebRtcIlbcfix_EncoderCreate
+        unsafe {
+            ffi::WebRtcIlbcfix_EncoderFree(self.instance);
+        }
+    }
+}
+
+// SAFETY: the instance is owned and only used through `&mut self`
+unsafe impl Send for Encoder {}
+
+struct Decoder {
+    instance: *mut ffi::IlbcDecoderInstance,
+    mode: FrameMode,
+}
+
+impl Decoder {
+    fn new(mode: FrameMode) -> Result<Self> {
+        let mut instance = ptr::null_mut();
+        // SAFETY: the instance is allocated by libilbc and only freed on drop
+        unsafe {
+            if ffi::WebRtcIlbcfix_DecoderCreate(&mut instance) != 0 || instance.is_null() {
+                return Err(anyhow!("Failed to create iLBC decoder"));
+            }
+        }
+        let mut decoder = Decoder { instance, mode };
+        decoder.init(mode)?;
+        Ok(decoder)
+    }
+
+    fn init(&mut self, mode: FrameMode) -> Result<()> {
+        // SAFETY: the instance is valid until dropped
+        if unsafe { ffi::WebRtcIlbcfix_DecoderInit(self.instance, mode.duration_ms() as i16) } != 0 {
+            return Err(anyhow!("Failed to initialize iLBC decoder"));
+        }
+        self.mode = mode;
+        Ok(())
+    }
+
+    /// Decodes the frames of a payload, switching mode if the payload size requires it.
+    fn decode(&mut self, payload: &[u8]) -> Result<Vec<i16>> {
+        let mode = FrameMode::from_payload_len(payload.len())
+            .ok_or(anyhow!("Invalid iLBC payload size {}", payload.len()))?;
+        if !payload.len().is_multiple_of(self.mode.bytes()) {
+            self.init(mode)?;
+        }
+
+        let mut decoded = vec![0i16; payload.len() / self.mode.bytes() * self.mode.samples()];
+        let mut speech_type = 0i16;
+        // SAFETY: `decoded` fits the frames of `payload`
+        let len = unsafe {
+            ffi::WebRtcIlbcfix_Decode(self.instance, payload.as_ptr(), payload.len(), decoded.as_mut_ptr(), &mut speech_type)
+        };
+        if len < 0 {
+            return Err(anyhow!("Failed to decode iLBC payload"));
+        }
+        decoded.truncate(len as usize);
+        Ok(decoded)
+    }
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        // SAFETY: the instance was created by WebRtcIlbcfix_DecoderCreate
+        unsafe {
+            ffi::WebRtcIlbcfix_DecoderFree(self.instance);
+        }
+    }
+}
+
+// SAFETY: the instance is owned and only used through `&mut self`
+unsafe impl Send for Decoder {}
+
+/// Sends the frames of a packet as a single payload.
+#[derive(Debug, Default, Copy, Clone)]
+struct IlbcPayloader;
+
+impl Payloader for IlbcPayloader {
+    fn payload(&mut self, _mtu: usize, payload: &Bytes) -> Result<Vec<Bytes>, rtp::Error> {
+        if payload.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(vec![payload.clone()])
+    }
+
+    fn clone_to(&self) -> Box<dyn Payloader + Send + Sync> {
+        Box::new(*self)
+    }
+}
+
+pub struct IlbcCodec {
+    mode: FrameMode,
+    /// Packet duration in milliseconds, a whole number of frames.
+    ptime: u32,
+    payload_type: u8,
+    /// Sample rate of the audio exchanged with the call.
+    audio_rate: u32,
+    channels: u8,
+
+    encoder: Encoder,
+    decoder: Decoder,
+    packetizer: Box<dyn Packetizer + Send + Sync>,
+
+    buffer_out: AudioBuffer,
+    resampler: PacketResampler,
+}
+
+impl IlbcCodec {
+    pub fn try_from_sdp_session(sdp_session: &SdpSession, config: &Config, rtp_stream: &RtpStream) -> Result<Option<Self>> {
+        for media in sdp_session.media.iter() {
+            if media.get_type() != &SdpMediaValue::Audio {
+                continue;
+            }
+
+            for attr in media.get_attributes().iter() {
+                if let SdpAttribute::Rtpmap(a) = attr {
+                    if a.codec_name.eq_ignore_ascii_case("ilbc") {
+                        let mode = FrameMode::from_sdp_media(media, a.payload_type);
+                        let frames = (get_sdp_media_ptime(media) / mode.duration_ms()).max(1);
+
+                        let instance = IlbcCodec {
+                            mode,
+                            ptime: frames * mode.duration_ms(),
+                            payload_type: a.payload_type,
+                            audio_rate: config.audio_sample_rate,
+                            channels: config.audio_channels,
+
+                            encoder: Encoder::new(mode)?,
+                            decoder: Decoder::new(mode)?,
+                            packetizer: rtp_stream.new_packetizer(1200, a.payload_type, Box::new(IlbcPayloader), SAMPLE_RATE),
+
+                            buffer_out: AudioBuffer::new(config.max_audio_buffer, config.audio_sample_rate, config.audio_channels),
+                            resampler: PacketResampler::new(SAMPLE_RATE, config.audio_sample_rate, config.audio_channels),
+                        };
+
+                        return Ok(Some(instance));
+                    }
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl RTPCodec for IlbcCodec {
+    fn populate_sdp_media(sdp_media: &mut SdpMedia, _config: &Config) -> Result<()>
+    where
+        Self: Sized
+    {
+        sdp_media.add_codec(SdpAttributeRtpmap {
+            payload_type: PAYLOAD_TYPE,
+            codec_name: "iLBC".to_string(),
+            frequency: SAMPLE_RATE,
+            channels: None,
+        })?;
+
+        sdp_media.add_attribute(SdpAttribute::Fmtp(SdpAttributeFmtp {
+            payload_type: PAYLOAD_TYPE,
+            parameters: SdpAttributeFmtpParameters {
+                packetization_mode: 0,
+                level_asymmetry_allowed: false,
+                profile_level_id: 0,
+                max_fs: 0,
+                max_cpb: 0,
+                max_dpb: 0,
+                max_br: 0,
+                max_mbps: 0,
+                max_fr: 0,
+                profile: None,
+                level_idx: None,
+                tier: None,
+                maxplaybackrate: 48000,
+                maxaveragebitrate: 0,
+                usedtx: false,
+                stereo: false,
+                useinbandfec: false,
+                cbr: false,
+                ptime: 0,
+                minptime: 0,
+                maxptime: 0,
+                encodings: vec![],
+                dtmf_tones: "".to_string(),
+                rtx: None,
+                unknown_tokens: vec![format!("mode={}", ADVERTISED_MODE.duration_ms())],
+            },
+        }))?;
+
+        Ok(())
+    }
+
+    fn get_payload_type(&self) -> u8 {
+        self.payload_type
+    }
+
+    fn get_codec_info(&self) -> CodecInfo {
+        CodecInfo {
+            name: "iLBC".to_string(),
+            clock_rate: SAMPLE_RATE,
+            payload_type: self.payload_type,
+        }
+    }
+
+    fn can_handle_media(&self, media: &Media) -> bool {
+        matches!(media, Media::Audio(_) | Media::AudioI16 { .. })
+    }
+
+    fn decode_payload(&mut self, payload: Bytes, _timestamp: u32) -> Result<Option<Media>> {
+        let audio = self.decoder.decode(&payload)?;
+        let audio = self.resampler.resample_decoded(audio);
+
+        Ok(Some(Media::Audio(audio)))
+    }
+
+    fn get_ptime(&self) -> Option<u32> {
+        Some(self.ptime)
+    }
+
+    fn buffered_len(&self) -> usize {
+        self.buffer_out.len()
+    }
+
+    fn append_to_buffer(&mut self, media: Media) -> Result<bool> {
+        if let Some(buffer) = outgoing_audio(media, self.audio_rate, self.channels) {
+            return Ok(self.buffer_out.append(buffer));
+        }
+        Ok(true)
+    }
+
+    fn get_next_packet(&mut self) -> Result<Vec<Packet>> {
+        if self.buffer_out.is_empty() {
+            return Ok(vec![]);
+        }
+        let samples_count = (self.audio_rate / 1000 * self.ptime * self.channels as u32) as usize;
+        let mut samples = self.buffer_out.drain(samples_count);
+        if samples.len() < samples_count {
+            samples.resize(samples_count, 0.0);
+        }
+
+        // The encoder only takes whole frames
+        let mut audio = self.resampler.resample_for_encoding(samples);
+        audio.resize((SAMPLE_RATE / 1000 * self.ptime) as usize, 0);
+        let payload = self.encoder.encode(&audio, self.mode)?;
+
+        let packets = self.packetizer.packetize(&Bytes::from(payload), SAMPLE_RATE / 1000 * self.ptime)?;
+        Ok(packets)
+    }
+}
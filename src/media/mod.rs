@@ -1,77 +1,378 @@
 #[cfg(feature = "opus")]
-pub mod opus;
+pub(crate) mod opus;
 #[cfg(feature = "pcmu")]
-pub mod pcmu;
+pub(crate) mod pcmu;
 #[cfg(feature = "pcma")]
-pub mod pcma;
+pub(crate) mod pcma;
+#[cfg(feature = "ilbc")]
+pub(crate) mod ilbc;
 pub mod telephone_events;
+pub(crate) mod comfort_noise;
+pub(crate) mod passthrough;
+#[cfg(any(feature = "opus", feature = "pcmu", feature = "pcma", feature = "ilbc"))]
+pub(crate) mod audio_buffer;
+pub(crate) mod resample;
+#[cfg(any(feature = "pcmu", feature = "pcma"))]
+pub(crate) mod plc;
 
 
 use anyhow::Result;
 use bytes::Bytes;
 use rtp::packet::Packet;
-use webrtc_sdp::media_type::SdpMedia;
+use rtp::packetizer::{new_packetizer, Payloader, Packetizer};
+use rtp::sequence::{new_fixed_sequencer, Sequencer};
+use webrtc_sdp::attribute_type::{SdpAttribute, SdpAttributeType};
+use webrtc_sdp::media_type::{SdpMedia, SdpMediaValue};
 use webrtc_sdp::SdpSession;
 use crate::call::Media;
+use crate::config::Config;
 #[cfg(feature = "opus")]
 use crate::media::opus::OpusCodec;
 #[cfg(feature = "pcmu")]
 use crate::media::pcmu::PcmuCodec;
 #[cfg(feature = "pcma")]
 use crate::media::pcma::PcmaCodec;
+#[cfg(feature = "ilbc")]
+use crate::media::ilbc::IlbcCodec;
+use crate::media::comfort_noise::ComfortNoiseCodec;
 use crate::media::telephone_events::TelephoneEventsCodec;
+use crate::media::passthrough::PassthroughCodec;
+#[cfg(any(feature = "opus", feature = "pcmu", feature = "pcma", feature = "ilbc"))]
+use crate::media::resample::resample_i16;
 
-pub trait RTPCodec {
-    fn populate_sdp_media(sdp_media: &mut SdpMedia) -> Result<()> where Self: Sized;
+/// Describes an audio codec.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CodecInfo {
+    /// Codec name as found in the SDP. Ex: `"opus"`, `"PCMU"`.
+    pub name: String,
+    /// RTP clock rate in Hz.
+    pub clock_rate: u32,
+    /// RTP payload type.
+    pub payload_type: u8,
+}
+
+/// Header of a received RTP packet, see [Config::rtp_packet_info](crate::config::Config::rtp_packet_info).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RtpPacketInfo {
+    pub sequence_number: u16,
+    /// RTP timestamp, in units of the codec clock rate.
+    pub timestamp: u32,
+    /// Set on the first packet of a talk spurt for audio codecs.
+    pub marker: bool,
+    pub payload_type: u8,
+    pub ssrc: u32,
+}
+
+impl From<&rtp::header::Header> for RtpPacketInfo {
+    fn from(header: &rtp::header::Header) -> Self {
+        Self {
+            sequence_number: header.sequence_number,
+            timestamp: header.timestamp,
+            marker: header.marker,
+            payload_type: header.payload_type,
+            ssrc: header.ssrc,
+        }
+    }
+}
+
+/// Audio codecs supported by the crate.
+///
+/// Codecs whose crate feature is not enabled are ignored.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CodecKind {
+    Opus,
+    Pcmu,
+    Pcma,
+    Ilbc,
+}
+
+impl CodecKind {
+    /// Returns the codecs enabled through crate features, in order of preference.
+    pub fn enabled() -> Vec<CodecKind> {
+        vec![
+            #[cfg(feature = "opus")]
+            CodecKind::Opus,
+            #[cfg(feature = "pcmu")]
+            CodecKind::Pcmu,
+            #[cfg(feature = "pcma")]
+            CodecKind::Pcma,
+            #[cfg(feature = "ilbc")]
+            CodecKind::Ilbc,
+        ]
+    }
+
+    /// Returns `true` if the codec feature is enabled.
+    pub fn is_enabled(&self) -> bool {
+        CodecKind::enabled().contains(self)
+    }
+
+    /// Returns the codec info as advertised in our offers.
+    pub fn codec_info(&self) -> CodecInfo {
+        let (name, clock_rate, payload_type) = match self {
+            CodecKind::Opus => ("opus", 48000, 107),
+            CodecKind::Pcmu => ("PCMU", 8000, 0),
+            CodecKind::Pcma => ("PCMA", 8000, 8),
+            CodecKind::Ilbc => ("iLBC", 8000, 97),
+        };
+        CodecInfo {
+            name: name.to_string(),
+            clock_rate,
+            payload_type,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<CodecKind> {
+        match name.to_lowercase().as_str() {
+            "opus" => Some(CodecKind::Opus),
+            "pcmu" => Some(CodecKind::Pcmu),
+            "pcma" => Some(CodecKind::Pcma),
+            "ilbc" => Some(CodecKind::Ilbc),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the audio codecs enabled through crate features, in order of preference.
+pub fn available_codecs() -> Vec<CodecInfo> {
+    CodecKind::enabled().iter().map(CodecKind::codec_info).collect()
+}
+
+/// Outgoing RTP stream of a session, shared by its codecs so that they use the same SSRC and sequence numbers.
+#[derive(Clone)]
+pub(crate) struct RtpStream {
+    ssrc: u32,
+    sequencer: Box<dyn Sequencer + Send + Sync>,
+}
+
+impl RtpStream {
+    pub fn new(ssrc: u32, initial_sequence_number: u16) -> Self {
+        Self {
+            ssrc,
+            sequencer: Box::new(new_fixed_sequencer(initial_sequence_number)),
+        }
+    }
+
+    pub fn new_packetizer(
+        &self,
+        mtu: usize,
+        payload_type: u8,
+        payloader: Box<dyn Payloader + Send + Sync>,
+        clock_rate: u32,
+    ) -> Box<dyn Packetizer + Send + Sync> {
+        Box::new(new_packetizer(mtu, payload_type, self.ssrc, payloader, self.sequencer.clone(), clock_rate))
+    }
+}
+
+pub(crate) trait RTPCodec {
+    fn populate_sdp_media(sdp_media: &mut SdpMedia, config: &Config) -> Result<()> where Self: Sized;
 
     fn get_payload_type(&self) -> u8;
+    fn get_codec_info(&self) -> CodecInfo;
     fn can_handle_media(&self, media: &Media) -> bool;
 
-    fn decode_payload(&mut self, payload: Bytes) -> Result<Option<Media>>;
+    /// Decodes the payload of a received packet, `timestamp` being the RTP timestamp of the packet.
+    fn decode_payload(&mut self, payload: Bytes, timestamp: u32) -> Result<Option<Media>>;
 
-    fn append_to_buffer(&mut self, media: Media) -> Result<()>;
+    /// Returns the audio concealing `lost_packets` packets lost right before the next received one,
+    /// `None` if the codec has no packet loss concealment.
+    fn conceal_lost_packets(&mut self, _lost_packets: u16) -> Option<Media> {
+        None
+    }
+
+    /// Returns the media produced without receiving a packet, called on each packet interval.
+    fn poll_media(&mut self) -> Vec<Media> {
+        Vec::new()
+    }
+
+    /// Duration of the audio in each packet in milliseconds, pacing the outgoing packets.
+    /// `None` for codecs not sending audio.
+    fn get_ptime(&self) -> Option<u32> {
+        None
+    }
+
+    /// Number of outgoing audio samples waiting in the codec buffer.
+    fn buffered_len(&self) -> usize {
+        0
+    }
+
+    /// Appends outgoing media to the codec buffer.
+    ///
+    /// Returns `false` if some of the media was dropped because the buffer is full.
+    fn append_to_buffer(&mut self, media: Media) -> Result<bool>;
     fn get_next_packet(&mut self) -> Result<Vec<Packet>>;
 }
 
-pub fn get_codecs_from_sdp_session(sdp_session: &SdpSession) -> Result<Vec<Box<dyn RTPCodec + Send>>>
+/// Returns the outgoing audio as interleaved `f32` samples at `audio_rate` with `channels` channels, `None` if the media is not audio.
+#[cfg(any(feature = "opus", feature = "pcmu", feature = "pcma", feature = "ilbc"))]
+pub(crate) fn outgoing_audio(media: Media, audio_rate: u32, channels: u8) -> Option<Vec<f32>>
+{
+    match media {
+        Media::Audio(audio) => Some(audio),
+        Media::AudioI16 { samples, sample_rate, channels: from_channels } => Some(resample_i16(samples, sample_rate, audio_rate, from_channels, channels)),
+        _ => None,
+    }
+}
+
+/// Returns the root mean square of the samples, 0.0 for no samples.
+pub(crate) fn rms_level(samples: &[f32]) -> f32
+{
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+/// Packet duration in milliseconds when the SDP does not specify one.
+pub(crate) const DEFAULT_PTIME: u32 = 20;
+
+/// Returns the packet duration in milliseconds requested by the sdp media with `a=ptime`, capped by `a=maxptime`.
+pub(crate) fn get_sdp_media_ptime(sdp_media: &SdpMedia) -> u32
+{
+    let ptime = match sdp_media.get_attribute(SdpAttributeType::Ptime) {
+        Some(SdpAttribute::Ptime(ptime)) if *ptime > 0 => *ptime as u32,
+        _ => DEFAULT_PTIME,
+    };
+    match sdp_media.get_attribute(SdpAttributeType::MaxPtime) {
+        Some(SdpAttribute::MaxPtime(max_ptime)) if *max_ptime > 0 => ptime.min(*max_ptime as u32),
+        _ => ptime,
+    }
+}
+
+/// Bytes of the IPv4, UDP and RTP headers of each packet, counted by `b=AS`.
+#[cfg(feature = "opus")]
+const PACKET_OVERHEAD: u32 = 20 + 8 + 12;
+
+/// Returns the maximum payload bitrate in bits per second allowed by the `b=TIAS` or `b=AS` line of the sdp media,
+/// or of the session when the media has none. `ptime` is used to remove the packet headers counted by `b=AS`.
+#[cfg(feature = "opus")]
+pub(crate) fn get_sdp_max_bitrate(sdp_session: &SdpSession, sdp_media: &SdpMedia, ptime: u32) -> Option<u32>
+{
+    let max_bitrate = |bandwidth: &[webrtc_sdp::SdpBandwidth]| {
+        bandwidth.iter().find_map(|bandwidth| match bandwidth {
+            webrtc_sdp::SdpBandwidth::Tias(bits) => Some(*bits),
+            _ => None,
+        }).or_else(|| bandwidth.iter().find_map(|bandwidth| match bandwidth {
+            webrtc_sdp::SdpBandwidth::As(kilobits) => {
+                let overhead = PACKET_OVERHEAD * 8 * 1000 / ptime.max(1);
+                Some((kilobits * 1000).saturating_sub(overhead))
+            }
+            _ => None,
+        }))
+    };
+    max_bitrate(sdp_media.get_bandwidth()).or_else(|| max_bitrate(&sdp_session.bandwidth))
+}
+
+/// Returns the audio codecs advertised in the sdp session.
+pub(crate) fn get_codec_kinds_from_sdp_session(sdp_session: &SdpSession) -> Vec<CodecKind>
+{
+    let mut codecs = Vec::new();
+    for media in sdp_session.media.iter() {
+        for codec in get_codec_kinds_from_sdp_media(media) {
+            if !codecs.contains(&codec) {
+                codecs.push(codec);
+            }
+        }
+    }
+    codecs
+}
+
+/// Returns the audio codecs advertised in the sdp media.
+pub(crate) fn get_codec_kinds_from_sdp_media(sdp_media: &SdpMedia) -> Vec<CodecKind>
+{
+    let mut codecs = Vec::new();
+    for attribute in sdp_media.get_attributes() {
+        if let SdpAttribute::Rtpmap(rtpmap) = attribute {
+            if let Some(codec) = CodecKind::from_name(&rtpmap.codec_name) {
+                if !codecs.contains(&codec) {
+                    codecs.push(codec);
+                }
+            }
+        }
+    }
+    codecs
+}
+
+/// Instantiates the codecs found in the sdp session, restricted to `allowed_codecs`.
+pub(crate) fn get_codecs_from_sdp_session(
+    sdp_session: &SdpSession,
+    config: &Config,
+    allowed_codecs: &[CodecKind],
+    rtp_stream: &RtpStream,
+) -> Result<Vec<Box<dyn RTPCodec + Send>>>
 {
     let mut codecs = Vec::new();
 
+    #[cfg(not(any(feature = "opus", feature = "pcmu", feature = "pcma", feature = "ilbc")))]
+    let _ = allowed_codecs;
+
     #[cfg(feature = "opus")]
-    if let Some(opus_codec) = OpusCodec::try_from_sdp_session(sdp_session)? {
-        let boxed: Box<dyn RTPCodec + Send> = Box::new(opus_codec);
-        codecs.push(boxed);
+    if allowed_codecs.contains(&CodecKind::Opus) {
+        if let Some(opus_codec) = OpusCodec::try_from_sdp_session(sdp_session, config, rtp_stream)? {
+            let boxed: Box<dyn RTPCodec + Send> = Box::new(opus_codec);
+            codecs.push(boxed);
+        }
     }
 
     #[cfg(feature = "pcmu")]
-    if let Some(pcmu_codec) = PcmuCodec::try_from_sdp_session(sdp_session)? {
-        let boxed: Box<dyn RTPCodec + Send> = Box::new(pcmu_codec);
-        codecs.push(boxed);
+    if allowed_codecs.contains(&CodecKind::Pcmu) {
+        if let Some(pcmu_codec) = PcmuCodec::try_from_sdp_session(sdp_session, config, rtp_stream)? {
+            let boxed: Box<dyn RTPCodec + Send> = Box::new(pcmu_codec);
+            codecs.push(boxed);
+        }
     }
 
     #[cfg(feature = "pcma")]
-    if let Some(pcma_codec) = PcmaCodec::try_from_sdp_session(sdp_session)? {
-        let boxed: Box<dyn RTPCodec + Send> = Box::new(pcma_codec);
-        codecs.push(boxed);
+    if allowed_codecs.contains(&CodecKind::Pcma) {
+        if let Some(pcma_codec) = PcmaCodec::try_from_sdp_session(sdp_session, config, rtp_stream)? {
+            let boxed: Box<dyn RTPCodec + Send> = Box::new(pcma_codec);
+            codecs.push(boxed);
+        }
     }
 
-    if let Some(telephone_events_codec) = TelephoneEventsCodec::try_from_sdp(sdp_session) {
+    #[cfg(feature = "ilbc")]
+    if allowed_codecs.contains(&CodecKind::Ilbc) {
+        if let Some(ilbc_codec) = IlbcCodec::try_from_sdp_session(sdp_session, config, rtp_stream)? {
+            let boxed: Box<dyn RTPCodec + Send> = Box::new(ilbc_codec);
+            codecs.push(boxed);
+        }
+    }
+
+    if let Some(telephone_events_codec) = TelephoneEventsCodec::try_from_sdp(sdp_session, rtp_stream) {
         let boxed: Box<dyn RTPCodec + Send> = Box::new(telephone_events_codec);
         codecs.push(boxed);
     }
 
+    if config.rtp_passthrough {
+        for media in sdp_session.media.iter().filter(|media| media.get_type() == &SdpMediaValue::Audio) {
+            for passthrough_codec in PassthroughCodec::from_sdp_media(media, config, rtp_stream) {
+                let boxed: Box<dyn RTPCodec + Send> = Box::new(passthrough_codec);
+                codecs.push(boxed);
+            }
+        }
+    }
+
     Ok(codecs)
 }
 
-pub fn populate_sdp_media_from_codecs(sdp_media: &mut SdpMedia) -> Result<()>
+/// Advertises the given codecs in the sdp media, in the given order.
+pub(crate) fn populate_sdp_media_from_codecs(sdp_media: &mut SdpMedia, config: &Config, codecs: &[CodecKind]) -> Result<()>
 {
-    #[cfg(feature = "opus")]
-    OpusCodec::populate_sdp_media(sdp_media)?;
-    #[cfg(feature = "pcmu")]
-    PcmuCodec::populate_sdp_media(sdp_media)?;
-    #[cfg(feature = "pcma")]
-    PcmaCodec::populate_sdp_media(sdp_media)?;
-    TelephoneEventsCodec::populate_sdp_media(sdp_media)?;
+    for codec in codecs {
+        match codec {
+            #[cfg(feature = "opus")]
+            CodecKind::Opus => OpusCodec::populate_sdp_media(sdp_media, config)?,
+            #[cfg(feature = "pcmu")]
+            CodecKind::Pcmu => PcmuCodec::populate_sdp_media(sdp_media, config)?,
+            #[cfg(feature = "pcma")]
+            CodecKind::Pcma => PcmaCodec::populate_sdp_media(sdp_media, config)?,
+            #[cfg(feature = "ilbc")]
+            CodecKind::Ilbc => IlbcCodec::populate_sdp_media(sdp_media, config)?,
+            #[allow(unreachable_patterns)]
+            _ => {}
+        }
+    }
+    TelephoneEventsCodec::populate_sdp_media(sdp_media, config)?;
+    ComfortNoiseCodec::populate_sdp_media(sdp_media, config)?;
 
     Ok(())
-}
\ No newline at end of file
+}
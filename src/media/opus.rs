@@ -1,32 +1,43 @@
-use crate::media::{RTPCodec};
+use crate::media::{get_sdp_max_bitrate, get_sdp_media_ptime, outgoing_audio, CodecInfo, RTPCodec, RtpStream};
+use crate::media::audio_buffer::AudioBuffer;
 use anyhow::Result;
 use bytes::Bytes;
-use opus::{Application, Channels, Decoder, Encoder};
+use opus::{Application, Bitrate, Channels, Decoder, Encoder};
 use rtp::codecs::opus::OpusPayloader;
 use rtp::packet::Packet;
-use rtp::packetizer::{new_packetizer, Packetizer};
+use rtp::packetizer::Packetizer;
 use webrtc_sdp::attribute_type::{SdpAttribute, SdpAttributeFmtp, SdpAttributeFmtpParameters, SdpAttributeRtpmap};
 use webrtc_sdp::media_type::{SdpMedia, SdpMediaValue};
 use webrtc_sdp::SdpSession;
 use crate::call::Media;
+use crate::config::Config;
+
+/// Opus frame durations in milliseconds, the shorter 2.5 and 5 ms frames are not used.
+const FRAME_DURATIONS: [u32; 4] = [10, 20, 40, 60];
+/// Lowest bitrate supported by the encoder in bits per second.
+const MIN_BITRATE: i32 = 6000;
 
 pub struct OpusCodec {
     ptime: u32,
 
     payload_type: u8,
     sample_rate: u32,
+    /// Sample rate of the audio exchanged with the call, the encoder and decoder run at this rate.
+    audio_rate: u32,
     channels: u8,
+    /// Channels of the encoded audio, stereo only if both sides asked for it.
+    encoder_channels: u8,
 
     decoder: Decoder,
     encoder: Encoder,
 
     packetizer: Box<dyn Packetizer + Send + Sync>,
 
-    buffer_out: Vec<f32>
+    buffer_out: AudioBuffer,
 }
 
 impl OpusCodec {
-    pub fn try_from_sdp_session(sdp_session: &SdpSession) -> Result<Option<Self>> {
+    pub fn try_from_sdp_session(sdp_session: &SdpSession, config: &Config, rtp_stream: &RtpStream) -> Result<Option<Self>> {
         for media in sdp_session.media.iter() {
             if media.get_type() != &SdpMediaValue::Audio  {
                 continue;
@@ -38,29 +49,44 @@ impl OpusCodec {
                         // TODO: Handle the fmtp params
 
                         let sample_rate = a.frequency;
-                        let channels = a.channels.unwrap_or(1) as u8;
-                        let channels_opus = match channels {
-                            2 => Channels::Stereo,
-                            _ => Channels::Mono
+                        let channels = config.audio_channels;
+                        let encoder_channels = if config.opus.channels == 2 && sdp_media_receives_stereo(media, a.payload_type) {
+                            2
+                        } else {
+                            1
                         };
+                        let audio_rate = config.audio_sample_rate;
+                        // Longest frame fitting in the requested packet duration
+                        let ptime = get_sdp_media_ptime(media);
+                        let ptime = FRAME_DURATIONS.into_iter().rev().find(|duration| *duration <= ptime).unwrap_or(FRAME_DURATIONS[0]);
+
+                        let mut encoder = Encoder::new(audio_rate, get_opus_channels(encoder_channels), Application::Voip)?;
+                        if let Some(bitrate) = config.opus.bitrate {
+                            encoder.set_bitrate(Bitrate::Bits(bitrate))?;
+                        }
+                        // The bitrate chosen by the encoder or the configured one is lowered to the bandwidth of the remote
+                        if let Some(max_bitrate) = get_sdp_max_bitrate(sdp_session, media, ptime) {
+                            let max_bitrate = (max_bitrate as i32).max(MIN_BITRATE);
+                            if matches!(encoder.get_bitrate()?, Bitrate::Bits(bitrate) if bitrate > max_bitrate) {
+                                encoder.set_bitrate(Bitrate::Bits(max_bitrate))?;
+                            }
+                        }
+                        encoder.set_inband_fec(config.opus.inband_fec)?;
+
                         let instance = Self {
-                            ptime: 20,
+                            ptime,
                             payload_type: a.payload_type,
                             sample_rate,
+                            audio_rate,
                             channels,
-                            decoder: Decoder::new(sample_rate, channels_opus)?,
-                            encoder:  Encoder::new(sample_rate, channels_opus, Application::Voip)?,
-
-                            packetizer: Box::new(new_packetizer(
-                                400,
-                                a.payload_type,
-                                rand::random::<u32>(),
-                                Box::new(OpusPayloader::default()),
-                                Box::new(rtp::sequence::new_random_sequencer()),
-                                a.frequency
-                            )),
-
-                            buffer_out: vec![],
+                            encoder_channels,
+                            // The decoder mixes the received audio to our number of channels
+                            decoder: Decoder::new(audio_rate, get_opus_channels(channels))?,
+                            encoder,
+
+                            packetizer: rtp_stream.new_packetizer(400, a.payload_type, Box::new(OpusPayloader::default()), a.frequency),
+
+                            buffer_out: AudioBuffer::new(config.max_audio_buffer, audio_rate, channels),
                         };
 
                         return Ok(Some(instance));
@@ -74,7 +100,7 @@ impl OpusCodec {
 }
 
 impl RTPCodec for OpusCodec {
-    fn populate_sdp_media(sdp_media: &mut SdpMedia) -> Result<()>
+    fn populate_sdp_media(sdp_media: &mut SdpMedia, config: &Config) -> Result<()>
     where
         Self: Sized
     {
@@ -84,6 +110,7 @@ impl RTPCodec for OpusCodec {
             frequency: 48000,
             channels: Some(2),
         })?;
+        let stereo = config.opus.channels == 2;
 
         sdp_media.add_attribute(SdpAttribute::Fmtp(SdpAttributeFmtp {
             payload_type: 107,
@@ -101,10 +128,10 @@ impl RTPCodec for OpusCodec {
                 level_idx: None,
                 tier: None,
                 maxplaybackrate: 48000,
-                maxaveragebitrate: 0,
-                usedtx: false,
-                stereo: false,
-                useinbandfec: true,
+                maxaveragebitrate: config.opus.bitrate.unwrap_or(0) as u32,
+                usedtx: config.opus.use_dtx,
+                stereo,
+                useinbandfec: config.opus.inband_fec,
                 cbr: false,
                 ptime: 0,
                 minptime: 0,
@@ -112,7 +139,7 @@ impl RTPCodec for OpusCodec {
                 encodings: vec![],
                 dtmf_tones: "".to_string(),
                 rtx: None,
-                unknown_tokens: vec![],
+                unknown_tokens: if stereo { vec!["sprop-stereo=1".to_string()] } else { vec![] },
             },
         }))?;
 
@@ -123,14 +150,19 @@ impl RTPCodec for OpusCodec {
         self.payload_type
     }
 
-    fn can_handle_media(&self, media: &Media) -> bool {
-        if let Media::Audio(_) = media {
-            return true
+    fn get_codec_info(&self) -> CodecInfo {
+        CodecInfo {
+            name: "opus".to_string(),
+            clock_rate: self.sample_rate,
+            payload_type: self.payload_type,
         }
-        false
     }
 
-    fn decode_payload(&mut self, payload: Bytes) -> Result<Option<Media>> {
+    fn can_handle_media(&self, media: &Media) -> bool {
+        matches!(media, Media::Audio(_) | Media::AudioI16 { .. })
+    }
+
+    fn decode_payload(&mut self, payload: Bytes, _timestamp: u32) -> Result<Option<Media>> {
         let payload = payload.to_vec();
         let nb_samples = self.decoder.get_nb_samples(payload.as_slice())? * self.channels as usize;
         let mut buffer = vec![0.0; nb_samples];
@@ -139,32 +171,57 @@ impl RTPCodec for OpusCodec {
         Ok(Some(Media::Audio(buffer)))
     }
 
-    fn append_to_buffer(&mut self, media: Media) -> Result<()> {
-        if let Media::Audio(mut buffer) = media {
-            self.buffer_out.append(&mut buffer);
+    fn get_ptime(&self) -> Option<u32> {
+        Some(self.ptime)
+    }
+
+    fn buffered_len(&self) -> usize {
+        self.buffer_out.len()
+    }
+
+    fn append_to_buffer(&mut self, media: Media) -> Result<bool> {
+        if let Some(buffer) = outgoing_audio(media, self.audio_rate, self.channels) {
+            return Ok(self.buffer_out.append(buffer));
         }
-        Ok(())
+        Ok(true)
     }
 
     fn get_next_packet(&mut self) -> Result<Vec<Packet>> {
         if self.buffer_out.is_empty() {
             return Ok(vec![]);
         }
-        let samples_count = (self.sample_rate / 1000 * self.ptime * self.channels as u32) as usize;
-
-        let take_length = if self.buffer_out.len() < samples_count {
-            self.buffer_out.len()
-        } else {
-            samples_count
-        };
+        let samples_count = (self.audio_rate / 1000 * self.ptime * self.channels as u32) as usize;
 
-        let mut samples = self.buffer_out.drain(0..take_length).collect::<Vec<_>>();
+        let mut samples = self.buffer_out.drain(samples_count);
         if samples.len() < samples_count  {
             samples.resize(samples_count, 0.0);
         }
+        let samples = convert_channels(samples, self.channels, self.encoder_channels);
         let payload = self.encoder.encode_vec_float(samples.as_slice(), samples.len())?;
-        let packets = self.packetizer.packetize(&Bytes::from(payload), samples_count as u32)?;
+        // The RTP clock counts samples per channel
+        let packets = self.packetizer.packetize(&Bytes::from(payload), self.sample_rate / 1000 * self.ptime)?;
 
         Ok(packets)
     }
-}
\ No newline at end of file
+}
+
+fn get_opus_channels(channels: u8) -> Channels {
+    match channels {
+        2 => Channels::Stereo,
+        _ => Channels::Mono,
+    }
+}
+
+/// Returns `true` if the media asks to receive stereo with `stereo=1` in the fmtp of the payload type (RFC 7587).
+fn sdp_media_receives_stereo(media: &SdpMedia, payload_type: u8) -> bool {
+    media.get_attributes().iter().any(|attr| matches!(attr, SdpAttribute::Fmtp(fmtp) if fmtp.payload_type == payload_type && fmtp.parameters.stereo))
+}
+
+/// Converts interleaved samples between mono and stereo, mixing down by averaging the channels.
+fn convert_channels(samples: Vec<f32>, from_channels: u8, to_channels: u8) -> Vec<f32> {
+    match (from_channels, to_channels) {
+        (2, 1) => samples.chunks(2).map(|frame| frame.iter().sum::<f32>() / frame.len() as f32).collect(),
+        (1, 2) => samples.into_iter().flat_map(|sample| [sample, sample]).collect(),
+        _ => samples,
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::VecDeque;
+use anyhow::Result;
+use bytes::Bytes;
+use rtp::codecs::g7xx::G711Payloader;
+use rtp::packet::Packet;
+use rtp::packetizer::Packetizer;
+use webrtc_sdp::attribute_type::{SdpAttribute, SdpAttributeRtpmap};
+use webrtc_sdp::media_type::SdpMedia;
+use crate::call::Media;
+use crate::config::Config;
+use crate::media::{get_sdp_media_ptime, CodecInfo, CodecKind, RTPCodec, RtpStream};
+
+/// Returns the codecs of the sdp media the crate can not decode, passed through when [Config::rtp_passthrough] is enabled.
+/// Telephone events and comfort noise are handled by the crate and never passed through.
+pub(crate) fn get_passthrough_rtpmaps(sdp_media: &SdpMedia) -> Vec<SdpAttributeRtpmap> {
+    sdp_media.get_attributes().iter().filter_map(|attr| match attr {
+        SdpAttribute::Rtpmap(rtpmap) if is_passthrough_codec(&rtpmap.codec_name) => Some(rtpmap.clone()),
+        _ => None,
+    }).collect()
+}
+
+fn is_passthrough_codec(codec_name: &str) -> bool {
+    !codec_name.eq_ignore_ascii_case("telephone-event")
+        && !codec_name.eq_ignore_ascii_case("cn")
+        && !CodecKind::from_name(codec_name).is_some_and(|codec| codec.is_enabled())
+}
+
+/// Codec the crate can not decode, its payloads are received and sent as is in [Media::RawPayload].
+pub(crate) struct PassthroughCodec {
+    payload_type: u8,
+    codec_name: String,
+    clock_rate: u32,
+    ptime: u32,
+    packetizer: Box<dyn Packetizer + Send + Sync>,
+    /// Payloads waiting to be sent, one per packet.
+    buffer: VecDeque<Vec<u8>>,
+    /// Maximum number of buffered payloads, from [Config::max_audio_buffer].
+    max_buffer: Option<usize>,
+}
+
+impl PassthroughCodec {
+    /// Returns a codec for each codec of the sdp media the crate can not decode.
+    pub fn from_sdp_media(sdp_media: &SdpMedia, config: &Config, rtp_stream: &RtpStream) -> Vec<PassthroughCodec> {
+        let ptime = get_sdp_media_ptime(sdp_media);
+        get_passthrough_rtpmaps(sdp_media).into_iter().map(|rtpmap| PassthroughCodec {
+            payload_type: rtpmap.payload_type,
+            codec_name: rtpmap.codec_name,
+            clock_rate: rtpmap.frequency,
+            ptime,
+            packetizer: rtp_stream.new_packetizer(1200, rtpmap.payload_type, Box::new(G711Payloader::default()), rtpmap.frequency),
+            buffer: VecDeque::new(),
+            max_buffer: config.max_audio_buffer.map(|max| (max.as_millis() as usize / ptime.max(1) as usize).max(1)),
+        }).collect()
+    }
+}
+
+impl RTPCodec for PassthroughCodec {
+    fn populate_sdp_media(_sdp_media: &mut SdpMedia, _config: &Config) -> Result<()>
+    where
+        Self: Sized
+    {
+        // Only answered, with the codecs of the offer
+        Ok(())
+    }
+
+    fn get_payload_type(&self) -> u8 {
+        self.payload_type
+    }
+
+    fn get_codec_info(&self) -> CodecInfo {
+        CodecInfo {
+            name: self.codec_name.clone(),
+            clock_rate: self.clock_rate,
+            payload_type: self.payload_type,
+        }
+    }
+
+    fn can_handle_media(&self, media: &Media) -> bool {
+        matches!(media, Media::RawPayload { payload_type, .. } if *payload_type == self.payload_type)
+    }
+
+    fn decode_payload(&mut self, payload: Bytes, _timestamp: u32) -> Result<Option<Media>> {
+        Ok(Some(Media::RawPayload {
+            payload_type: self.payload_type,
+            payload: payload.to_vec(),
+        }))
+    }
+
+    fn append_to_buffer(&mut self, media: Media) -> Result<bool> {
+        if let Media::RawPayload { payload, .. } = media {
+            if self.max_buffer.is_some_and(|max| self.buffer.len() >= max) {
+                return Ok(false);
+            }
+            self.buffer.push_back(payload);
+        }
+        Ok(true)
+    }
+
+    fn get_next_packet(&mut self) -> Result<Vec<Packet>> {
+        let Some(payload) = self.buffer.pop_front() else {
+            return Ok(Vec::new());
+        };
+        Ok(self.packetizer.packetize(&Bytes::from(payload), self.clock_rate / 1000 * self.ptime)?)
+    }
+}
@@ -1,13 +1,16 @@
-use crate::media::RTPCodec;
+use crate::media::{get_sdp_media_ptime, outgoing_audio, CodecInfo, RTPCodec, RtpStream};
+use crate::media::audio_buffer::AudioBuffer;
+use crate::media::comfort_noise::sdp_media_has_comfort_noise;
+use crate::media::plc::PacketLossConcealment;
+use crate::media::resample::{resample_i16, PacketResampler};
 use crate::call::Media;
+use crate::config::Config;
 use anyhow::Result;
 use bytes::Bytes;
-use fon::chan::Channel;
-use fon::Audio;
 use rtp::codecs::g7xx::G711Payloader;
 use rtp::packet::Packet;
-use rtp::packetizer::{new_packetizer, Packetizer};
-use webrtc_sdp::attribute_type::{SdpAttribute, SdpAttributeRtpmap, SdpAttributeType};
+use rtp::packetizer::Packetizer;
+use webrtc_sdp::attribute_type::{SdpAttribute, SdpAttributeRtpmap};
 use webrtc_sdp::media_type::{SdpMedia, SdpMediaValue};
 use webrtc_sdp::SdpSession;
 
@@ -62,43 +65,46 @@ pub struct PcmuCodec {
     ptime: u32,
     payload_type: u8,
     sample_rate: u32,
+    /// Sample rate of the audio exchanged with the call.
+    audio_rate: u32,
+    channels: u8,
+    /// Comfort noise was negotiated, silence is not sent.
+    comfort_noise: bool,
 
     packetizer: Box<dyn Packetizer + Send + Sync>,
 
-    buffer_out: Vec<f32>,
+    buffer_out: AudioBuffer,
+    /// Outgoing mono `i16` audio already at the codec sample rate, encoded without conversion.
+    buffer_native: AudioBuffer<i16>,
+    resampler: PacketResampler,
+    plc: PacketLossConcealment,
 }
 
 impl PcmuCodec {
-    pub fn try_from_sdp_session(sdp_session: &SdpSession) -> Result<Option<Self>> {
+    pub fn try_from_sdp_session(sdp_session: &SdpSession, config: &Config, rtp_stream: &RtpStream) -> Result<Option<Self>> {
         for media in sdp_session.media.iter() {
             if media.get_type() != &SdpMediaValue::Audio {
                 continue;
             }
 
-            let ptime = media.get_attribute(SdpAttributeType::Ptime).unwrap_or(&SdpAttribute::Ptime(20));
-            let ptime = if let SdpAttribute::Ptime(ptime) = ptime {
-                *ptime
-            } else {
-                20
-            };
+            let ptime = get_sdp_media_ptime(media);
 
             for attr in media.get_attributes().iter() {
                 if let SdpAttribute::Rtpmap(a) = attr {
                     if a.codec_name.to_lowercase().as_str() == "pcmu" {
                         let instance = PcmuCodec {
-                            ptime: ptime as u32,
+                            ptime,
                             payload_type: a.payload_type,
                             sample_rate: a.frequency,
-
-                            packetizer: Box::new(new_packetizer(
-                                300,
-                                a.payload_type,
-                                rand::random::<u32>(),
-                                Box::new(G711Payloader::default()),
-                                Box::new(rtp::sequence::new_random_sequencer()),
-                                a.frequency,
-                            )),
-                            buffer_out: Vec::new(),
+                            audio_rate: config.audio_sample_rate,
+                            channels: config.audio_channels,
+                            comfort_noise: config.comfort_noise && sdp_media_has_comfort_noise(media),
+
+                            packetizer: rtp_stream.new_packetizer(1200, a.payload_type, Box::new(G711Payloader::default()), a.frequency),
+                            buffer_out: AudioBuffer::new(config.max_audio_buffer, config.audio_sample_rate, config.audio_channels),
+                            buffer_native: AudioBuffer::new(config.max_audio_buffer, a.frequency, 1),
+                            resampler: PacketResampler::new(a.frequency, config.audio_sample_rate, config.audio_channels),
+                            plc: PacketLossConcealment::new(a.frequency),
                         };
 
                         return Ok(Some(instance));
@@ -111,7 +117,7 @@ impl PcmuCodec {
 }
 
 impl RTPCodec for PcmuCodec {
-    fn populate_sdp_media(sdp_media: &mut SdpMedia) -> Result<()>
+    fn populate_sdp_media(sdp_media: &mut SdpMedia, _config: &Config) -> Result<()>
     where
         Self: Sized
     {
@@ -129,57 +135,116 @@ impl RTPCodec for PcmuCodec {
         self.payload_type
     }
 
-    fn can_handle_media(&self, media: &Media) -> bool {
-        if let Media::Audio(_) = media {
-            return true;
+    fn get_codec_info(&self) -> CodecInfo {
+        CodecInfo {
+            name: "PCMU".to_string(),
+            clock_rate: self.sample_rate,
+            payload_type: self.payload_type,
         }
-        false
     }
 
-    fn decode_payload(&mut self, payload: Bytes) -> Result<Option<Media>> {
+    fn can_handle_media(&self, media: &Media) -> bool {
+        matches!(media, Media::Audio(_) | Media::AudioI16 { .. })
+    }
+
+    fn decode_payload(&mut self, payload: Bytes, _timestamp: u32) -> Result<Option<Media>> {
         let audio = payload
             .into_iter()
             .map(|i| decode(i))
             .collect::<Vec<_>>();
-        let audio = Audio::<fon::chan::Ch16, 1>::with_i16_buffer(self.sample_rate, audio);
-
-        let audio = Audio::<fon::chan::Ch32, 2>::with_audio(48000, &audio)
-            .iter()
-            .flat_map(|i| [i.channels()[0].to_f32(), i.channels()[1].to_f32()])
-            .collect::<Vec<_>>();
+        self.plc.record(&audio);
+        let audio = self.resampler.resample_decoded(audio);
 
         Ok(Some(Media::Audio(audio)))
     }
 
-    fn append_to_buffer(&mut self, media: Media) -> Result<()> {
-        if self.buffer_out.len() > 5000 {
-            return Ok(());
+    fn conceal_lost_packets(&mut self, lost_packets: u16) -> Option<Media> {
+        let audio = self.plc.conceal(lost_packets);
+        if audio.is_empty() {
+            return None;
         }
-        if let Media::Audio(mut buffer) = media {
-            self.buffer_out.append(&mut buffer);
+        Some(Media::Audio(self.resampler.resample_decoded(audio)))
+    }
+
+    fn get_ptime(&self) -> Option<u32> {
+        Some(self.ptime)
+    }
+
+    fn buffered_len(&self) -> usize {
+        // Counted as samples at the audio sample rate like the rest of the outgoing audio
+        self.buffer_out.len() + self.buffer_native.len() * self.audio_rate as usize / self.sample_rate as usize * self.channels as usize
+    }
+
+    fn append_to_buffer(&mut self, media: Media) -> Result<bool> {
+        match media {
+            Media::AudioI16 { samples, sample_rate, channels: 1 } if sample_rate == self.sample_rate && self.buffer_out.is_empty() => {
+                Ok(self.buffer_native.append(samples))
+            }
+            media => {
+                // Converts the native audio first to keep the audio in order
+                if !self.buffer_native.is_empty() {
+                    let native = self.buffer_native.drain(self.buffer_native.len());
+                    self.buffer_out.append(resample_i16(native, self.sample_rate, self.audio_rate, 1, self.channels));
+                }
+                match outgoing_audio(media, self.audio_rate, self.channels) {
+                    Some(buffer) => Ok(self.buffer_out.append(buffer)),
+                    None => Ok(true),
+                }
+            }
         }
-        Ok(())
     }
 
     fn get_next_packet(&mut self) -> Result<Vec<Packet>> {
-        let samples_count = (48000 / 1000 * self.ptime * 2) as usize;
-        let take_length = if self.buffer_out.len() < samples_count {
-            self.buffer_out.len()
-        } else {
-            samples_count
-        };
-
-        let mut samples = self.buffer_out.drain(0..take_length).collect::<Vec<_>>();
-        if samples.len() < samples_count {
-            samples.extend(std::iter::repeat(0.0).take(take_length - samples.len()));
+        if self.comfort_noise && self.buffer_out.is_empty() && self.buffer_native.is_empty() {
+            return Ok(vec![]);
         }
 
-        let audio = Audio::<fon::chan::Ch32, 2>::with_f32_buffer(48000, samples);
-        let audio = Audio::<fon::chan::Ch16, 1>::with_audio(self.sample_rate, &audio)
-            .iter()
-            .map(|i| encode(i.channels()[0].into()))
+        let audio = if !self.buffer_native.is_empty() {
+            let samples_count = (self.sample_rate / 1000 * self.ptime) as usize;
+            let mut samples = self.buffer_native.drain(samples_count);
+            samples.resize(samples_count, 0);
+            samples
+        } else {
+            let samples_count = (self.audio_rate / 1000 * self.ptime * self.channels as u32) as usize;
+            let mut samples = self.buffer_out.drain(samples_count);
+            if samples.len() < samples_count {
+                samples.resize(samples_count, 0.0);
+            }
+            self.resampler.resample_for_encoding(samples)
+        };
+        let audio = audio
+            .into_iter()
+            .map(encode)
             .collect::<Vec<_>>();
-        let packets = self.packetizer.packetize(&Bytes::from(audio), self.sample_rate)?;
+        let packets = self.packetizer.packetize(&Bytes::from(audio), self.sample_rate / 1000 * self.ptime)?;
         Ok(packets)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use crate::media::CodecKind;
+    use crate::call::MediaDirection;
+    use crate::sip_proto::sdp::generate_sdp_new;
+
+    fn new_codec() -> PcmuCodec {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 5060));
+        let config = Config::builder().server(addr).own_addr(addr).credentials("1000", "").build().unwrap();
+        let sdp = generate_sdp_new(&config, SocketAddr::from(([127, 0, 0, 1], 20000)), &[CodecKind::Pcmu], MediaDirection::SendRecv).unwrap();
+        PcmuCodec::try_from_sdp_session(&sdp, &config, &RtpStream::new(1, 1)).unwrap().unwrap()
+    }
+
+    #[test]
+    fn pads_partially_filled_buffer_to_a_full_packet() {
+        let mut codec = new_codec();
+
+        // 5 ms of 48 kHz stereo audio, a packet holds 20 ms
+        codec.append_to_buffer(Media::Audio(vec![0.5; 480])).unwrap();
+        let packets = codec.get_next_packet().unwrap();
+
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].payload.len(), 160);
+    }
+}
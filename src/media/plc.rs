@@ -0,0 +1,42 @@
+/// Longest concealed audio in milliseconds, the audio then stays silent until the next packet.
+const MAX_CONCEALMENT_MS: u32 = 60;
+
+/// Packet loss concealment for codecs decoding to mono `i16` samples.
+///
+/// Lost packets are replaced by the last received frame, faded out over [MAX_CONCEALMENT_MS].
+pub struct PacketLossConcealment {
+    sample_rate: u32,
+    last_frame: Vec<i16>,
+}
+
+impl PacketLossConcealment {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            last_frame: Vec::new(),
+        }
+    }
+
+    /// Keeps the last decoded frame, repeated when the next packets are lost.
+    pub fn record(&mut self, frame: &[i16]) {
+        self.last_frame.clear();
+        self.last_frame.extend_from_slice(frame);
+    }
+
+    /// Returns the audio replacing `lost_packets` packets, empty if no frame was received yet.
+    pub fn conceal(&mut self, lost_packets: u16) -> Vec<i16> {
+        if self.last_frame.is_empty() {
+            return Vec::new();
+        }
+        let fade_len = (self.sample_rate / 1000 * MAX_CONCEALMENT_MS) as usize;
+        let len = (self.last_frame.len() * lost_packets as usize).min(fade_len);
+
+        let concealed = (0..len).map(|i| {
+            let gain = 1.0 - i as f32 / fade_len as f32;
+            (self.last_frame[i % self.last_frame.len()] as f32 * gain) as i16
+        }).collect();
+        // Only the start of a loss is concealed
+        self.last_frame.clear();
+        concealed
+    }
+}
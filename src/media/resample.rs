@@ -0,0 +1,182 @@
+#[cfg(any(feature = "pcmu", feature = "pcma", feature = "ilbc"))]
+use fon::chan::Ch16;
+use fon::chan::{Ch32, Channel};
+use fon::Audio;
+#[cfg(all(feature = "rubato", any(feature = "pcmu", feature = "pcma", feature = "ilbc")))]
+use rubato::{FftFixedInOut, Resampler};
+
+/// Converts mono `i16` samples at `sample_rate` to interleaved `f32` samples at `audio_rate` with the given amount of channels.
+#[cfg(any(feature = "pcmu", feature = "pcma", feature = "ilbc"))]
+pub fn resample_from_mono_i16(samples: Vec<i16>, sample_rate: u32, audio_rate: u32, channels: u8) -> Vec<f32> {
+    let audio = Audio::<Ch16, 1>::with_i16_buffer(sample_rate, samples);
+
+    match channels {
+        1 => Audio::<Ch32, 1>::with_audio(audio_rate, &audio)
+            .iter()
+            .map(|i| i.channels()[0].to_f32())
+            .collect(),
+        _ => Audio::<Ch32, 2>::with_audio(audio_rate, &audio)
+            .iter()
+            .flat_map(|i| [i.channels()[0].to_f32(), i.channels()[1].to_f32()])
+            .collect(),
+    }
+}
+
+/// Converts interleaved `f32` samples at `audio_rate` with the given amount of channels to mono `i16` samples at `sample_rate`.
+#[cfg(any(feature = "pcmu", feature = "pcma", feature = "ilbc"))]
+pub fn resample_to_mono_i16(samples: Vec<f32>, audio_rate: u32, sample_rate: u32, channels: u8) -> Vec<i16> {
+    match channels {
+        1 => {
+            let audio = Audio::<Ch32, 1>::with_f32_buffer(audio_rate, samples);
+            Audio::<Ch16, 1>::with_audio(sample_rate, &audio)
+                .iter()
+                .map(|i| i.channels()[0].into())
+                .collect()
+        }
+        _ => {
+            let audio = Audio::<Ch32, 2>::with_f32_buffer(audio_rate, samples);
+            Audio::<Ch16, 1>::with_audio(sample_rate, &audio)
+                .iter()
+                .map(|i| i.channels()[0].into())
+                .collect()
+        }
+    }
+}
+
+/// Converts `f32` samples at `sample_rate` with `from_channels` channels to interleaved `f32` samples at `audio_rate` with `to_channels` channels.
+pub fn resample_f32(samples: Vec<f32>, sample_rate: u32, audio_rate: u32, from_channels: u8, to_channels: u8) -> Vec<f32> {
+    match (from_channels, to_channels) {
+        (1, 1) => {
+            let audio = Audio::<Ch32, 1>::with_f32_buffer(sample_rate, samples);
+            Audio::<Ch32, 1>::with_audio(audio_rate, &audio)
+                .iter()
+                .map(|i| i.channels()[0].to_f32())
+                .collect()
+        }
+        (1, _) => {
+            let audio = Audio::<Ch32, 1>::with_f32_buffer(sample_rate, samples);
+            Audio::<Ch32, 2>::with_audio(audio_rate, &audio)
+                .iter()
+                .flat_map(|i| [i.channels()[0].to_f32(), i.channels()[1].to_f32()])
+                .collect()
+        }
+        (_, 1) => {
+            let audio = Audio::<Ch32, 2>::with_f32_buffer(sample_rate, samples);
+            Audio::<Ch32, 1>::with_audio(audio_rate, &audio)
+                .iter()
+                .map(|i| i.channels()[0].to_f32())
+                .collect()
+        }
+        _ => {
+            let audio = Audio::<Ch32, 2>::with_f32_buffer(sample_rate, samples);
+            Audio::<Ch32, 2>::with_audio(audio_rate, &audio)
+                .iter()
+                .flat_map(|i| [i.channels()[0].to_f32(), i.channels()[1].to_f32()])
+                .collect()
+        }
+    }
+}
+
+/// Converts `i16` samples at `sample_rate` with `from_channels` channels to interleaved `f32` samples at `audio_rate` with `to_channels` channels.
+#[cfg(any(feature = "opus", feature = "pcmu", feature = "pcma", feature = "ilbc"))]
+pub fn resample_i16(samples: Vec<i16>, sample_rate: u32, audio_rate: u32, from_channels: u8, to_channels: u8) -> Vec<f32> {
+    let samples = samples.into_iter().map(|i| i as f32 / 32768.0).collect();
+    if sample_rate == audio_rate && from_channels == to_channels {
+        return samples;
+    }
+    resample_f32(samples, sample_rate, audio_rate, from_channels, to_channels)
+}
+
+/// Resamples the audio of a codec one packet at a time, between mono `i16` samples at the codec sample rate
+/// and interleaved `f32` samples at the audio sample rate of the calls.
+///
+/// With the `rubato` feature, a band-limited FFT resampler keeping its state between packets is used,
+/// otherwise falls back to [resample_from_mono_i16] and [resample_to_mono_i16].
+/// No resampling happens when both rates match.
+#[cfg(any(feature = "pcmu", feature = "pcma", feature = "ilbc"))]
+pub struct PacketResampler {
+    sample_rate: u32,
+    audio_rate: u32,
+    channels: u8,
+    #[cfg(feature = "rubato")]
+    decoder: Option<FftFixedInOut<f32>>,
+    #[cfg(feature = "rubato")]
+    encoder: Option<FftFixedInOut<f32>>,
+}
+
+#[cfg(any(feature = "pcmu", feature = "pcma", feature = "ilbc"))]
+impl PacketResampler {
+    pub fn new(sample_rate: u32, audio_rate: u32, channels: u8) -> Self {
+        PacketResampler {
+            sample_rate,
+            audio_rate,
+            channels,
+            #[cfg(feature = "rubato")]
+            decoder: None,
+            #[cfg(feature = "rubato")]
+            encoder: None,
+        }
+    }
+
+    /// Converts decoded mono `i16` samples to interleaved `f32` samples at the audio sample rate.
+    pub fn resample_decoded(&mut self, samples: Vec<i16>) -> Vec<f32> {
+        if self.sample_rate == self.audio_rate {
+            return resample_i16(samples, self.sample_rate, self.audio_rate, 1, self.channels);
+        }
+        #[cfg(feature = "rubato")]
+        {
+            let mono = samples.iter().map(|i| *i as f32 / 32768.0).collect();
+            if let Some(audio) = process_chunk(&mut self.decoder, self.sample_rate, self.audio_rate, mono) {
+                return match self.channels {
+                    1 => audio,
+                    _ => audio.into_iter().flat_map(|i| [i, i]).collect(),
+                };
+            }
+        }
+        resample_from_mono_i16(samples, self.sample_rate, self.audio_rate, self.channels)
+    }
+
+    /// Converts interleaved `f32` samples at the audio sample rate to mono `i16` samples to encode.
+    pub fn resample_for_encoding(&mut self, samples: Vec<f32>) -> Vec<i16> {
+        if self.sample_rate == self.audio_rate {
+            return to_mono(samples, self.channels).into_iter().map(to_i16).collect();
+        }
+        #[cfg(feature = "rubato")]
+        {
+            if let Some(audio) = process_chunk(&mut self.encoder, self.audio_rate, self.sample_rate, to_mono(samples.clone(), self.channels)) {
+                return audio.into_iter().map(to_i16).collect();
+            }
+        }
+        resample_to_mono_i16(samples, self.audio_rate, self.sample_rate, self.channels)
+    }
+}
+
+/// Mixes interleaved samples down to mono by averaging the channels.
+#[cfg(any(feature = "pcmu", feature = "pcma", feature = "ilbc"))]
+fn to_mono(samples: Vec<f32>, channels: u8) -> Vec<f32> {
+    match channels {
+        1 => samples,
+        _ => samples.chunks(2).map(|i| i.iter().sum::<f32>() / i.len() as f32).collect(),
+    }
+}
+
+#[cfg(any(feature = "pcmu", feature = "pcma", feature = "ilbc"))]
+fn to_i16(sample: f32) -> i16 {
+    (sample * 32768.0).clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+/// Resamples a chunk of mono audio, the resampler is recreated when the chunk size changes.
+///
+/// Returns `None` when the chunk cannot be resampled at once, i.e. when its size is not a multiple of the ratio of the sample rates.
+#[cfg(all(feature = "rubato", any(feature = "pcmu", feature = "pcma", feature = "ilbc")))]
+fn process_chunk(resampler: &mut Option<FftFixedInOut<f32>>, rate_in: u32, rate_out: u32, samples: Vec<f32>) -> Option<Vec<f32>> {
+    if samples.is_empty() {
+        return Some(samples);
+    }
+    if resampler.as_ref().is_none_or(|r| r.input_frames_next() != samples.len()) {
+        *resampler = FftFixedInOut::new(rate_in as usize, rate_out as usize, samples.len(), 1)
+            .ok()
+            .filter(|r| r.input_frames_next() == samples.len());
+    }
+    resampler.as_mut()?.process(&[samples], None).ok()?.pop()
+}
@@ -1,15 +1,20 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
+use crate::logging::debug;
+use rtp::codecs::g7xx::G711Payloader;
 use rtp::packet::Packet;
+use rtp::packetizer::Packetizer;
 use webrtc_sdp::attribute_type::{SdpAttribute, SdpAttributeFmtp, SdpAttributeFmtpParameters, SdpAttributeRtpmap};
 use webrtc_sdp::media_type::{SdpMedia, SdpMediaValue};
 use webrtc_sdp::SdpSession;
 use crate::call::Media;
-use crate::media::RTPCodec;
+use crate::config::Config;
+use crate::media::{get_sdp_media_ptime, CodecInfo, RTPCodec, RtpStream};
 
 #[repr(u8)]
-#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum TelephoneEvent {
     Zero = 0,
     One = 1,
@@ -30,6 +35,33 @@ pub enum TelephoneEvent {
 }
 
 impl TelephoneEvent {
+    /// Returns the digit as a character, `'*'` and `'#'` for [TelephoneEvent::Star] and [TelephoneEvent::Hash].
+    pub fn as_char(&self) -> char {
+        match self {
+            TelephoneEvent::Star => '*',
+            TelephoneEvent::Hash => '#',
+            TelephoneEvent::A => 'A',
+            TelephoneEvent::B => 'B',
+            TelephoneEvent::C => 'C',
+            TelephoneEvent::D => 'D',
+            digit => (b'0' + *digit as u8) as char,
+        }
+    }
+
+    /// Parses a digit from its character, the reverse of [TelephoneEvent::as_char]. Letters are case insensitive.
+    pub fn try_from_char(c: char) -> Result<Self> {
+        match c.to_ascii_uppercase() {
+            '*' => Ok(TelephoneEvent::Star),
+            '#' => Ok(TelephoneEvent::Hash),
+            'A' => Ok(TelephoneEvent::A),
+            'B' => Ok(TelephoneEvent::B),
+            'C' => Ok(TelephoneEvent::C),
+            'D' => Ok(TelephoneEvent::D),
+            c @ '0'..='9' => Self::try_from_byte(&(c as u8 - b'0')),
+            _ => Err(anyhow!("Invalid DTMF digit {:?}", c)),
+        }
+    }
+
     pub fn try_from_byte(b: &u8) -> Result<Self> {
         match b {
             0 => Ok(TelephoneEvent::Zero),
@@ -53,13 +85,58 @@ impl TelephoneEvent {
     }
 }
 
-pub struct TelephoneEventsCodec {
+/// A key is released when no packet was received for its press in this time, in case all end packets were lost.
+const STALE_KEY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Volume of the sent events, in -dBm0.
+const EVENT_VOLUME: u8 = 10;
+/// The end packet of an event is sent three times in case some are lost (RFC 4733 2.5.1.4).
+const END_PACKETS: usize = 3;
+
+struct PressedKey {
+    /// RTP timestamp identifying the press.
+    timestamp: u32,
+    last_packet_at: Instant,
+}
+
+pub(crate) struct TelephoneEventsCodec {
     payload_type: u8,
-    pressed_keys: HashSet<TelephoneEvent>,
+    pressed_keys: HashMap<TelephoneEvent, PressedKey>,
+    /// RTP timestamp of the last ended press of each key, to ignore its remaining packets.
+    ended_keys: HashMap<TelephoneEvent, u32>,
+    /// Events to report on the next poll.
+    pending_media: Vec<Media>,
+
+    clock_rate: u32,
+    /// Samples of the clock rate in each packet interval.
+    ptime_samples: u32,
+    packetizer: Box<dyn Packetizer + Send + Sync>,
+    /// Digits waiting to be sent, with their duration and the silence after them in samples.
+    send_queue: VecDeque<(TelephoneEvent, u32, u32)>,
+    /// Digit being sent.
+    sending: Option<SendingEvent>,
+    /// Samples of silence left before the next digit.
+    gap_left: u32,
+}
+
+struct SendingEvent {
+    event: TelephoneEvent,
+    /// Samples of the event sent so far.
+    elapsed: u32,
+    duration: u32,
+    gap: u32,
+}
+
+/// Returns `true` if telephone events are advertised in the audio media of the session.
+pub(crate) fn sdp_has_telephone_events(sdp_session: &SdpSession) -> bool {
+    sdp_session.media.iter()
+        .filter(|media| media.get_type() == &SdpMediaValue::Audio)
+        .flat_map(|media| media.get_attributes())
+        .any(|attr| matches!(attr, SdpAttribute::Rtpmap(rtpmap) if rtpmap.codec_name.eq_ignore_ascii_case("telephone-event")))
 }
 
 impl TelephoneEventsCodec {
-    pub fn try_from_sdp(sdp_session: &SdpSession) -> Option<TelephoneEventsCodec> {
+    pub fn try_from_sdp(sdp_session: &SdpSession, rtp_stream: &RtpStream) -> Option<TelephoneEventsCodec> {
         for md in sdp_session.media.iter() {
             if md.get_type() != &SdpMediaValue::Audio {
                 continue;
@@ -70,7 +147,15 @@ impl TelephoneEventsCodec {
                         return Some(
                             TelephoneEventsCodec {
                                 payload_type: attr.payload_type,
-                                pressed_keys: HashSet::new()
+                                pressed_keys: HashMap::new(),
+                                ended_keys: HashMap::new(),
+                                pending_media: Vec::new(),
+                                clock_rate: attr.frequency,
+                                ptime_samples: attr.frequency / 1000 * get_sdp_media_ptime(md),
+                                packetizer: rtp_stream.new_packetizer(300, attr.payload_type, Box::new(G711Payloader::default()), attr.frequency),
+                                send_queue: VecDeque::new(),
+                                sending: None,
+                                gap_left: 0,
                             }
                         )
                     }
@@ -79,10 +164,14 @@ impl TelephoneEventsCodec {
         }
         None
     }
+
+    fn duration_samples(&self, duration: Duration) -> u32 {
+        (duration.as_millis() as u64 * self.clock_rate as u64 / 1000).min(u32::MAX as u64) as u32
+    }
 }
 
 impl RTPCodec for TelephoneEventsCodec {
-    fn populate_sdp_media(sdp_media: &mut SdpMedia) -> Result<()>
+    fn populate_sdp_media(sdp_media: &mut SdpMedia, _config: &Config) -> Result<()>
     where
         Self: Sized
     {
@@ -131,38 +220,219 @@ impl RTPCodec for TelephoneEventsCodec {
         self.payload_type
     }
 
-    fn can_handle_media(&self, media: &Media) -> bool {
-        if let Media::TelephoneEvent(_) = media {
-            return true;
+    fn get_codec_info(&self) -> CodecInfo {
+        CodecInfo {
+            name: "telephone-event".to_string(),
+            clock_rate: 8000,
+            payload_type: self.payload_type,
         }
-        false
     }
 
-    fn decode_payload(&mut self, payload: Bytes) -> Result<Option<Media>> {
+    fn can_handle_media(&self, media: &Media) -> bool {
+        matches!(media, Media::DtmfDigit { .. })
+    }
+
+    fn decode_payload(&mut self, payload: Bytes, timestamp: u32) -> Result<Option<Media>> {
         let event = TelephoneEvent::try_from_byte(
-            payload.get(0).ok_or(anyhow!("Invalid main body"))?
+            payload.first().ok_or(anyhow!("Invalid main body"))?
         )?;
         let end = payload.get(1).ok_or(anyhow!("Invalid end"))? & 0b1000_0000 != 0;
 
-        if !end && self.pressed_keys.contains(&event) {
-            return Ok(None)
+        // Packets of a press share its RTP timestamp, the end packet is sent three times
+        if self.ended_keys.get(&event) == Some(&timestamp) {
+            return Ok(None);
+        }
+
+        let mut media = Vec::new();
+        if self.pressed_keys.get(&event).is_some_and(|pressed| pressed.timestamp != timestamp) {
+            // New press of the same key, all end packets of the previous press were lost
+            self.pressed_keys.remove(&event);
+            media.push(Media::TelephoneEvent((event, true)));
         }
+
         if end {
             self.pressed_keys.remove(&event);
+            self.ended_keys.insert(event, timestamp);
+            media.push(Media::TelephoneEvent((event, true)));
+        } else if let Some(pressed) = self.pressed_keys.get_mut(&event) {
+            pressed.last_packet_at = Instant::now();
         } else {
-            self.pressed_keys.insert(event.clone());
+            self.pressed_keys.insert(event, PressedKey { timestamp, last_packet_at: Instant::now() });
+            media.push(Media::TelephoneEvent((event, false)));
         }
 
-        Ok(Some(Media::TelephoneEvent((event, end))))
+        let mut media = media.into_iter();
+        let first = media.next();
+        self.pending_media.extend(media);
+        Ok(first)
     }
 
-    fn append_to_buffer(&mut self, _: Media) -> Result<()> {
-        // TODO: Handle sending of telephone events
-        Ok(())
+    fn poll_media(&mut self) -> Vec<Media> {
+        let stale_keys: Vec<TelephoneEvent> = self.pressed_keys
+            .iter()
+            .filter(|(_, pressed)| pressed.last_packet_at.elapsed() > STALE_KEY_TIMEOUT)
+            .map(|(event, _)| *event)
+            .collect();
+        for event in stale_keys {
+            if let Some(pressed) = self.pressed_keys.remove(&event) {
+                debug!("No end received for telephone event {:?}, releasing it", event);
+                self.ended_keys.insert(event, pressed.timestamp);
+                self.pending_media.push(Media::TelephoneEvent((event, true)));
+            }
+        }
+        std::mem::take(&mut self.pending_media)
+    }
+
+    fn append_to_buffer(&mut self, media: Media) -> Result<bool> {
+        if let Media::DtmfDigit { digit, duration, gap } = media {
+            // The duration field of the payload is 16 bits
+            let duration = self.duration_samples(duration).clamp(1, u16::MAX as u32);
+            let gap = self.duration_samples(gap);
+            self.send_queue.push_back((digit, duration, gap));
+        }
+        Ok(true)
     }
 
     fn get_next_packet(&mut self) -> Result<Vec<Packet>> {
-        Ok(Vec::new())
+        if self.sending.is_none() {
+            if self.gap_left > 0 {
+                self.gap_left = self.gap_left.saturating_sub(self.ptime_samples);
+                return Ok(Vec::new());
+            }
+            let Some((event, duration, gap)) = self.send_queue.pop_front() else {
+                return Ok(Vec::new());
+            };
+            self.sending = Some(SendingEvent { event, elapsed: 0, duration, gap });
+        }
+        let Some(sending) = self.sending.as_mut() else {
+            return Ok(Vec::new());
+        };
+
+        let start = sending.elapsed == 0;
+        sending.elapsed = (sending.elapsed + self.ptime_samples).min(sending.duration);
+        let end = sending.elapsed == sending.duration;
+        let payload = Bytes::from(vec![
+            sending.event as u8,
+            if end { 0b1000_0000 } else { 0 } | EVENT_VOLUME,
+            (sending.elapsed >> 8) as u8,
+            sending.elapsed as u8,
+        ]);
+
+        // All the packets of an event carry the timestamp of its start
+        let mut packets = Vec::new();
+        for _ in 0..if end { END_PACKETS } else { 1 } {
+            packets.extend(self.packetizer.packetize(&payload, 0)?);
+        }
+        for (i, packet) in packets.iter_mut().enumerate() {
+            packet.header.marker = start && i == 0;
+        }
+
+        if end {
+            self.packetizer.skip_samples(sending.duration);
+            self.gap_left = sending.gap;
+            self.sending = None;
+        }
+        Ok(packets)
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webrtc_sdp::parse_sdp;
+
+    fn new_codec() -> TelephoneEventsCodec {
+        let sdp = parse_sdp(concat!(
+            "v=0\r\n",
+            "o=- 1 1 IN IP4 127.0.0.1\r\n",
+            "s=-\r\n",
+            "c=IN IP4 127.0.0.1\r\n",
+            "t=0 0\r\n",
+            "m=audio 20000 RTP/AVP 0 101\r\n",
+            "a=rtpmap:0 PCMU/8000\r\n",
+            "a=rtpmap:101 telephone-event/8000\r\n",
+        ), false).unwrap();
+        TelephoneEventsCodec::try_from_sdp(&sdp, &RtpStream::new(1, 1)).unwrap()
+    }
+
+    /// Payload of a telephone event packet (RFC 4733).
+    fn payload(event: u8, end: bool) -> Bytes {
+        Bytes::from(vec![event, if end { 0b1000_0000 } else { 0 }, 0, 160])
+    }
+
+    /// Packets of a press of `event` at `timestamp`, the end packet being sent `end_packets` times.
+    fn press(event: u8, timestamp: u32, end_packets: usize) -> Vec<(Bytes, u32)> {
+        let mut packets = vec![(payload(event, false), timestamp); 3];
+        packets.extend(vec![(payload(event, true), timestamp); end_packets]);
+        packets
+    }
+
+    /// Decodes the packets, returning the reported events.
+    fn decode(codec: &mut TelephoneEventsCodec, packets: &[(Bytes, u32)]) -> Vec<(TelephoneEvent, bool)> {
+        let mut media = Vec::new();
+        for (payload, timestamp) in packets {
+            media.extend(codec.decode_payload(payload.clone(), *timestamp).unwrap());
+            media.extend(codec.poll_media());
+        }
+        media.into_iter()
+            .filter_map(|media| match media {
+                Media::TelephoneEvent(event) => Some(event),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn repeated_packets_report_each_press_once() {
+        let mut packets = press(5, 0, 3);
+        packets.extend(press(5, 800, 3));
+
+        let events = decode(&mut new_codec(), &packets);
+        assert_eq!(events, vec![
+            (TelephoneEvent::Five, false),
+            (TelephoneEvent::Five, true),
+            (TelephoneEvent::Five, false),
+            (TelephoneEvent::Five, true),
+        ]);
+    }
+
+    #[test]
+    fn press_is_ended_when_some_end_packets_are_lost() {
+        // Only one of the retransmissions of the end packet arrives
+        let events = decode(&mut new_codec(), &press(1, 0, 1));
+        assert_eq!(events, vec![(TelephoneEvent::One, false), (TelephoneEvent::One, true)]);
+    }
+
+    #[test]
+    fn new_press_ends_previous_press_without_end_packets() {
+        let mut packets = press(2, 0, 0);
+        packets.extend(press(2, 800, 0));
+
+        let mut codec = new_codec();
+        let events = decode(&mut codec, &packets);
+        assert_eq!(events, vec![
+            (TelephoneEvent::Two, false),
+            (TelephoneEvent::Two, true),
+            (TelephoneEvent::Two, false),
+        ]);
+
+        // The last press is released once its packets stop
+        std::thread::sleep(STALE_KEY_TIMEOUT + Duration::from_millis(50));
+        assert!(matches!(codec.poll_media().as_slice(), [Media::TelephoneEvent((TelephoneEvent::Two, true))]));
+    }
+
+    #[test]
+    fn overlapping_presses_are_reported_separately() {
+        // Packets of both keys interleaved
+        let packets = press(3, 0, 3).into_iter().zip(press(4, 160, 3)).flat_map(|(three, four)| [three, four]).collect::<Vec<_>>();
+
+        let events = decode(&mut new_codec(), &packets);
+        assert_eq!(events, vec![
+            (TelephoneEvent::Three, false),
+            (TelephoneEvent::Four, false),
+            (TelephoneEvent::Three, true),
+            (TelephoneEvent::Four, true),
+        ]);
+    }
+}
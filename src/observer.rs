@@ -0,0 +1,40 @@
+//! Observation of the SIP messages exchanged with the server, for tracing and debugging.
+
+use rsip::SipMessage;
+
+/// Direction of an observed SIP message.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MessageDirection {
+    /// Received from the SIP server.
+    Inbound,
+    /// Sent to the SIP server.
+    Outbound,
+}
+
+/// Receives every SIP message read from or written to the SIP connection,
+/// see [SipManager::set_message_observer](crate::manager::SipManager::set_message_observer).
+///
+/// Called from the SIP connection task, implementations should return quickly.
+/// Keep-alive pings are not SIP messages and are not observed.
+///
+/// # Examples
+/// ```
+///  use simple_sip_rs::observer::MessageDirection;
+///  use rsip::SipMessage;
+///
+///  let observer = |direction: MessageDirection, message: &SipMessage| {
+///     println!("{:?}\n{}", direction, message);
+///  };
+/// ```
+pub trait MessageObserver: Send + Sync {
+    fn on_message(&self, direction: MessageDirection, message: &SipMessage);
+}
+
+impl<F> MessageObserver for F
+where
+    F: Fn(MessageDirection, &SipMessage) + Send + Sync,
+{
+    fn on_message(&self, direction: MessageDirection, message: &SipMessage) {
+        self(direction, message)
+    }
+}
@@ -1,12 +1,183 @@
-use rsip::Method;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use anyhow::Result;
+use rsip::param::Tag;
+use rsip::prelude::*;
+use rsip::{Header, Headers, Method, Request, Response, StatusCode};
 use rsip::typed::Allow;
+use uuid::Uuid;
+use crate::config::Config;
 
+pub mod multipart;
 pub mod options;
+pub mod prack;
 pub mod register;
+pub mod replaces;
+pub mod retransmission;
+pub mod route;
 pub mod sdp;
+pub mod session_timer;
 pub mod sip_message_decoder;
 
-pub fn get_allow_header() -> Allow
+/// Returns the `Allow` header listing the methods we support, UPDATE only when [Config::allow_update] is set.
+pub fn get_allow_header(config: &Config) -> Allow
 {
-    Allow::from(vec![Method::Invite, Method::Ack, Method::Bye, Method::Cancel, Method::Options])
-}
\ No newline at end of file
+    let mut methods = vec![Method::Invite, Method::Ack, Method::Bye, Method::Cancel, Method::Options];
+    if config.allow_update {
+        methods.push(Method::Update);
+    }
+    Allow::from(methods)
+}
+
+/// Returns the methods listed in the `Allow` headers of the remote, `None` when it sent none.
+///
+/// Unknown methods are ignored.
+pub fn get_allowed_methods(headers: &Headers) -> Option<Vec<Method>>
+{
+    let value = get_header_value(headers, "Allow")?;
+    Some(value.split(',').filter_map(|method| method.trim().parse().ok()).collect())
+}
+
+/// Returns the delay requested by the `Retry-After` header, ignoring its comment and parameters.
+pub fn get_retry_after(headers: &Headers) -> Option<Duration>
+{
+    headers.iter().find_map(|header| match header {
+        Header::RetryAfter(retry_after) => {
+            let value = retry_after.value().trim();
+            let seconds = value.split(|c: char| !c.is_ascii_digit()).next()?;
+            seconds.parse().ok().map(Duration::from_secs)
+        }
+        _ => None,
+    })
+}
+
+/// Returns the value of the header named `name`, compared case-insensitively.
+///
+/// Repeated headers are joined with `, `, as allowed by RFC 3261 for comma-separated lists.
+pub fn get_header_value(headers: &Headers, name: &str) -> Option<String>
+{
+    let values = headers
+        .iter()
+        .filter_map(|header| {
+            let header = header.to_string();
+            let (header_name, value) = header.split_once(':')?;
+            header_name.trim().eq_ignore_ascii_case(name).then(|| value.trim().to_string())
+        })
+        .collect::<Vec<_>>();
+    if values.is_empty() {
+        return None;
+    }
+    Some(values.join(", "))
+}
+
+/// Splits a header value listing several name-addrs on the commas outside of quotes and angle brackets.
+pub fn split_name_addrs(value: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut bracketed = false;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '<' if !quoted => bracketed = true,
+            '>' if !quoted => bracketed = false,
+            ',' if !quoted && !bracketed => {
+                parts.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+/// Formats a header parameter value as is when it is a token, as a quoted string otherwise (RFC 3261 25.1).
+///
+/// Line breaks cannot be quoted and are dropped.
+pub fn token_or_quoted_string(value: &str) -> String {
+    let is_token = !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || "-.!%*_+`'~".contains(c));
+    if is_token {
+        return value.to_string();
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars().filter(|c| !matches!(c, '\r' | '\n')) {
+        if matches!(c, '"' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Generates a response to a request outside of any dialog, typically an error.
+pub fn generate_response(request: &Request, status_code: StatusCode) -> Result<Response>
+{
+    let mut to = request.to_header()?.typed()?;
+    if to.tag().is_none() {
+        to = to.with_tag(Tag::new(format!("tt{}", Uuid::new_v4())));
+    }
+
+    let mut headers: rsip::Headers = Default::default();
+    // A request without Via can not be answered
+    request.via_header()?;
+    for via in request.headers.iter().filter(|header| matches!(header, Header::Via(_))) {
+        headers.push(via.clone());
+    }
+    headers.push(request.from_header()?.clone().into());
+    headers.push(to.into());
+    headers.push(request.call_id_header()?.clone().into());
+    headers.push(request.cseq_header()?.clone().into());
+    headers.push(rsip::headers::UserAgent::new("rust-sip").into());
+    headers.push(rsip::headers::ContentLength::default().into());
+
+    Ok(Response {
+        status_code,
+        version: rsip::Version::V2,
+        headers,
+        body: Default::default(),
+    })
+}
+/// Error handling a request, answered with `status_code` instead of ending the connection or the call.
+#[derive(Debug)]
+pub struct RequestError {
+    pub status_code: StatusCode,
+    /// Additional headers of the error response, like the `Min-SE` of a 422.
+    pub headers: Vec<Header>,
+    pub reason: String,
+}
+
+impl RequestError {
+    pub fn new(status_code: StatusCode, reason: impl Into<String>) -> Self
+    {
+        Self { status_code, headers: Vec::new(), reason: reason.into() }
+    }
+
+    pub fn with_header(mut self, header: Header) -> Self
+    {
+        self.headers.push(header);
+        self
+    }
+}
+
+impl Display for RequestError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.reason, self.status_code)
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+/// Generates the response to a request that could not be handled, with the status code of the [RequestError] if any,
+/// 500 Server Internal Error otherwise.
+pub fn generate_error_response(request: &Request, err: &anyhow::Error) -> Result<Response>
+{
+    let Some(request_error) = err.downcast_ref::<RequestError>() else {
+        return generate_response(request, StatusCode::ServerInternalError);
+    };
+    let mut response = generate_response(request, request_error.status_code.clone())?;
+    response.headers.extend(request_error.headers.clone());
+    Ok(response)
+}
@@ -0,0 +1,136 @@
+use anyhow::Result;
+use rsip::headers::Accept;
+use rsip::prelude::UntypedHeader;
+use rsip::{Headers, StatusCode};
+use webrtc_sdp::{parse_sdp, SdpSession};
+use crate::sip_proto::{get_header_value, RequestError};
+
+/// Parses the SDP of a message body, taken from its `application/sdp` part when the body is `multipart/*` (RFC 5621).
+///
+/// The other parts, like ISUP or geolocation, are ignored. A multipart body without a valid boundary or an invalid SDP
+/// fails with 400 Bad Request, a multipart body without an `application/sdp` part with 415 Unsupported Media Type.
+pub fn parse_sdp_body(headers: &Headers, body: &[u8]) -> Result<SdpSession> {
+    let body = std::str::from_utf8(body).map_err(|_| RequestError::new(StatusCode::BadRequest, "Body is not valid UTF-8"))?;
+    let content_type = get_header_value(headers, "Content-Type").unwrap_or_default();
+    let sdp = match get_multipart_boundary(&content_type)? {
+        Some(boundary) => get_multipart_sdp(body, &boundary)?,
+        None => body,
+    };
+    Ok(parse_sdp(sdp, false).map_err(|e| RequestError::new(StatusCode::BadRequest, format!("Invalid SDP: {}", e)))?)
+}
+
+/// Returns the boundary of a `multipart/*` content type, `None` for other content types.
+fn get_multipart_boundary(content_type: &str) -> Result<Option<String>, RequestError> {
+    let mut parts = content_type.split(';').map(str::trim);
+    let media_type = parts.next().unwrap_or_default();
+    if !media_type.to_ascii_lowercase().starts_with("multipart/") {
+        return Ok(None);
+    }
+    let boundary = parts.find_map(|param| match param.split_once('=') {
+        Some((name, value)) if name.trim().eq_ignore_ascii_case("boundary") => Some(value.trim().trim_matches('"').to_string()),
+        _ => None,
+    });
+    match boundary {
+        Some(boundary) if !boundary.is_empty() => Ok(Some(boundary)),
+        _ => Err(RequestError::new(StatusCode::BadRequest, "Multipart body without a boundary")),
+    }
+}
+
+/// Returns the content of the first `application/sdp` part of a multipart body.
+fn get_multipart_sdp<'a>(body: &'a str, boundary: &str) -> Result<&'a str, RequestError> {
+    let delimiter = format!("--{}", boundary);
+    if !body.contains(delimiter.as_str()) {
+        return Err(RequestError::new(StatusCode::BadRequest, format!("Multipart body without its boundary {}", boundary)));
+    }
+    let sdp = body.split(delimiter.as_str())
+        // Skip the preamble, stop at the close delimiter
+        .skip(1)
+        .take_while(|part| !part.starts_with("--"))
+        .find_map(|part| {
+            // Rest of the delimiter line, then the headers of the part
+            let (_, part) = part.split_once("\r\n")?;
+            let (headers, content) = match part.strip_prefix("\r\n") {
+                Some(content) => ("", content),
+                None => part.split_once("\r\n\r\n")?,
+            };
+            let is_sdp = headers.split("\r\n").any(|header| match header.split_once(':') {
+                Some((name, value)) => name.trim().eq_ignore_ascii_case("Content-Type")
+                    && value.split(';').next().is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("application/sdp")),
+                None => false,
+            });
+            // The line break before the next delimiter belongs to the delimiter
+            is_sdp.then(|| content.strip_suffix("\r\n").unwrap_or(content))
+        });
+    sdp.ok_or_else(|| RequestError::new(StatusCode::UnsupportedMediaType, "Multipart body without an application/sdp part")
+        .with_header(Accept::new("application/sdp").into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsip::Header;
+
+    const SDP: &str = "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio 4000 RTP/AVP 0\r\n";
+
+    fn multipart_body(boundary: &str) -> String {
+        format!(
+            "preamble\r\n\
+            --{boundary}\r\n\
+            Content-Type: application/ISUP;version=nxv3\r\n\r\n\
+            isup\r\n\
+            --{boundary}\r\n\
+            Content-Type: Application/SDP\r\n\r\n\
+            {SDP}\r\n\
+            --{boundary}--\r\n\
+            epilogue"
+        )
+    }
+
+    fn status_code(result: Result<impl std::fmt::Debug, RequestError>) -> StatusCode {
+        result.unwrap_err().status_code
+    }
+
+    #[test]
+    fn parses_boundary_of_multipart_content_types_only() {
+        assert_eq!(get_multipart_boundary("multipart/mixed;boundary=unique-boundary-1").unwrap(), Some("unique-boundary-1".to_string()));
+        assert_eq!(get_multipart_boundary("Multipart/Mixed; Boundary=\"quoted boundary\"").unwrap(), Some("quoted boundary".to_string()));
+        assert_eq!(get_multipart_boundary("application/sdp").unwrap(), None);
+        assert_eq!(get_multipart_boundary("").unwrap(), None);
+
+        assert_eq!(status_code(get_multipart_boundary("multipart/mixed")), StatusCode::BadRequest);
+        assert_eq!(status_code(get_multipart_boundary("multipart/mixed;boundary=\"\"")), StatusCode::BadRequest);
+    }
+
+    #[test]
+    fn takes_the_sdp_part_between_boundaries() {
+        let body = multipart_body("unique-boundary-1");
+        assert_eq!(get_multipart_sdp(&body, "unique-boundary-1").unwrap(), SDP);
+    }
+
+    #[test]
+    fn fails_without_boundary_or_sdp_part() {
+        let body = multipart_body("unique-boundary-1");
+        assert_eq!(status_code(get_multipart_sdp(&body, "other-boundary")), StatusCode::BadRequest);
+
+        let body = "--b\r\nContent-Type: application/ISUP\r\n\r\nisup\r\n--b--\r\n";
+        let err = get_multipart_sdp(body, "b").unwrap_err();
+        assert_eq!(err.status_code, StatusCode::UnsupportedMediaType);
+        assert!(err.headers.iter().any(|header| matches!(header, Header::Accept(_))));
+
+        // Parts after the close delimiter are ignored
+        let body = format!("--b\r\nContent-Type: text/plain\r\n\r\ntext\r\n--b--\r\n--b\r\nContent-Type: application/sdp\r\n\r\n{SDP}\r\n");
+        assert_eq!(status_code(get_multipart_sdp(&body, "b")), StatusCode::UnsupportedMediaType);
+    }
+
+    #[test]
+    fn parses_sdp_of_plain_and_multipart_bodies() {
+        let mut headers = Headers::default();
+        headers.push(Header::ContentType("application/sdp".into()));
+        assert!(parse_sdp_body(&headers, SDP.as_bytes()).is_ok());
+
+        let mut headers = Headers::default();
+        headers.push(Header::ContentType("multipart/mixed;boundary=unique-boundary-1".into()));
+        let sdp = parse_sdp_body(&headers, multipart_body("unique-boundary-1").as_bytes()).unwrap();
+        assert_eq!(sdp.media.len(), 1);
+    }
+}
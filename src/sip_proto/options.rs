@@ -3,32 +3,22 @@ use crate::sip_proto::get_allow_header;
 use rsip::headers::AcceptLanguage;
 use rsip::prelude::*;
 use rsip::typed::{Accept, MediaType};
-use rsip::{HostWithPort, Request, Scheme, SipMessage, StatusCode};
+use rsip::{Header, Request, SipMessage, StatusCode};
 
 pub fn generate_options_response(request: Request, config: &Config) -> SipMessage {
     let mut headers: rsip::Headers = Default::default();
 
-    let request_via = request.via_header().unwrap().clone().into_typed().unwrap();
-    headers.push(request_via.into());
+    for via in request.headers.iter().filter(|header| matches!(header, Header::Via(_))) {
+        headers.push(via.clone());
+    }
 
-    headers.push(
-        rsip::typed::Contact {
-            display_name: None,
-            uri: rsip::Uri {
-                scheme: Some(Scheme::Sip),
-                auth: Some((config.username.clone(), Option::<String>::None).into()),
-                host_with_port: HostWithPort::from(config.own_addr),
-                ..Default::default()
-            },
-            params: vec![],
-        }.into(),
-    );
+    headers.push(config.get_register_contact().into());
     headers.push(request.to_header().unwrap().clone().into());
     headers.push(request.from_header().unwrap().clone().into());
     headers.push(request.call_id_header().unwrap().clone().into());
     headers.push(request.cseq_header().unwrap().clone().into());
 
-    headers.push(get_allow_header().into());
+    headers.push(get_allow_header(config).into());
     headers.push(Accept::from(vec![MediaType::Sdp(Default::default())]).into());
     headers.push(AcceptLanguage::from("en").into());
 
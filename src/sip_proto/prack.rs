@@ -0,0 +1,31 @@
+use rsip::headers::Supported;
+use rsip::prelude::UntypedHeader;
+use rsip::{Header, Headers, Method};
+
+/// Option tag of reliable provisional responses (RFC 3262).
+const OPTION_TAG_100REL: &str = "100rel";
+
+pub fn supported_100rel_header() -> Header {
+    Supported::new(OPTION_TAG_100REL).into()
+}
+
+/// Returns `true` if the `Require` headers contain the option tag `100rel`, the provisional response must then be acknowledged with a PRACK.
+pub fn requires_100rel(headers: &Headers) -> bool {
+    headers.iter().any(|header| match header {
+        Header::Require(require) => require.value().split(',').any(|tag| tag.trim().eq_ignore_ascii_case(OPTION_TAG_100REL)),
+        _ => false,
+    })
+}
+
+/// Returns the value of the `RSeq` header if any.
+pub fn get_rseq(headers: &Headers) -> Option<u32> {
+    headers.iter().find_map(|header| match header {
+        Header::Other(name, value) if name.eq_ignore_ascii_case("rseq") => value.trim().parse().ok(),
+        _ => None,
+    })
+}
+
+/// `RAck` header acknowledging the reliable provisional response with the given `RSeq`, sent in response to the request with the given `CSeq`.
+pub fn rack_header(rseq: u32, cseq: u32, method: Method) -> Header {
+    Header::Other("RAck".to_string(), format!("{} {} {}", rseq, cseq, method))
+}
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 use crate::config::Config;
 use crate::sip_proto::get_allow_header;
@@ -10,13 +10,51 @@ use rsip::prelude::*;
 use rsip::typed::CSeq;
 use rsip::Param::Transport;
 use rsip::Transport::Tcp;
-use rsip::{HostWithPort, Method, Scheme, SipMessage};
+use rsip::{Header, HostWithPort, Method, Response, Scheme, SipMessage};
 use uuid::Uuid;
 
+/// Number of times a request is authenticated again with the fresh nonce of a stale challenge, before giving up.
+pub const MAX_STALE_RETRIES: u32 = 3;
+
 pub struct ConfigAuth<'a> {
     pub config: &'a Config,
     pub realm: String,
     pub nonce: String,
+    /// Opaque value of the challenge, echoed back unchanged.
+    pub opaque: Option<String>,
+    /// Answers a `Proxy-Authenticate` challenge with a `Proxy-Authorization` header.
+    pub proxy: bool,
+    /// The previous nonce expired (`stale=true`), the credentials were accepted and the request should be retried with the new nonce.
+    pub stale: bool,
+}
+
+impl<'a> ConfigAuth<'a> {
+    /// Answers the challenge of a 401 or 407 response.
+    ///
+    /// When the response lists several `WWW-Authenticate` or `Proxy-Authenticate` headers,
+    /// the first MD5 digest one is picked, the others being unsupported.
+    pub fn from_challenge(config: &'a Config, response: &Response) -> Result<Self> {
+        let challenge = response.headers.iter().find_map(|header| {
+            let (challenge, proxy) = match header {
+                Header::WwwAuthenticate(header) => (header.typed().ok()?, false),
+                Header::ProxyAuthenticate(header) => (header.typed().ok()?.0, true),
+                _ => return None,
+            };
+            let supported = challenge.scheme == auth::Scheme::Digest
+                && matches!(challenge.algorithm, None | Some(Algorithm::Md5));
+            supported.then_some((challenge, proxy))
+        });
+        let (challenge, proxy) = challenge.ok_or(anyhow!("Missing supported authenticate header"))?;
+
+        Ok(ConfigAuth {
+            config,
+            realm: challenge.realm,
+            nonce: challenge.nonce,
+            stale: challenge.stale.is_some_and(|stale| stale.eq_ignore_ascii_case("true")),
+            opaque: challenge.opaque,
+            proxy,
+        })
+    }
 }
 
 fn get_md5(input: String) -> String {
@@ -48,23 +86,21 @@ pub fn add_auth_header(mut message: SipMessage, payload: &ConfigAuth) -> Result<
         },
         response: auth_response,
         algorithm: Some(Algorithm::Md5),
-        opaque: None,
+        opaque: payload.opaque.clone(),
         qop: None,
     };
 
-    message.headers_mut().push(auth_header.into());
+    if payload.proxy {
+        message.headers_mut().push(rsip::typed::ProxyAuthorization(auth_header).into());
+    } else {
+        message.headers_mut().push(auth_header.into());
+    }
     Ok(message)
 }
 
-pub fn generate_register_request(config: &Config) -> SipMessage {
+pub fn generate_register_request(config: &Config, call_id: &str, cseq: u32, expires: u32) -> SipMessage {
     let mut headers: rsip::Headers = Default::default();
 
-    let self_uri = rsip::Uri {
-        scheme: Some(Scheme::Sip),
-        auth: Some((config.username.clone(), Option::<String>::None).into()),
-        host_with_port: HostWithPort::from(config.own_addr),
-        ..Default::default()
-    };
     let remote_uri = rsip::Uri {
         scheme: Some(Scheme::Sip),
         auth: Some((config.username.clone(), Option::<String>::None).into()),
@@ -78,7 +114,7 @@ pub fn generate_register_request(config: &Config) -> SipMessage {
         version: rsip::Version::V2,
         transport: Tcp,
         uri: rsip::Uri {
-            host_with_port: HostWithPort::from(config.own_addr),
+            host_with_port: HostWithPort::from(config.advertised_addr()),
             ..Default::default()
         },
         params: vec![
@@ -88,13 +124,7 @@ pub fn generate_register_request(config: &Config) -> SipMessage {
     }.into());
     headers.push(rsip::headers::MaxForwards::default().into());
 
-    headers.push(
-        rsip::typed::Contact {
-            display_name: None,
-            uri: self_uri,
-            params: vec![],
-        }.into(),
-    );
+    headers.push(config.get_register_contact().into());
     headers.push(rsip::typed::To {
         display_name: None,
         uri: remote_uri.clone(),
@@ -105,15 +135,16 @@ pub fn generate_register_request(config: &Config) -> SipMessage {
         uri: remote_uri.clone(),
         params: vec![rsip::Param::Tag(rsip::param::Tag::new("a73kszlflasda"))],
     }.into());
-    headers.push(rsip::headers::CallId::from(Uuid::new_v4().to_string()).into());
+    headers.push(rsip::headers::CallId::from(call_id.to_string()).into());
     headers.push(
         CSeq {
-            seq: 1,
+            seq: cseq,
             method: Method::Register,
         }.into(),
     );
+    headers.push(rsip::headers::Expires::from(expires).into());
 
-    headers.push(get_allow_header().into());
+    headers.push(get_allow_header(config).into());
     headers.push(rsip::headers::UserAgent::new("rust-sip").into());
     headers.push(rsip::headers::ContentLength::default().into());
 
@@ -0,0 +1,99 @@
+use anyhow::{anyhow, Result};
+use rsip::{Header, Request};
+
+/// Dialog to replace, from a `Replaces` header (RFC 3891).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replaces {
+    pub call_id: String,
+    /// Tag of the recipient of the header, our local tag.
+    pub to_tag: String,
+    /// Tag of the remote of the replaced dialog.
+    pub from_tag: String,
+    /// Only an early dialog can be replaced.
+    pub early_only: bool,
+}
+
+/// Returns the `Replaces` header of the request if any.
+pub fn get_replaces(request: &Request) -> Result<Option<Replaces>> {
+    let value = request.headers.iter().find_map(|header| match header {
+        Header::Other(name, value) if name.eq_ignore_ascii_case("replaces") => Some(value),
+        _ => None,
+    });
+    let Some(value) = value else {
+        return Ok(None);
+    };
+
+    let mut parts = value.split(';').map(str::trim);
+    let call_id = parts.next().filter(|call_id| !call_id.is_empty())
+        .ok_or(anyhow!("Replaces header without Call-ID"))?;
+
+    let mut to_tag = None;
+    let mut from_tag = None;
+    let mut early_only = false;
+    for param in parts {
+        match param.split_once('=') {
+            Some((name, tag)) if name.trim().eq_ignore_ascii_case("to-tag") => to_tag = Some(tag.trim()),
+            Some((name, tag)) if name.trim().eq_ignore_ascii_case("from-tag") => from_tag = Some(tag.trim()),
+            None if param.eq_ignore_ascii_case("early-only") => early_only = true,
+            _ => {}
+        }
+    }
+
+    Ok(Some(Replaces {
+        call_id: call_id.to_string(),
+        to_tag: to_tag.ok_or(anyhow!("Replaces header without to-tag"))?.to_string(),
+        from_tag: from_tag.ok_or(anyhow!("Replaces header without from-tag"))?.to_string(),
+        early_only,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(replaces: Option<&str>) -> Request {
+        let mut request = Request {
+            method: rsip::Method::Invite,
+            uri: "sip:1000@127.0.0.1".try_into().unwrap(),
+            version: Default::default(),
+            headers: Default::default(),
+            body: vec![],
+        };
+        if let Some(replaces) = replaces {
+            request.headers.push(Header::Other("Replaces".to_string(), replaces.to_string()));
+        }
+        request
+    }
+
+    #[test]
+    fn parses_tags_in_any_order() {
+        let replaces = get_replaces(&request(Some("425928@bobster.example.org;to-tag=7743;from-tag=6472"))).unwrap();
+        assert_eq!(replaces, Some(Replaces {
+            call_id: "425928@bobster.example.org".to_string(),
+            to_tag: "7743".to_string(),
+            from_tag: "6472".to_string(),
+            early_only: false,
+        }));
+
+        let replaces = get_replaces(&request(Some("abc ; From-Tag = 2 ; TO-TAG = 1"))).unwrap().unwrap();
+        assert_eq!((replaces.to_tag.as_str(), replaces.from_tag.as_str()), ("1", "2"));
+    }
+
+    #[test]
+    fn parses_early_only() {
+        let replaces = get_replaces(&request(Some("abc;to-tag=1;early-only;from-tag=2"))).unwrap().unwrap();
+        assert!(replaces.early_only);
+
+        // Only the flag, not a parameter with a value
+        let replaces = get_replaces(&request(Some("abc;to-tag=1;from-tag=2;early-only=no"))).unwrap().unwrap();
+        assert!(!replaces.early_only);
+    }
+
+    #[test]
+    fn fails_without_call_id_or_tags() {
+        assert_eq!(get_replaces(&request(None)).unwrap(), None);
+        assert!(get_replaces(&request(Some(";to-tag=1;from-tag=2"))).is_err());
+        assert!(get_replaces(&request(Some("abc;from-tag=2"))).is_err());
+        assert!(get_replaces(&request(Some("abc;to-tag=1"))).is_err());
+    }
+}
@@ -0,0 +1,43 @@
+use rsip::SipMessage;
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+
+/// RTT estimate, initial retransmission interval.
+pub const T1: Duration = Duration::from_millis(500);
+/// Maximum retransmission interval.
+pub const T2: Duration = Duration::from_secs(4);
+
+/// Retransmits a message following RFC 3261 timers: starting at [T1], doubling up to [T2] and giving up after 64*[T1].
+pub struct Retransmission {
+    message: SipMessage,
+    interval: Duration,
+    next: Instant,
+    deadline: Instant,
+}
+
+impl Retransmission {
+    pub fn new(message: SipMessage) -> Self {
+        let now = Instant::now();
+        Self {
+            message,
+            interval: T1,
+            next: now + T1,
+            deadline: now + T1 * 64,
+        }
+    }
+
+    /// Waits for the next retransmission and returns the message to send.
+    ///
+    /// Returns `None` once the retransmission timed out.
+    pub async fn next(&mut self) -> Option<SipMessage> {
+        if self.next >= self.deadline {
+            sleep_until(self.deadline).await;
+            return None;
+        }
+
+        sleep_until(self.next).await;
+        self.interval = (self.interval * 2).min(T2);
+        self.next += self.interval;
+        Some(self.message.clone())
+    }
+}
@@ -0,0 +1,55 @@
+use rsip::headers::Route;
+use rsip::prelude::UntypedHeader;
+use rsip::{Header, Headers, Request, Uri};
+use crate::sip_proto::split_name_addrs;
+
+/// Returns the route set of a dialog (RFC 3261 12.1), recorded by the proxies in the `Record-Route` headers of the
+/// message establishing it.
+///
+/// The UAS keeps the order of the INVITE, the UAC reverses the order of the response.
+pub fn get_route_set(headers: &Headers, is_uac: bool) -> Vec<String>
+{
+    let mut route_set = headers.iter().filter_map(|header| match header {
+        Header::RecordRoute(record_route) => Some(record_route.value()),
+        _ => None,
+    })
+        .flat_map(split_name_addrs)
+        .map(str::trim)
+        .filter(|route| !route.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+
+    if is_uac {
+        route_set.reverse();
+    }
+    route_set
+}
+
+/// Returns the `Route` headers sending an in-dialog request through the route set, proxies are expected to loose route.
+pub fn get_route_headers(route_set: &[String]) -> Vec<Header>
+{
+    route_set.iter().map(|route| Route::new(route.clone()).into()).collect()
+}
+
+/// Returns the `Record-Route` headers of the request, copied unchanged to our responses.
+pub fn get_record_route_headers(request: &Request) -> Vec<Header>
+{
+    request.headers.iter().filter(|header| matches!(header, Header::RecordRoute(_))).cloned().collect()
+}
+
+/// Returns the URI of the `Contact` header, the remote target of the dialog (RFC 3261 12.1).
+///
+/// Only the URI is parsed, rsip cannot parse quoted parameters like `+sip.instance`.
+pub fn get_remote_target(headers: &Headers) -> Option<Uri>
+{
+    let value = headers.iter().find_map(|header| match header {
+        Header::Contact(contact) => Some(contact.value()),
+        _ => None,
+    })?;
+    let contact = split_name_addrs(value).into_iter().next()?.trim();
+    let uri = match contact.split_once('<') {
+        Some((_, rest)) => rest.split_once('>')?.0,
+        None => contact.split(';').next()?,
+    };
+    Uri::try_from(uri.trim()).ok()
+}
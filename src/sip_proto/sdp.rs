@@ -1,22 +1,234 @@
+use crate::call::MediaDirection;
 use crate::config::Config;
-use crate::media::populate_sdp_media_from_codecs;
-use anyhow::Result;
+use crate::media::{get_codec_kinds_from_sdp_media, populate_sdp_media_from_codecs, CodecKind};
+use crate::media::passthrough::get_passthrough_rtpmaps;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use webrtc_sdp::address::ExplicitlyTypedAddress;
-use webrtc_sdp::attribute_type::SdpAttribute;
+use webrtc_sdp::attribute_type::{SdpAttribute, SdpAttributeRtpmap};
 use webrtc_sdp::media_type::{SdpFormatList, SdpMedia, SdpMediaLine, SdpMediaValue, SdpProtocolValue};
 use webrtc_sdp::{SdpConnection, SdpOrigin, SdpSession, SdpTiming};
 
-pub fn generate_sdp_new(config: &Config, rtp_port: u16) -> Result<SdpSession>
+/// First RTP payload type available for dynamic assignment.
+const DYNAMIC_PAYLOAD_TYPES_START: u8 = 96;
+
+/// Generates an offer, `rtp_addr` is the address advertised for our RTP socket.
+pub fn generate_sdp_new(config: &Config, rtp_addr: SocketAddr, codecs: &[CodecKind], direction: MediaDirection) -> Result<SdpSession>
+{
+    let mut session = generate_sdp_session(config, rtp_addr.ip());
+    session.extend_media(vec![generate_audio_media(config, rtp_addr.port(), codecs, direction)?]);
+
+    Ok(session)
+}
+
+/// Generates an answer containing the same media sections as the offer.
+/// The audio section selected by [get_audio_media_index] is accepted, only advertising the codecs of the offered section,
+/// the others are declined with a port of 0.
+/// The accepted section uses `direction` restricted to the direction offered for it.
+pub fn generate_sdp_answer(
+    config: &Config,
+    rtp_addr: SocketAddr,
+    codecs: &[CodecKind],
+    offer: &SdpSession,
+    direction: MediaDirection,
+) -> Result<SdpSession>
+{
+    let mut session = generate_sdp_session(config, rtp_addr.ip());
+    let selected_index = get_audio_media_index(offer, codecs, config.rtp_passthrough);
+
+    let mut media = Vec::new();
+    for (index, offered_media) in offer.media.iter().enumerate() {
+        if Some(index) == selected_index {
+            let direction = direction.intersect(get_media_direction(offer, offered_media).reverse());
+            let mut audio_media = generate_audio_media(config, rtp_addr.port(), codecs, direction)?;
+            retain_offered_codecs(&mut audio_media, offered_media)?;
+            mirror_payload_types(&mut audio_media, offered_media)?;
+            if config.rtp_passthrough {
+                add_passthrough_codecs(&mut audio_media, offered_media)?;
+            }
+            media.push(audio_media);
+        } else {
+            media.push(SdpMedia::new(SdpMediaLine {
+                media: offered_media.get_type().clone(),
+                port: 0,
+                port_count: 0,
+                proto: offered_media.get_proto().clone(),
+                formats: offered_media.get_formats().clone(),
+            }));
+        }
+    }
+    session.extend_media(media);
+
+    Ok(session)
+}
+
+/// Returns the index of the first enabled audio media section offering one of the given codecs,
+/// or with `passthrough` a codec the crate can not decode.
+pub fn get_audio_media_index(sdp: &SdpSession, codecs: &[CodecKind], passthrough: bool) -> Option<usize>
+{
+    sdp.media.iter().position(|media| {
+        media.get_type() == &SdpMediaValue::Audio
+            && media.get_port() != 0
+            && (get_codec_kinds_from_sdp_media(media).iter().any(|codec| codecs.contains(codec))
+                || (passthrough && !get_passthrough_rtpmaps(media).is_empty()))
+    })
+}
+
+/// Returns the direction of the media, taken from its attributes or the session attributes, `sendrecv` by default.
+pub fn get_media_direction(sdp: &SdpSession, media: &SdpMedia) -> MediaDirection
+{
+    let direction = |attributes: &[SdpAttribute]| attributes.iter().find_map(|attribute| match attribute {
+        SdpAttribute::Sendrecv => Some(MediaDirection::SendRecv),
+        SdpAttribute::Sendonly => Some(MediaDirection::SendOnly),
+        SdpAttribute::Recvonly => Some(MediaDirection::RecvOnly),
+        SdpAttribute::Inactive => Some(MediaDirection::Inactive),
+        _ => None,
+    });
+
+    direction(media.get_attributes())
+        .or_else(|| direction(&sdp.attribute))
+        .unwrap_or_default()
+}
+
+/// Removes the codecs of the media not found in the offered media, including telephone events and comfort noise.
+/// Static payload types are matched on the payload type as the offer may omit their `rtpmap`.
+fn retain_offered_codecs(media: &mut SdpMedia, offered_media: &SdpMedia) -> Result<()>
+{
+    let offered_rtpmaps = offered_media.get_attributes().iter().filter_map(|attr| match attr {
+        SdpAttribute::Rtpmap(rtpmap) => Some(rtpmap),
+        _ => None,
+    }).collect::<Vec<_>>();
+    let offered_payload_types = match offered_media.get_formats() {
+        SdpFormatList::Integers(formats) => formats.iter().map(|format| *format as u8).collect::<Vec<_>>(),
+        SdpFormatList::Strings(_) => Vec::new(),
+    };
+    let is_offered = |rtpmap: &SdpAttributeRtpmap| {
+        offered_rtpmaps.iter().any(|offered| {
+            offered.codec_name.eq_ignore_ascii_case(&rtpmap.codec_name) && offered.frequency == rtpmap.frequency
+        }) || (rtpmap.payload_type < DYNAMIC_PAYLOAD_TYPES_START && offered_payload_types.contains(&rtpmap.payload_type))
+    };
+
+    let (rtpmaps, removed): (Vec<_>, Vec<_>) = media.get_attributes().iter().filter_map(|attr| match attr {
+        SdpAttribute::Rtpmap(rtpmap) => Some(rtpmap.clone()),
+        _ => None,
+    }).partition(is_offered);
+    if removed.is_empty() {
+        return Ok(());
+    }
+    let fmtps = media.get_attributes().iter().filter_map(|attr| match attr {
+        SdpAttribute::Fmtp(fmtp) if rtpmaps.iter().any(|rtpmap| rtpmap.payload_type == fmtp.payload_type) => Some(fmtp.clone()),
+        _ => None,
+    }).collect::<Vec<_>>();
+
+    media.remove_codecs();
+    for rtpmap in rtpmaps {
+        media.add_codec(rtpmap)?;
+    }
+    for fmtp in fmtps {
+        media.add_attribute(SdpAttribute::Fmtp(fmtp))?;
+    }
+    Ok(())
+}
+
+/// Adds the offered codecs the crate can not decode to the answer media, with their payload types and formats.
+/// Our dynamic payload types were already moved away from the offered ones.
+fn add_passthrough_codecs(media: &mut SdpMedia, offered_media: &SdpMedia) -> Result<()>
+{
+    for rtpmap in get_passthrough_rtpmaps(offered_media) {
+        let payload_type = rtpmap.payload_type;
+        media.add_codec(rtpmap)?;
+        for attr in offered_media.get_attributes() {
+            if let SdpAttribute::Fmtp(fmtp) = attr {
+                if fmtp.payload_type == payload_type {
+                    media.add_attribute(SdpAttribute::Fmtp(fmtp.clone()))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Uses the dynamic payload types of the offered media for the same codecs in the answer media.
+/// Our other dynamic payload types are moved if they collide with one used by the offer.
+fn mirror_payload_types(media: &mut SdpMedia, offered_media: &SdpMedia) -> Result<()>
+{
+    let offered_rtpmaps = offered_media.get_attributes().iter().filter_map(|attr| match attr {
+        SdpAttribute::Rtpmap(rtpmap) => Some(rtpmap),
+        _ => None,
+    }).collect::<Vec<_>>();
+    let offered_payload_types = match offered_media.get_formats() {
+        SdpFormatList::Integers(formats) => formats.iter().map(|format| *format as u8).collect::<Vec<_>>(),
+        SdpFormatList::Strings(_) => Vec::new(),
+    };
+
+    let rtpmaps = media.get_attributes().iter().filter_map(|attr| match attr {
+        SdpAttribute::Rtpmap(rtpmap) => Some(rtpmap.clone()),
+        _ => None,
+    }).collect::<Vec<_>>();
+    let fmtps = media.get_attributes().iter().filter_map(|attr| match attr {
+        SdpAttribute::Fmtp(fmtp) => Some(fmtp.clone()),
+        _ => None,
+    }).collect::<Vec<_>>();
+
+    let mut mapping: HashMap<u8, u8> = HashMap::new();
+    for rtpmap in rtpmaps.iter().filter(|rtpmap| rtpmap.payload_type >= DYNAMIC_PAYLOAD_TYPES_START) {
+        let offered = offered_rtpmaps.iter().find(|offered| {
+            offered.codec_name.eq_ignore_ascii_case(&rtpmap.codec_name) && offered.frequency == rtpmap.frequency
+        });
+        if let Some(offered) = offered {
+            mapping.insert(rtpmap.payload_type, offered.payload_type);
+        }
+    }
+    for rtpmap in rtpmaps.iter().filter(|rtpmap| rtpmap.payload_type >= DYNAMIC_PAYLOAD_TYPES_START) {
+        if mapping.contains_key(&rtpmap.payload_type) {
+            continue;
+        }
+        let is_used = |payload_type: u8| offered_payload_types.contains(&payload_type) || mapping.values().any(|pt| *pt == payload_type);
+        if is_used(rtpmap.payload_type) {
+            let free = (DYNAMIC_PAYLOAD_TYPES_START..=127)
+                .find(|payload_type| !is_used(*payload_type) && !rtpmaps.iter().any(|r| r.payload_type == *payload_type))
+                .ok_or(anyhow!("No free dynamic payload type"))?;
+            mapping.insert(rtpmap.payload_type, free);
+        }
+    }
+
+    if mapping.is_empty() {
+        return Ok(());
+    }
+
+    media.remove_codecs();
+    for mut rtpmap in rtpmaps {
+        rtpmap.payload_type = *mapping.get(&rtpmap.payload_type).unwrap_or(&rtpmap.payload_type);
+        media.add_codec(rtpmap)?;
+    }
+    for mut fmtp in fmtps {
+        fmtp.payload_type = *mapping.get(&fmtp.payload_type).unwrap_or(&fmtp.payload_type);
+        media.add_attribute(SdpAttribute::Fmtp(fmtp))?;
+    }
+
+    Ok(())
+}
+
+/// Increments the version of the origin of our SDP, sent again in a new offer or answer of the dialog.
+pub fn increment_session_version(sdp: &mut SdpSession)
 {
+    sdp.origin.session_version += 1;
+}
+
+/// Generates the session with a unique origin for each call, the version starting at the session id.
+fn generate_sdp_session(config: &Config, ip: IpAddr) -> SdpSession
+{
+    let session_id = rand::random::<u32>() as u64;
     let mut session = SdpSession::new(0, SdpOrigin {
-        username: "Z".to_string(),
-        session_id: 0,
-        session_version: 1234,
-        unicast_addr: ExplicitlyTypedAddress::Ip(config.own_addr.ip()),
-    }, "Z".to_string());
+        username: config.sdp_username.clone(),
+        session_id,
+        session_version: session_id,
+        unicast_addr: ExplicitlyTypedAddress::Ip(ip),
+    }, config.sdp_session_name.clone());
 
     session.set_connection(SdpConnection {
-        address: ExplicitlyTypedAddress::Ip(config.own_addr.ip()),
+        address: ExplicitlyTypedAddress::Ip(ip),
         ttl: None,
         amount: None,
     });
@@ -26,6 +238,11 @@ pub fn generate_sdp_new(config: &Config, rtp_port: u16) -> Result<SdpSession>
         stop: 0,
     });
 
+    session
+}
+
+fn generate_audio_media(config: &Config, rtp_port: u16, codecs: &[CodecKind], direction: MediaDirection) -> Result<SdpMedia>
+{
     let mut media = SdpMedia::new(SdpMediaLine {
         media: SdpMediaValue::Audio,
         port: rtp_port as u32,
@@ -33,11 +250,16 @@ pub fn generate_sdp_new(config: &Config, rtp_port: u16) -> Result<SdpSession>
         proto: SdpProtocolValue::RtpAvp,
         formats: SdpFormatList::Integers(vec![]),
     });
-    populate_sdp_media_from_codecs(&mut media)?;
+    populate_sdp_media_from_codecs(&mut media, config, codecs)?;
 
-    media.add_attribute(SdpAttribute::Sendrecv)?;
+    media.add_attribute(match direction {
+        MediaDirection::SendRecv => SdpAttribute::Sendrecv,
+        MediaDirection::SendOnly => SdpAttribute::Sendonly,
+        MediaDirection::RecvOnly => SdpAttribute::Recvonly,
+        MediaDirection::Inactive => SdpAttribute::Inactive,
+    })?;
     media.add_attribute(SdpAttribute::RtcpMux)?;
-    session.extend_media(vec![media]);
-    
-    Ok(session)
-}
\ No newline at end of file
+
+    Ok(media)
+}
+
@@ -0,0 +1,242 @@
+use anyhow::{anyhow, Result};
+use rsip::headers::{Require, Supported};
+use rsip::prelude::UntypedHeader;
+use rsip::{Header, Headers, StatusCode};
+use crate::sip_proto::RequestError;
+
+/// Minimum session interval we accept, in seconds (RFC 4028).
+pub const MIN_SE: u32 = 90;
+
+/// Side of the dialog refreshing the session.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Refresher {
+    Uac,
+    Uas,
+}
+
+/// Value of a `Session-Expires` header (RFC 4028).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SessionExpires {
+    /// Session interval in seconds.
+    pub interval: u32,
+    pub refresher: Option<Refresher>,
+}
+
+impl SessionExpires {
+    pub fn to_header(self) -> Header {
+        let value = match self.refresher {
+            Some(Refresher::Uac) => format!("{};refresher=uac", self.interval),
+            Some(Refresher::Uas) => format!("{};refresher=uas", self.interval),
+            None => self.interval.to_string(),
+        };
+        Header::Other("Session-Expires".to_string(), value)
+    }
+}
+
+/// Returns the `Session-Expires` header if any, also in its compact form `x`.
+pub fn get_session_expires(headers: &Headers) -> Result<Option<SessionExpires>> {
+    let Some(value) = get_other_header(headers, &["session-expires", "x"]) else {
+        return Ok(None);
+    };
+
+    let mut parts = value.split(';').map(str::trim);
+    let interval = parts.next().unwrap_or_default().parse::<u32>()
+        .map_err(|e| anyhow!("Invalid Session-Expires header {}: {}", value, e))?;
+    let refresher = parts.find_map(|param| match param.split_once('=') {
+        Some((name, refresher)) if name.trim().eq_ignore_ascii_case("refresher") => {
+            match refresher.trim().to_ascii_lowercase().as_str() {
+                "uac" => Some(Refresher::Uac),
+                "uas" => Some(Refresher::Uas),
+                _ => None,
+            }
+        }
+        _ => None,
+    });
+
+    Ok(Some(SessionExpires { interval, refresher }))
+}
+
+/// Returns the value of the `Min-SE` header if any.
+pub fn get_min_se(headers: &Headers) -> Option<u32> {
+    get_other_header(headers, &["min-se"])?
+        .split(';')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+pub fn min_se_header(min_se: u32) -> Header {
+    Header::Other("Min-SE".to_string(), min_se.to_string())
+}
+
+/// Returns `true` if the `Supported` headers contain the option tag `timer`.
+pub fn supports_timer(headers: &Headers) -> bool {
+    headers.iter().any(|header| match header {
+        Header::Supported(supported) => supported.value().split(',').any(|tag| tag.trim().eq_ignore_ascii_case("timer")),
+        _ => false,
+    })
+}
+
+pub fn supported_timer_header() -> Header {
+    Supported::new("timer").into()
+}
+
+pub fn require_timer_header() -> Header {
+    Require::new("timer").into()
+}
+
+fn get_other_header<'a>(headers: &'a Headers, names: &[&str]) -> Option<&'a str> {
+    headers.iter().find_map(|header| match header {
+        Header::Other(name, value) if names.iter().any(|n| name.eq_ignore_ascii_case(n)) => Some(value.as_str()),
+        _ => None,
+    })
+}
+
+/// Session timer of a dialog.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SessionTimer {
+    /// Session interval in seconds.
+    pub interval: u32,
+    /// We refresh the session, otherwise the remote does.
+    pub local_refresher: bool,
+}
+
+impl SessionTimer {
+    /// Timer negotiated by a 2xx response to our request.
+    pub fn from_response(session_expires: SessionExpires) -> Self {
+        SessionTimer {
+            interval: session_expires.interval,
+            local_refresher: session_expires.refresher != Some(Refresher::Uas),
+        }
+    }
+
+    /// Timer negotiated by our 2xx response to a request, see [answer_session_expires].
+    ///
+    /// Fails with 400 Bad Request if the session timer headers are malformed,
+    /// with 422 Session Interval Too Small and our `Min-SE` if the session interval is below [MIN_SE].
+    pub fn from_request(request_headers: &Headers) -> Result<Option<Self>> {
+        if get_other_header(request_headers, &["min-se"]).is_some() && get_min_se(request_headers).is_none() {
+            return Err(RequestError::new(StatusCode::BadRequest, "Invalid Min-SE header").into());
+        }
+        let session_expires = answer_session_expires(request_headers)
+            .map_err(|e| RequestError::new(StatusCode::BadRequest, e.to_string()))?;
+        if let Some(session_expires) = session_expires.filter(|session_expires| session_expires.interval < MIN_SE) {
+            return Err(RequestError::new(
+                StatusCode::SessionIntervalTooSmall,
+                format!("Session interval {} is below {}", session_expires.interval, MIN_SE),
+            ).with_header(min_se_header(MIN_SE)).into());
+        }
+
+        Ok(session_expires.map(|session_expires| SessionTimer {
+            interval: session_expires.interval,
+            local_refresher: session_expires.refresher == Some(Refresher::Uas),
+        }))
+    }
+}
+
+/// Returns the `Session-Expires` of our 2xx response to a request, the refresher being chosen when the request does not.
+///
+/// The remote refreshes when it supports session timers, we do otherwise.
+pub fn answer_session_expires(request_headers: &Headers) -> Result<Option<SessionExpires>> {
+    let Some(mut session_expires) = get_session_expires(request_headers)? else {
+        return Ok(None);
+    };
+    if session_expires.refresher.is_none() {
+        session_expires.refresher = Some(if supports_timer(request_headers) { Refresher::Uac } else { Refresher::Uas });
+    }
+    Ok(Some(session_expires))
+}
+
+/// Adds the session timer headers to our 2xx response to a request.
+pub fn add_response_headers(request_headers: &Headers, response_headers: &mut Headers) -> Result<()> {
+    if let Some(session_expires) = answer_session_expires(request_headers)? {
+        response_headers.push(session_expires.to_header());
+        if supports_timer(request_headers) {
+            response_headers.push(require_timer_header());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(headers: &[(&str, &str)]) -> Headers {
+        let mut result = Headers::default();
+        for (name, value) in headers {
+            match *name {
+                "Supported" => result.push(Supported::new(*value).into()),
+                _ => result.push(Header::Other(name.to_string(), value.to_string())),
+            }
+        }
+        result
+    }
+
+    fn request_error(headers: &Headers) -> RequestError {
+        let err = SessionTimer::from_request(headers).unwrap_err();
+        err.downcast::<RequestError>().unwrap()
+    }
+
+    #[test]
+    fn parses_session_expires() {
+        let session_expires = get_session_expires(&headers(&[("Session-Expires", "1800; Refresher=UAS")])).unwrap();
+        assert_eq!(session_expires, Some(SessionExpires { interval: 1800, refresher: Some(Refresher::Uas) }));
+
+        // Compact form, unknown refresher and parameters are ignored
+        let session_expires = get_session_expires(&headers(&[("x", "600;foo=bar;refresher=other")])).unwrap();
+        assert_eq!(session_expires, Some(SessionExpires { interval: 600, refresher: None }));
+
+        assert_eq!(get_session_expires(&headers(&[])).unwrap(), None);
+        assert!(get_session_expires(&headers(&[("Session-Expires", "soon")])).is_err());
+    }
+
+    #[test]
+    fn parses_min_se() {
+        assert_eq!(get_min_se(&headers(&[("Min-SE", "120;foo=bar")])), Some(120));
+        assert_eq!(get_min_se(&headers(&[("min-se", " 90 ")])), Some(90));
+        assert_eq!(get_min_se(&headers(&[("Min-SE", "soon")])), None);
+        assert_eq!(get_min_se(&headers(&[])), None);
+    }
+
+    #[test]
+    fn refresher_of_our_response_is_chosen_when_the_request_does_not() {
+        // The remote supports session timers, it refreshes
+        let timer = SessionTimer::from_request(&headers(&[("Session-Expires", "1800"), ("Supported", "100rel, timer")])).unwrap();
+        assert_eq!(timer, Some(SessionTimer { interval: 1800, local_refresher: false }));
+
+        // The remote does not, we refresh
+        let timer = SessionTimer::from_request(&headers(&[("Session-Expires", "1800")])).unwrap();
+        assert_eq!(timer, Some(SessionTimer { interval: 1800, local_refresher: true }));
+
+        // The refresher of the request is kept
+        let timer = SessionTimer::from_request(&headers(&[("Session-Expires", "1800;refresher=uac"), ("Supported", "timer")])).unwrap();
+        assert_eq!(timer, Some(SessionTimer { interval: 1800, local_refresher: false }));
+
+        assert_eq!(SessionTimer::from_request(&headers(&[])).unwrap(), None);
+    }
+
+    #[test]
+    fn refresher_of_a_response_to_our_request() {
+        let timer = SessionTimer::from_response(SessionExpires { interval: 900, refresher: Some(Refresher::Uas) });
+        assert!(!timer.local_refresher);
+        let timer = SessionTimer::from_response(SessionExpires { interval: 900, refresher: Some(Refresher::Uac) });
+        assert!(timer.local_refresher);
+        let timer = SessionTimer::from_response(SessionExpires { interval: 900, refresher: None });
+        assert!(timer.local_refresher);
+    }
+
+    #[test]
+    fn invalid_session_timer_requests_are_rejected() {
+        let err = request_error(&headers(&[("Session-Expires", "60")]));
+        assert_eq!(err.status_code, StatusCode::SessionIntervalTooSmall);
+        assert_eq!(err.headers, vec![min_se_header(MIN_SE)]);
+
+        let err = request_error(&headers(&[("Session-Expires", "soon")]));
+        assert_eq!(err.status_code, StatusCode::BadRequest);
+
+        let err = request_error(&headers(&[("Session-Expires", "1800"), ("Min-SE", "soon")]));
+        assert_eq!(err.status_code, StatusCode::BadRequest);
+    }
+}
@@ -1,4 +1,6 @@
+use std::io::{Error, ErrorKind};
 use bytes::{Buf, BytesMut};
+use crate::logging::warn;
 use rsip::Header::ContentLength;
 use rsip::prelude::HasHeaders;
 use rsip::SipMessage;
@@ -8,11 +10,13 @@ const MAX_CONTENT_LENGTH: usize = 50 * 1000;
 
 pub struct SipMessageDecoder {
     pending_message: Option<SipMessage>,
+    /// Bytes of the body of a discarded message left to skip.
+    discarded_body_len: usize,
 }
 
 impl SipMessageDecoder {
     pub fn new() -> Self {
-        Self { pending_message: None }
+        Self { pending_message: None, discarded_body_len: 0 }
     }
 }
 
@@ -20,49 +24,195 @@ impl Decoder for SipMessageDecoder {
     type Item = SipMessage;
     type Error = std::io::Error;
 
+    /// Decodes the next message, discarding the messages that can not be parsed or are too large
+    /// so that a single bad message does not end the stream.
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        if self.pending_message.is_none() {
-            if let Some(index) = src
-                .windows(4)
-                .enumerate()
-                .find(|&(_, w)| matches!(w, b"\r\n\r\n"))
-                .map(|(ix, _)| ix + 4) {
-                if index == 4 {
-                    // Received keep alive
-                    src.advance(4);
-                    return Ok(None)
+        loop {
+            if self.discarded_body_len > 0 {
+                let len = self.discarded_body_len.min(src.len());
+                src.advance(len);
+                self.discarded_body_len -= len;
+                if self.discarded_body_len > 0 {
+                    return Ok(None);
                 }
-
-                let message = SipMessage::try_from(src.split_to(index).as_ref()).unwrap();
-                self.pending_message = Some(message);
             }
-        }
 
-        if let Some(message) = self.pending_message.as_mut() {
-            let content_length = (message
-                .headers()
-                .iter()
-                .find_map(|header| {
-                    if let ContentLength(header) = header {
-                        Some(header.length().unwrap_or(0))
-                    } else {
-                        None
+            if self.pending_message.is_none() {
+                // Skip keep alive pings (CRLFCRLF) and pongs (CRLF) in between messages
+                while src.starts_with(b"\r\n") {
+                    src.advance(2);
+                }
+
+                let Some(index) = src
+                    .windows(4)
+                    .position(|w| w == b"\r\n\r\n")
+                    .map(|ix| ix + 4) else {
+                    return Ok(None);
+                };
+                let head = src.split_to(index);
+                match SipMessage::try_from(head.as_ref()) {
+                    Ok(message) => self.pending_message = Some(message),
+                    Err(err) => {
+                        // Its body is skipped too when its length can still be read
+                        warn!("Discarding unparsable SIP message: {}", err);
+                        self.discarded_body_len = get_raw_content_length(&head).unwrap_or(0);
+                        continue;
                     }
-                })
-                .unwrap_or(0) as usize)
-                .min(MAX_CONTENT_LENGTH);
-
-            if src.len() >= content_length {
-                message.body_mut().append(&mut src.split_to(content_length).to_vec());
-            } else {
-                src.reserve(content_length - src.len());
+                }
             }
 
-            if message.body().len() == content_length {
+            if let Some(message) = self.pending_message.as_mut() {
+                let content_length = match get_content_length(message) {
+                    Ok(content_length) if content_length > MAX_CONTENT_LENGTH => {
+                        warn!("Discarding SIP message with Content-Length {} over {}", content_length, MAX_CONTENT_LENGTH);
+                        self.pending_message = None;
+                        self.discarded_body_len = content_length;
+                        continue;
+                    }
+                    Ok(content_length) => content_length,
+                    Err(err) => {
+                        // The end of the message is unknown, the decoding resumes at the next header block
+                        warn!("Discarding SIP message with an invalid Content-Length: {}", err);
+                        self.pending_message = None;
+                        continue;
+                    }
+                };
+
+                // The body may arrive over several reads, wait for all of it before taking it from the stream.
+                if src.len() < content_length {
+                    src.reserve(content_length - src.len());
+                    return Ok(None);
+                }
+
+                *message.body_mut() = src.split_to(content_length).to_vec();
                 return Ok(self.pending_message.take());
             }
         }
+    }
+}
+
+/// Returns the length of the body announced by the message, 0 without a `Content-Length` header.
+fn get_content_length(message: &SipMessage) -> Result<usize, Error> {
+    let Some(header) = message.headers().iter().find_map(|header| match header {
+        ContentLength(header) => Some(header),
+        _ => None,
+    }) else {
+        return Ok(0);
+    };
+
+    Ok(header.length().map_err(|err| Error::new(ErrorKind::InvalidData, err))? as usize)
+}
+
+/// Reads the `Content-Length` header, also in its compact form `l`, from the raw head of a message that could not be parsed.
+fn get_raw_content_length(head: &[u8]) -> Option<usize> {
+    String::from_utf8_lossy(head).split("\r\n").find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        let name = name.trim();
+        if name.eq_ignore_ascii_case("content-length") || name.eq_ignore_ascii_case("l") {
+            value.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsip::prelude::{HeadersExt, UntypedHeader};
+    use rsip::Method;
+
+    fn options(call_id: &str, body: &str) -> String {
+        format!(
+            "OPTIONS sip:1000@127.0.0.1:5060 SIP/2.0\r\n\
+            Via: SIP/2.0/TCP 127.0.0.1:5060;branch=z9hG4bK{call_id}\r\n\
+            From: <sip:2000@127.0.0.1>;tag=1\r\n\
+            To: <sip:1000@127.0.0.1>\r\n\
+            Call-ID: {call_id}\r\n\
+            CSeq: 1 OPTIONS\r\n\
+            Content-Length: {}\r\n\r\n{body}",
+            body.len(),
+        )
+    }
+
+    fn decode_all(decoder: &mut SipMessageDecoder, src: &mut BytesMut) -> Vec<SipMessage> {
+        let mut messages = Vec::new();
+        while let Some(message) = decoder.decode(src).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
 
-        Ok(None)
+    fn call_id(message: &SipMessage) -> String {
+        message.call_id_header().unwrap().value().to_string()
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn decodes_message_split_across_reads() {
+        let message = options("split", "v=0\r\ns=-\r\n");
+        let (head, body) = message.split_at(message.find("\r\n\r\n").unwrap() + 4);
+        let mut decoder = SipMessageDecoder::new();
+        let mut src = BytesMut::new();
+
+        // Headers cut in the middle of the CRLFCRLF
+        src.extend_from_slice(&head.as_bytes()[..head.len() - 2]);
+        assert!(decoder.decode(&mut src).unwrap().is_none());
+        src.extend_from_slice(&head.as_bytes()[head.len() - 2..]);
+        src.extend_from_slice(&body.as_bytes()[..4]);
+        assert!(decoder.decode(&mut src).unwrap().is_none());
+        src.extend_from_slice(&body.as_bytes()[4..]);
+
+        let message = decoder.decode(&mut src).unwrap().unwrap();
+        assert_eq!(message.body(), body.as_bytes());
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn decodes_message_with_content_length_zero() {
+        let mut decoder = SipMessageDecoder::new();
+        let mut src = BytesMut::from(format!("{}{}", options("first", ""), options("second", "v=0\r\n")).as_str());
+
+        let messages = decode_all(&mut decoder, &mut src);
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].body().is_empty());
+        assert_eq!(call_id(&messages[0]), "first");
+        assert_eq!(messages[1].body(), b"v=0\r\n");
+    }
+
+    #[test]
+    fn skips_keepalives_between_messages() {
+        let mut decoder = SipMessageDecoder::new();
+        let mut src = BytesMut::from(format!("\r\n\r\n{}\r\n", options("first", "")).as_str());
+
+        let messages = decode_all(&mut decoder, &mut src);
+        assert_eq!(messages.len(), 1);
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn discards_unparsable_message_and_its_body() {
+        let mut decoder = SipMessageDecoder::new();
+        let garbage = "NOT A SIP MESSAGE\r\nContent-Length: 9\r\n\r\nnot a sdp";
+        let mut src = BytesMut::from(format!("{}{}", garbage, options("valid", "")).as_str());
+
+        let messages = decode_all(&mut decoder, &mut src);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(call_id(&messages[0]), "valid");
+    }
+
+    #[test]
+    fn discards_message_over_max_content_length() {
+        let mut decoder = SipMessageDecoder::new();
+        let body = "a".repeat(MAX_CONTENT_LENGTH + 1);
+        let mut src = BytesMut::from(options("too-large", &body).as_str());
+
+        // The body is skipped as it arrives, without being buffered
+        assert!(decoder.decode(&mut src).unwrap().is_none());
+        assert!(src.is_empty());
+
+        src.extend_from_slice(options("valid", "").as_bytes());
+        let message = decoder.decode(&mut src).unwrap().unwrap();
+        assert!(matches!(&message, SipMessage::Request(request) if request.method == Method::Options));
+        assert_eq!(call_id(&message), "valid");
+    }
+}
@@ -0,0 +1,414 @@
+//! In-process mock SIP server, to test applications built on the crate without a live SIP server.
+//!
+//! The [MockSipServer] accepts the TCP connection of a [SipManager](crate::manager::SipManager) and answers its registrations,
+//! the returned [MockSipConnection] is then scripted to answer the INVITEs of the client or to call it.
+//!
+//! No RTP is sent by the mock, the RTP address given when answering or calling is only advertised in its SDP.
+//!
+//! # Examples
+//! ```no_run
+//!  use std::net::SocketAddr;
+//!  use std::str::FromStr;
+//!  use rsip::Method;
+//!  use simple_sip_rs::call::outgoing_call::OutgoingCallResponse;
+//!  use simple_sip_rs::config::Config;
+//!  use simple_sip_rs::manager::SipManager;
+//!  use simple_sip_rs::testing::MockSipServer;
+//!
+//!  async fn test_outgoing_call() {
+//!     let server = MockSipServer::bind().await.unwrap();
+//!     let config = Config::builder()
+//!         .server(server.local_addr())
+//!         .own_addr(SocketAddr::from_str("127.0.0.1:5060").unwrap())
+//!         .username("1000")
+//!         .build()
+//!         .unwrap();
+//!     let mut sip_manager = SipManager::from_config(config).await.unwrap();
+//!
+//!     let (connection, started) = tokio::join!(server.accept(), sip_manager.start());
+//!     let mut connection = connection.unwrap();
+//!     started.unwrap();
+//!
+//!     let outgoing_call = sip_manager.call("2000".to_string()).await.unwrap();
+//!     let (response, answered) = tokio::join!(outgoing_call.into_call_response(), async {
+//!         let invite = connection.recv_request(Method::Invite).await?;
+//!         connection.ring(&invite).await?;
+//!         connection.answer(&invite, SocketAddr::from_str("127.0.0.1:30000").unwrap()).await
+//!     });
+//!
+//!     answered.unwrap();
+//!     assert!(matches!(response, Ok(OutgoingCallResponse::Accepted(_))));
+//!  }
+//! ```
+
+use std::net::SocketAddr;
+use anyhow::{anyhow, Context, Result};
+use futures_util::StreamExt;
+use crate::logging::{debug, warn};
+use rsip::headers::{ContentLength, Expires, MaxForwards};
+use rsip::param::{Branch, Tag};
+use rsip::prelude::*;
+use rsip::typed::{CSeq, Contact, ContentType, MediaType, Via};
+use rsip::{Header, Headers, HostWithPort, Method, Param, Request, Response, Scheme, SipMessage, StatusCode, Uri};
+use tokio::io::AsyncWriteExt;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio_util::codec::FramedRead;
+use uuid::Uuid;
+use webrtc_sdp::parse_sdp;
+
+use crate::call::MediaDirection;
+use crate::config::Config;
+use crate::media::CodecKind;
+use crate::sip_proto::sdp::{generate_sdp_answer, generate_sdp_new};
+use crate::sip_proto::sip_message_decoder::SipMessageDecoder;
+
+/// Username of the mock in the URIs of its messages.
+const MOCK_USERNAME: &str = "mock";
+
+/// Mock SIP server listening for the TCP connection of a client.
+pub struct MockSipServer {
+    listener: TcpListener,
+}
+
+impl MockSipServer {
+    /// Binds the mock server on a random port of the loopback interface.
+    pub async fn bind() -> Result<Self> {
+        Self::bind_to("127.0.0.1:0").await
+    }
+
+    /// Binds the mock server on the given address.
+    pub async fn bind_to<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr).await?,
+        })
+    }
+
+    /// Address to use as the [server](crate::config::ConfigBuilder::server) of the client.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listener.local_addr().expect("Listener has a local address")
+    }
+
+    /// Accepts the connection of a client, returning once its registration was answered.
+    pub async fn accept(&self) -> Result<MockSipConnection> {
+        let (stream, _) = self.listener.accept().await?;
+        let local_addr = stream.local_addr()?;
+        let (stream_read, stream_write) = stream.into_split();
+
+        let mut connection = MockSipConnection {
+            sip_message_reader: FramedRead::new(stream_read, SipMessageDecoder::new()),
+            stream_write,
+            local_addr,
+            client_contact: None,
+            tag: format!("mock{}", Uuid::new_v4()),
+            cseq: 0,
+        };
+
+        loop {
+            let message = connection.read_next_message().await?;
+            let is_register = matches!(&message, SipMessage::Request(request) if request.method == Method::Register);
+            connection.handle_message(message).await?;
+            if is_register {
+                return Ok(connection);
+            }
+        }
+    }
+}
+
+/// Connection of a client to the [MockSipServer].
+///
+/// REGISTER requests are answered with a `200 OK` whenever messages are read,
+/// the other messages are left to the test.
+pub struct MockSipConnection {
+    sip_message_reader: FramedRead<OwnedReadHalf, SipMessageDecoder>,
+    stream_write: OwnedWriteHalf,
+    local_addr: SocketAddr,
+
+    /// Contact registered by the client, target of our INVITEs.
+    client_contact: Option<Uri>,
+    /// Tag of the mock in the dialogs it takes part in.
+    tag: String,
+    cseq: u32,
+}
+
+impl MockSipConnection {
+    /// Receives the next message from the client, other than a REGISTER request.
+    ///
+    /// # Errors
+    ///
+    /// The function will return an error if the client closed the connection.
+    pub async fn recv(&mut self) -> Result<SipMessage> {
+        loop {
+            let message = self.read_next_message().await?;
+            if let Some(message) = self.handle_message(message).await? {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Receives the next request with the given method, other messages are skipped.
+    pub async fn recv_request(&mut self, method: Method) -> Result<Request> {
+        loop {
+            match self.recv().await? {
+                SipMessage::Request(request) if request.method == method => return Ok(request),
+                message => warn!("Mock skipped message waiting for {}: {}", method, message),
+            }
+        }
+    }
+
+    /// Receives the next response to a request with the given method, other messages are skipped.
+    pub async fn recv_response(&mut self, method: Method) -> Result<Response> {
+        loop {
+            match self.recv().await? {
+                SipMessage::Response(response) if response.cseq_header()?.method()? == method => return Ok(response),
+                message => warn!("Mock skipped message waiting for a {} response: {}", method, message),
+            }
+        }
+    }
+
+    /// Sends a message to the client.
+    pub async fn send(&mut self, message: impl Into<SipMessage>) -> Result<()> {
+        let message = message.into();
+        debug!("Mock sending SIP message {}", message);
+        self.stream_write.write_all(message.to_string().as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Answers a request of the client without a body.
+    pub async fn respond(&mut self, request: &Request, status_code: StatusCode) -> Result<()> {
+        let response = self.generate_response(request, status_code, Vec::new())?;
+        self.send(response).await
+    }
+
+    /// Answers an INVITE of the client with `180 Ringing`.
+    pub async fn ring(&mut self, invite: &Request) -> Result<()> {
+        self.respond(invite, StatusCode::Ringing).await
+    }
+
+    /// Answers an INVITE of the client with a `200 OK`, returning once the client acknowledged it.
+    ///
+    /// The SDP answer advertises `rtp_addr` and the codecs of the offer enabled in the crate.
+    pub async fn answer(&mut self, invite: &Request, rtp_addr: SocketAddr) -> Result<()> {
+        let offer = parse_sdp(&String::from_utf8(invite.body().clone())?, false)?;
+        let sdp = generate_sdp_answer(&self.get_sdp_config(), rtp_addr, &CodecKind::enabled(), &offer, MediaDirection::SendRecv)?;
+
+        let response = self.generate_response(invite, StatusCode::OK, sdp.to_string().into_bytes())?;
+        self.send(response).await?;
+        self.recv_request(Method::Ack).await?;
+        Ok(())
+    }
+
+    /// Rejects an INVITE of the client with a final error response.
+    pub async fn reject(&mut self, invite: &Request, status_code: StatusCode) -> Result<()> {
+        self.respond(invite, status_code).await
+    }
+
+    /// Calls the client, returning the sent INVITE.
+    ///
+    /// The call is reported by the [SipManager](crate::manager::SipManager) as an [IncomingCall](crate::call::incoming_call::IncomingCall)
+    /// from `sip:<from_user>@<mock address>`, the SDP offer advertises `rtp_addr` and the codecs enabled in the crate.
+    /// Read the responses with [recv_response](MockSipConnection::recv_response) and acknowledge the final one with [ack](MockSipConnection::ack).
+    ///
+    /// # Errors
+    ///
+    /// The function will return an error if the client did not register yet.
+    pub async fn invite(&mut self, from_user: &str, rtp_addr: SocketAddr) -> Result<Request> {
+        let invite = self.generate_invite(from_user, rtp_addr)?;
+        self.send(invite.clone()).await?;
+        Ok(invite)
+    }
+
+    /// Generates the INVITE sent by [invite](MockSipConnection::invite) without sending it,
+    /// to send it altered with [send](MockSipConnection::send).
+    ///
+    /// # Errors
+    ///
+    /// The function will return an error if the client did not register yet.
+    pub fn generate_invite(&mut self, from_user: &str, rtp_addr: SocketAddr) -> Result<Request> {
+        let client_contact = self.client_contact.clone().context("Client is not registered")?;
+        let sdp = generate_sdp_new(&self.get_sdp_config(), rtp_addr, &CodecKind::enabled(), MediaDirection::SendRecv)?;
+        let body = sdp.to_string().into_bytes();
+
+        self.cseq += 1;
+        let mut headers = Headers::default();
+        headers.push(self.get_via().into());
+        headers.push(MaxForwards::default().into());
+        headers.push(rsip::typed::From {
+            display_name: None,
+            uri: self.get_uri(from_user),
+            params: vec![Param::Tag(Tag::new(&self.tag))],
+        }.into());
+        headers.push(rsip::typed::To {
+            display_name: None,
+            uri: client_contact.clone(),
+            params: vec![],
+        }.into());
+        headers.push(rsip::headers::CallId::from(Uuid::new_v4().to_string()).into());
+        headers.push(CSeq::from((self.cseq, Method::Invite)).into());
+        headers.push(self.get_contact(from_user).into());
+        headers.push(ContentType(MediaType::Sdp(Vec::new())).into());
+        headers.push(ContentLength::from(body.len() as u32).into());
+
+        Ok(Request {
+            method: Method::Invite,
+            uri: client_contact,
+            version: Default::default(),
+            headers,
+            body,
+        })
+    }
+
+    /// Cancels an INVITE sent with [invite](MockSipConnection::invite) before it was answered.
+    pub async fn cancel(&mut self, invite: &Request) -> Result<()> {
+        let mut headers = Headers::default();
+        headers.push(invite.via_header()?.clone().into());
+        headers.push(MaxForwards::default().into());
+        headers.push(invite.from_header()?.clone().into());
+        headers.push(invite.to_header()?.clone().into());
+        headers.push(invite.call_id_header()?.clone().into());
+        headers.push(CSeq::from((invite.cseq_header()?.seq()?, Method::Cancel)).into());
+        headers.push(ContentLength::default().into());
+
+        self.send(Request {
+            method: Method::Cancel,
+            uri: invite.uri.clone(),
+            version: Default::default(),
+            headers,
+            body: vec![],
+        }).await
+    }
+
+    /// Acknowledges the final response of the client to an INVITE sent with [invite](MockSipConnection::invite).
+    pub async fn ack(&mut self, invite: &Request, response: &Response) -> Result<()> {
+        // The ACK of a non 2xx response is part of the INVITE transaction and reuses its branch
+        let via = if response.status_code.code() >= 300 {
+            invite.via_header()?.clone().into()
+        } else {
+            self.get_via().into()
+        };
+
+        let mut headers = Headers::default();
+        headers.push(via);
+        headers.push(MaxForwards::default().into());
+        headers.push(invite.from_header()?.clone().into());
+        headers.push(response.to_header()?.clone().into());
+        headers.push(invite.call_id_header()?.clone().into());
+        headers.push(CSeq::from((invite.cseq_header()?.seq()?, Method::Ack)).into());
+        headers.push(ContentLength::default().into());
+
+        self.send(Request {
+            method: Method::Ack,
+            uri: invite.uri.clone(),
+            version: Default::default(),
+            headers,
+            body: vec![],
+        }).await
+    }
+
+    /// Generates the response sent by [respond](MockSipConnection::respond) without sending it,
+    /// to send it altered with [send](MockSipConnection::send).
+    ///
+    /// The responses to an INVITE carry the Contact of the mock.
+    pub fn generate_response(&self, request: &Request, status_code: StatusCode, body: Vec<u8>) -> Result<Response> {
+        let mut to = request.to_header()?.typed()?;
+        if to.tag().is_none() {
+            to = to.with_tag(Tag::new(&self.tag));
+        }
+
+        let mut headers = Headers::default();
+        for header in request.headers.iter() {
+            if let Header::Via(via) = header {
+                headers.push(via.clone().into());
+            }
+        }
+        headers.push(request.from_header()?.clone().into());
+        headers.push(to.into());
+        headers.push(request.call_id_header()?.clone().into());
+        headers.push(request.cseq_header()?.clone().into());
+        if request.method == Method::Invite {
+            headers.push(self.get_contact(MOCK_USERNAME).into());
+        }
+        if !body.is_empty() {
+            headers.push(ContentType(MediaType::Sdp(Vec::new())).into());
+        }
+        headers.push(ContentLength::from(body.len() as u32).into());
+
+        Ok(Response {
+            status_code,
+            version: Default::default(),
+            headers,
+            body,
+        })
+    }
+
+    async fn read_next_message(&mut self) -> Result<SipMessage> {
+        let message = self.sip_message_reader.next().await
+            .ok_or(anyhow!("Connection closed by the client"))??;
+        debug!("Mock received SIP message {}", message);
+        Ok(message)
+    }
+
+    /// Answers REGISTER requests, returning the other messages.
+    async fn handle_message(&mut self, message: SipMessage) -> Result<Option<SipMessage>> {
+        let SipMessage::Request(request) = message else {
+            return Ok(Some(message));
+        };
+        if request.method != Method::Register {
+            return Ok(Some(request.into()));
+        }
+
+        // rsip cannot parse quoted parameters like `+sip.instance`, only the URI is needed
+        let contact = request.contact_header()?.clone();
+        let uri = contact.value().split_once('<')
+            .and_then(|(_, rest)| rest.split_once('>'))
+            .map_or(contact.value(), |(uri, _)| uri);
+        self.client_contact = Some(Uri::try_from(uri)?);
+
+        let mut response = self.generate_response(&request, StatusCode::OK, Vec::new())?;
+        response.headers.push(contact.into());
+        if let Some(expires) = request.expires_header() {
+            response.headers.push(Expires::from(expires.seconds()?).into());
+        }
+        self.send(response).await?;
+        Ok(None)
+    }
+
+    /// Config used to generate the SDP of the mock, with the default codec settings.
+    fn get_sdp_config(&self) -> Config {
+        Config::builder()
+            .server(self.local_addr)
+            .own_addr(self.local_addr)
+            .username(MOCK_USERNAME)
+            .build()
+            .expect("Mock config is valid")
+    }
+
+    fn get_uri(&self, user: &str) -> Uri {
+        Uri {
+            scheme: Some(Scheme::Sip),
+            auth: Some((user.to_string(), Option::<String>::None).into()),
+            host_with_port: HostWithPort::from(self.local_addr),
+            ..Default::default()
+        }
+    }
+
+    fn get_contact(&self, user: &str) -> Contact {
+        Contact {
+            display_name: None,
+            uri: self.get_uri(user),
+            params: vec![],
+        }
+    }
+
+    fn get_via(&self) -> Via {
+        Via {
+            version: rsip::Version::V2,
+            transport: rsip::Transport::Tcp,
+            uri: Uri {
+                host_with_port: HostWithPort::from(self.local_addr),
+                ..Default::default()
+            },
+            params: vec![Param::Branch(Branch::new(format!("z9hG4bK{}", Uuid::new_v4())))],
+        }
+    }
+}
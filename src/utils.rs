@@ -1,5 +1,6 @@
 use anyhow::Result;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::{sleep_until, Instant, Interval};
 
 pub struct BidirectionalChannel<T> {
     pub sender: UnboundedSender<T>,
@@ -35,4 +36,22 @@ pub fn create_mpsc_bidirectional_unbounded<T>() -> (BidirectionalChannel<T>, Bid
     };
 
     (first_channel, second_channel)
-}
\ No newline at end of file
+}
+
+/// Sleeps until the deadline if present, never completes otherwise.
+pub async fn sleep_until_optional(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Ticks the interval if present, never completes otherwise.
+pub async fn tick_optional(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
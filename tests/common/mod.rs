@@ -0,0 +1,25 @@
+use std::net::SocketAddr;
+use std::str::FromStr;
+use simple_sip_rs::config::{Config, ConfigBuilder};
+use simple_sip_rs::manager::SipManager;
+use simple_sip_rs::testing::{MockSipConnection, MockSipServer};
+
+/// RTP address advertised by the mock, nothing is listening on it.
+#[allow(dead_code)]
+pub const MOCK_RTP_ADDR: &str = "127.0.0.1:30000";
+
+/// Starts a [SipManager] registered on a mock server, `config` customizing its config.
+pub async fn start(config: impl FnOnce(ConfigBuilder) -> ConfigBuilder) -> (SipManager, MockSipConnection) {
+    let server = MockSipServer::bind().await.unwrap();
+    let config = config(Config::builder()
+        .server(server.local_addr())
+        .own_addr(SocketAddr::from_str("127.0.0.1:5060").unwrap())
+        .username("1000"))
+        .build()
+        .unwrap();
+    let mut sip_manager = SipManager::from_config(config).await.unwrap();
+
+    let (connection, started) = tokio::join!(server.accept(), sip_manager.start());
+    started.unwrap();
+    (sip_manager, connection.unwrap())
+}
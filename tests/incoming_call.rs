@@ -0,0 +1,314 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use rsip::headers::{ContentLength, Via};
+use rsip::prelude::*;
+use rsip::typed::CSeq;
+use rsip::{Header, Headers, Method, Request, Response, StatusCode, StatusCodeKind};
+use simple_sip_rs::call::incoming_call::IncomingCallResult;
+use simple_sip_rs::call::{Call, CallControl};
+use simple_sip_rs::manager::SipManager;
+use simple_sip_rs::testing::MockSipConnection;
+use common::{start, MOCK_RTP_ADDR};
+
+/// Accepts the call of `invite`, returning the call and the responses of the client.
+async fn accept(sip_manager: &mut SipManager, connection: &mut MockSipConnection, invite: &Request) -> (Call, Vec<Response>) {
+    let incoming_call = sip_manager.recv_incoming_call().await.unwrap().unwrap();
+    let (accepted, responses) = tokio::join!(incoming_call.accept(), async {
+        let mut responses = Vec::new();
+        loop {
+            let response = connection.recv_response(Method::Invite).await.unwrap();
+            let is_final = response.status_code.kind() != StatusCodeKind::Provisional;
+            responses.push(response);
+            if is_final {
+                return responses;
+            }
+        }
+    });
+    let response = responses.last().unwrap();
+    assert_eq!(response.status_code, StatusCode::OK);
+    connection.ack(invite, response).await.unwrap();
+    let Ok(IncomingCallResult::Ok(call)) = accepted else {
+        panic!("Call was not accepted");
+    };
+    (call, responses)
+}
+
+/// Generates a request of the mock in the dialog of `invite`, established by `response`.
+fn generate_in_dialog_request(invite: &Request, response: &Response, method: Method, cseq: u32) -> Request {
+    let mut headers = Headers::default();
+    for header in invite.headers.iter() {
+        match header {
+            Header::Via(_) => headers.push(Via::new(format!("SIP/2.0/TCP 127.0.0.1;branch=z9hG4bK{}-{}", method, cseq)).into()),
+            Header::To(_) => headers.push(response.to_header().unwrap().clone().into()),
+            Header::CSeq(_) => headers.push(CSeq::from((cseq, method)).into()),
+            Header::ContentType(_) | Header::ContentLength(_) => {}
+            header => headers.push(header.clone()),
+        }
+    }
+    headers.push(ContentLength::default().into());
+    Request {
+        method,
+        uri: invite.uri.clone(),
+        version: Default::default(),
+        headers,
+        body: vec![],
+    }
+}
+
+fn to_tag(response: &Response) -> String {
+    response.to_header().unwrap().tag().unwrap().unwrap().value().to_string()
+}
+
+#[tokio::test]
+async fn invite_with_invalid_sdp_is_answered_with_400() {
+    let (mut sip_manager, mut connection) = start(|config| config).await;
+    let rtp_addr = SocketAddr::from_str(MOCK_RTP_ADDR).unwrap();
+
+    let mut invite = connection.generate_invite("2000", rtp_addr).unwrap();
+    invite.body = b"not an sdp".to_vec();
+    invite.headers.unique_push(ContentLength::from(invite.body.len() as u32).into());
+    connection.send(invite).await.unwrap();
+    let response = connection.recv_response(Method::Invite).await.unwrap();
+    assert_eq!(response.status_code, StatusCode::BadRequest);
+
+    // The connection is still up and handles the next INVITE
+    connection.invite("2000", rtp_addr).await.unwrap();
+    let incoming_call = sip_manager.recv_incoming_call().await.unwrap();
+    assert!(incoming_call.is_some());
+}
+
+#[tokio::test]
+async fn invite_without_free_rtp_port_is_answered_with_503() {
+    // A range of a single RTP port
+    let rtp_port = UdpSocket::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+    let (mut sip_manager, mut connection) = start(|config| config.rtp_ports(rtp_port, rtp_port + 1)).await;
+    let rtp_addr = SocketAddr::from_str(MOCK_RTP_ADDR).unwrap();
+
+    let invite = connection.invite("2000", rtp_addr).await.unwrap();
+    let (call, _) = accept(&mut sip_manager, &mut connection, &invite).await;
+
+    connection.invite("3000", rtp_addr).await.unwrap();
+    let response = connection.recv_response(Method::Invite).await.unwrap();
+    assert_eq!(response.status_code, StatusCode::ServiceUnavailable);
+    assert!(!call.is_finished());
+}
+
+#[tokio::test]
+async fn invite_with_invalid_replaces_is_answered_with_400_or_481() {
+    let (mut sip_manager, mut connection) = start(|config| config).await;
+    let rtp_addr = SocketAddr::from_str(MOCK_RTP_ADDR).unwrap();
+
+    let mut invite = connection.generate_invite("2000", rtp_addr).unwrap();
+    invite.headers.push(Header::Other("Replaces".to_string(), "abc;to-tag=1".to_string()));
+    connection.send(invite).await.unwrap();
+    let response = connection.recv_response(Method::Invite).await.unwrap();
+    assert_eq!(response.status_code, StatusCode::BadRequest);
+
+    let mut invite = connection.generate_invite("2000", rtp_addr).unwrap();
+    invite.headers.push(Header::Other("Replaces".to_string(), "abc;to-tag=1;from-tag=2".to_string()));
+    connection.send(invite).await.unwrap();
+    let response = connection.recv_response(Method::Invite).await.unwrap();
+    assert_eq!(response.status_code, StatusCode::CallTransactionDoesNotExist);
+
+    connection.invite("2000", rtp_addr).await.unwrap();
+    assert!(sip_manager.recv_incoming_call().await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn invite_with_invalid_session_expires_is_answered_with_400_or_422() {
+    let (mut sip_manager, mut connection) = start(|config| config).await;
+    let rtp_addr = SocketAddr::from_str(MOCK_RTP_ADDR).unwrap();
+
+    let mut invite = connection.generate_invite("2000", rtp_addr).unwrap();
+    invite.headers.push(Header::Other("Session-Expires".to_string(), "soon".to_string()));
+    connection.send(invite).await.unwrap();
+    let response = connection.recv_response(Method::Invite).await.unwrap();
+    assert_eq!(response.status_code, StatusCode::BadRequest);
+
+    let mut invite = connection.generate_invite("2000", rtp_addr).unwrap();
+    invite.headers.push(Header::Other("Session-Expires".to_string(), "30".to_string()));
+    connection.send(invite).await.unwrap();
+    let response = connection.recv_response(Method::Invite).await.unwrap();
+    assert_eq!(response.status_code, StatusCode::SessionIntervalTooSmall);
+    assert!(response.headers.iter().any(|header| header.to_string() == "Min-SE: 90"));
+
+    connection.invite("2000", rtp_addr).await.unwrap();
+    assert!(sip_manager.recv_incoming_call().await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn invite_with_invalid_multipart_body_is_answered_with_400_or_415() {
+    let (mut sip_manager, mut connection) = start(|config| config).await;
+    let rtp_addr = SocketAddr::from_str(MOCK_RTP_ADDR).unwrap();
+
+    let mut invite = connection.generate_invite("2000", rtp_addr).unwrap();
+    invite.headers.unique_push(Header::ContentType("multipart/mixed".into()));
+    connection.send(invite).await.unwrap();
+    let response = connection.recv_response(Method::Invite).await.unwrap();
+    assert_eq!(response.status_code, StatusCode::BadRequest);
+
+    let mut invite = connection.generate_invite("2000", rtp_addr).unwrap();
+    invite.headers.unique_push(Header::ContentType("multipart/mixed;boundary=unique".into()));
+    invite.body = b"--unique\r\nContent-Type: application/isup\r\n\r\nisup\r\n--unique--\r\n".to_vec();
+    invite.headers.unique_push(ContentLength::from(invite.body.len() as u32).into());
+    connection.send(invite).await.unwrap();
+    let response = connection.recv_response(Method::Invite).await.unwrap();
+    assert_eq!(response.status_code, StatusCode::UnsupportedMediaType);
+
+    connection.invite("2000", rtp_addr).await.unwrap();
+    assert!(sip_manager.recv_incoming_call().await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn local_tag_is_stable_across_the_dialog() {
+    let (mut sip_manager, mut connection) = start(|config| config.allow_update(true)).await;
+    let rtp_addr = SocketAddr::from_str(MOCK_RTP_ADDR).unwrap();
+
+    let invite = connection.invite("2000", rtp_addr).await.unwrap();
+    let (_call, responses) = accept(&mut sip_manager, &mut connection, &invite).await;
+    let tag = to_tag(responses.last().unwrap());
+    // Including the 180 Ringing sent automatically
+    assert!(responses.len() > 1);
+    assert!(responses.iter().all(|response| to_tag(response) == tag));
+
+    let mut reinvite = generate_in_dialog_request(&invite, responses.last().unwrap(), Method::Invite, 2);
+    reinvite.body = invite.body.clone();
+    reinvite.headers.unique_push(Header::ContentType("application/sdp".into()));
+    reinvite.headers.unique_push(ContentLength::from(reinvite.body.len() as u32).into());
+    connection.send(reinvite.clone()).await.unwrap();
+    let response = connection.recv_response(Method::Invite).await.unwrap();
+    assert_eq!(response.status_code, StatusCode::OK);
+    assert_eq!(to_tag(&response), tag);
+    connection.ack(&reinvite, &response).await.unwrap();
+
+    let update = generate_in_dialog_request(&invite, &response, Method::Update, 3);
+    connection.send(update).await.unwrap();
+    let response = connection.recv_response(Method::Update).await.unwrap();
+    assert_eq!(response.status_code, StatusCode::OK);
+    assert_eq!(to_tag(&response), tag);
+}
+
+#[tokio::test]
+async fn in_dialog_requests_target_the_remote_contact() {
+    let (mut sip_manager, mut connection) = start(|config| config).await;
+    let rtp_addr = SocketAddr::from_str(MOCK_RTP_ADDR).unwrap();
+
+    let mut invite = connection.generate_invite("2000", rtp_addr).unwrap();
+    invite.headers.retain(|header| !matches!(header, Header::Contact(_)));
+    invite.headers.push(Header::Contact("<sip:2000@127.0.0.1:5090;transport=TCP>".into()));
+    connection.send(invite.clone()).await.unwrap();
+    let (call, responses) = accept(&mut sip_manager, &mut connection, &invite).await;
+
+    let mut reinvite = generate_in_dialog_request(&invite, responses.last().unwrap(), Method::Invite, 2);
+    reinvite.headers.retain(|header| !matches!(header, Header::Contact(_)));
+    reinvite.headers.push(Header::Contact("<sip:2000@127.0.0.1:5091;transport=TCP>".into()));
+    reinvite.body = invite.body.clone();
+    reinvite.headers.unique_push(Header::ContentType("application/sdp".into()));
+    reinvite.headers.unique_push(ContentLength::from(reinvite.body.len() as u32).into());
+    connection.send(reinvite.clone()).await.unwrap();
+    let response = connection.recv_response(Method::Invite).await.unwrap();
+    assert_eq!(response.status_code, StatusCode::OK);
+    connection.ack(&reinvite, &response).await.unwrap();
+
+    // The target refreshed by the re-INVITE
+    call.hangup().unwrap();
+    let bye = connection.recv_request(Method::Bye).await.unwrap();
+    assert_eq!(bye.uri.to_string(), "sip:2000@127.0.0.1:5091;transport=TCP");
+}
+
+#[tokio::test]
+async fn call_sdps_follow_reinvites() {
+    let (mut sip_manager, mut connection) = start(|config| config).await;
+    let rtp_addr = SocketAddr::from_str(MOCK_RTP_ADDR).unwrap();
+
+    let invite = connection.invite("2000", rtp_addr).await.unwrap();
+    let (call, responses) = accept(&mut sip_manager, &mut connection, &invite).await;
+    assert_eq!(call.remote_sdp().to_string().as_bytes(), invite.body.as_slice());
+
+    // The remote moves its media
+    let new_rtp_addr = SocketAddr::new(rtp_addr.ip(), rtp_addr.port() + 2);
+    let mut reinvite = generate_in_dialog_request(&invite, responses.last().unwrap(), Method::Invite, 2);
+    reinvite.body = connection.generate_invite("2000", new_rtp_addr).unwrap().body;
+    reinvite.headers.unique_push(Header::ContentType("application/sdp".into()));
+    reinvite.headers.unique_push(ContentLength::from(reinvite.body.len() as u32).into());
+    connection.send(reinvite.clone()).await.unwrap();
+    let response = connection.recv_response(Method::Invite).await.unwrap();
+    assert_eq!(response.status_code, StatusCode::OK);
+    connection.ack(&reinvite, &response).await.unwrap();
+
+    assert_eq!(call.remote_sdp().to_string().as_bytes(), reinvite.body.as_slice());
+    assert_eq!(call.local_sdp().to_string().as_bytes(), response.body.as_slice());
+}
+
+#[tokio::test]
+async fn replaced_call_follows_the_new_remote() {
+    let (mut sip_manager, mut connection) = start(|config| config).await;
+    let rtp_addr = SocketAddr::from_str(MOCK_RTP_ADDR).unwrap();
+
+    let mut invite = connection.generate_invite("2000", rtp_addr).unwrap();
+    invite.headers.push(Header::Allow("INVITE, ACK, BYE, CANCEL".into()));
+    connection.send(invite.clone()).await.unwrap();
+    let (mut call, responses) = accept(&mut sip_manager, &mut connection, &invite).await;
+    assert_eq!(call.get_remote_uri(), "2000");
+    assert!(!call.remote_supports(Method::Info));
+
+    let replaces = format!(
+        "{};to-tag={};from-tag={}",
+        invite.call_id_header().unwrap().value(),
+        to_tag(responses.last().unwrap()),
+        invite.from_header().unwrap().tag().unwrap().unwrap().value(),
+    );
+    let mut replacing_invite = connection.generate_invite("3000", rtp_addr).unwrap();
+    replacing_invite.headers.push(Header::Other("Replaces".to_string(), replaces));
+    connection.send(replacing_invite.clone()).await.unwrap();
+    let response = connection.recv_response(Method::Invite).await.unwrap();
+    assert_eq!(response.status_code, StatusCode::OK);
+    connection.ack(&replacing_invite, &response).await.unwrap();
+    connection.recv_request(Method::Bye).await.unwrap();
+    while !matches!(call.recv().await, Some(CallControl::Replaced)) {}
+
+    assert_eq!(call.get_remote_uri(), "3000");
+    assert!(call.remote_supports(Method::Info));
+}
+
+#[tokio::test]
+async fn update_rejected_with_491_is_retried() {
+    let (mut sip_manager, mut connection) = start(|config| config.allow_update(true)).await;
+    let rtp_addr = SocketAddr::from_str(MOCK_RTP_ADDR).unwrap();
+
+    let invite = connection.invite("2000", rtp_addr).await.unwrap();
+    let (call, _) = accept(&mut sip_manager, &mut connection, &invite).await;
+
+    call.send_update().unwrap();
+    let update = connection.recv_request(Method::Update).await.unwrap();
+    assert!(!update.body.is_empty());
+    connection.respond(&update, StatusCode::RequestPending).await.unwrap();
+
+    // Retried within 2 seconds as we do not own the Call-ID, with its SDP
+    let retry = tokio::time::timeout(Duration::from_millis(2500), connection.recv_request(Method::Update)).await.unwrap().unwrap();
+    assert!(!retry.body.is_empty());
+    assert!(retry.cseq_header().unwrap().seq().unwrap() > update.cseq_header().unwrap().seq().unwrap());
+    connection.respond(&retry, StatusCode::OK).await.unwrap();
+    assert!(!call.is_finished());
+}
+
+#[tokio::test]
+async fn reject_with_non_error_status_code_fails_without_replying() {
+    let (mut sip_manager, mut connection) = start(|config| config).await;
+    let rtp_addr = SocketAddr::from_str(MOCK_RTP_ADDR).unwrap();
+
+    connection.invite("2000", rtp_addr).await.unwrap();
+    let incoming_call = sip_manager.recv_incoming_call().await.unwrap().unwrap();
+    assert!(incoming_call.reject_with(StatusCode::OK).await.is_err());
+
+    // Only the automatic 180 Ringing was sent
+    while let Ok(response) = tokio::time::timeout(Duration::from_millis(200), connection.recv_response(Method::Invite)).await {
+        assert_eq!(response.unwrap().status_code.kind(), StatusCodeKind::Provisional);
+    }
+}
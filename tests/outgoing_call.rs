@@ -0,0 +1,38 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use rsip::prelude::*;
+use std::time::Duration;
+use rsip::{Header, Method, Request, StatusCode};
+use common::start;
+
+#[tokio::test]
+async fn prack_is_a_new_transaction_to_the_remote_target() {
+    let (sip_manager, mut connection) = start(|config| config).await;
+    let mut outgoing_call = sip_manager.call("2000".to_string()).await.unwrap();
+    let invite = connection.recv_request(Method::Invite).await.unwrap();
+
+    let mut response = connection.generate_response(&invite, StatusCode::Ringing, Vec::new()).unwrap();
+    response.headers.push(Header::Require("100rel".into()));
+    response.headers.push(Header::Other("RSeq".to_string(), "1".to_string()));
+    response.headers.push(Header::RecordRoute("<sip:proxy1.example.com;lr>, <sip:proxy2.example.com;lr>".into()));
+    connection.send(response.clone()).await.unwrap();
+
+    let (_, prack) = tokio::join!(
+        tokio::time::timeout(Duration::from_millis(500), outgoing_call.peek_call_response()),
+        connection.recv_request(Method::PRack),
+    );
+    let prack = prack.unwrap();
+
+    let branch = |request: &Request| request.via_header().unwrap().typed().unwrap().branch().unwrap().to_string();
+    assert_ne!(branch(&prack), branch(&invite));
+    assert_eq!(prack.uri.to_string(), response.contact_header().unwrap().typed().unwrap().uri.to_string());
+    let routes = prack.headers.iter()
+        .filter_map(|header| match header {
+            Header::Route(route) => Some(route.value().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(routes, vec!["<sip:proxy2.example.com;lr>", "<sip:proxy1.example.com;lr>"]);
+}